@@ -0,0 +1,365 @@
+//! Derive macro for the `AnkiNote` trait of [genanki-rs](https://docs.rs/genanki-rs).
+//!
+//! Use it through the `derive` feature of genanki-rs, which re-exports `AnkiNote`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::meta::ParseNestedMeta;
+use syn::{Data, DeriveInput, Fields, LitBool, LitInt, LitStr, Type, parse_macro_input};
+
+/// Special fields Anki provides in templates in addition to the fields of the model
+const SPECIAL_FIELDS: &[&str] = &[
+    "FrontSide",
+    "Tags",
+    "Type",
+    "Deck",
+    "Subdeck",
+    "Card",
+    "CardFlag",
+    "CardID",
+];
+
+/// Implements `genanki_rs::AnkiNote` for a struct with named members.
+///
+/// Each member becomes a field of the model, in declaration order and named like the member
+/// unless renamed. Members are converted with `ToString`, `Option` members are empty if `None`.
+///
+/// Struct attributes, in `#[anki(...)]`:
+/// * `id = 1607392319` - id of the model, required
+/// * `name = "..."` - name of the model, defaults to the struct name
+/// * `css = "..."` or `css_file = "path"` - CSS of the model
+/// * `cloze` - makes the model a cloze model
+/// * `sort_field = "..."` - name of the sort field
+/// * `template(name = "...", qfmt = "...", afmt = "...")` - adds a template, repeatable; use
+///   `qfmt_file` and `afmt_file` to include the formats from files
+///
+/// Member attributes, in `#[anki(...)]`:
+/// * `rename = "..."` - name of the field
+/// * `font = "..."`, `size = 20`, `rtl`, `sticky` - options of the field
+/// * `tags` - the member is an iterator of tags instead of a field
+/// * `guid` - the member is the GUID of the note instead of a field
+///
+/// Paths of files are relative to the manifest directory of the crate. Fields used in inline
+/// templates are checked at compile time.
+#[proc_macro_derive(AnkiNote, attributes(anki))]
+pub fn derive_anki_note(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct TemplateSpec {
+    name: LitStr,
+    qfmt: Format,
+    afmt: Format,
+}
+
+enum Format {
+    Inline(LitStr),
+    File(LitStr),
+}
+
+impl Format {
+    fn tokens(&self) -> TokenStream2 {
+        match self {
+            Format::Inline(format) => quote!(#format),
+            Format::File(path) => {
+                quote!(include_str!(
+                    concat!(env!("CARGO_MANIFEST_DIR"), "/", #path)
+                ))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ModelSpec {
+    id: Option<LitInt>,
+    name: Option<LitStr>,
+    css: Option<Format>,
+    cloze: bool,
+    sort_field: Option<LitStr>,
+    templates: Vec<TemplateSpec>,
+}
+
+#[derive(Default)]
+struct FieldSpec {
+    rename: Option<LitStr>,
+    font: Option<LitStr>,
+    size: Option<LitInt>,
+    rtl: bool,
+    sticky: bool,
+    tags: bool,
+    guid: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let members = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "AnkiNote can only be derived for structs with named members",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "AnkiNote can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut model = ModelSpec::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("anki"))
+    {
+        attr.parse_nested_meta(|meta| parse_model_attr(&mut model, meta))?;
+    }
+    let id = model
+        .id
+        .as_ref()
+        .ok_or_else(|| syn::Error::new_spanned(ident, "missing model id, add #[anki(id = ...)]"))?;
+    if model.templates.is_empty() {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "missing template, add #[anki(template(name = ..., qfmt = ..., afmt = ...))]",
+        ));
+    }
+
+    let mut field_names = vec![];
+    let mut field_builders = vec![];
+    let mut field_values = vec![];
+    let mut tags = quote!(vec![]);
+    let mut guid = quote!(None);
+    for member in members {
+        let mut spec = FieldSpec::default();
+        for attr in member
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("anki"))
+        {
+            attr.parse_nested_meta(|meta| parse_field_attr(&mut spec, meta))?;
+        }
+        let member_ident = member.ident.as_ref().expect("named member");
+        if spec.tags {
+            tags = quote!(self.#member_ident.iter().map(|tag| tag.to_string()).collect());
+            continue;
+        }
+        if spec.guid {
+            guid = quote!(Some(self.#member_ident.to_string()));
+            continue;
+        }
+
+        let name = spec
+            .rename
+            .as_ref()
+            .map(LitStr::value)
+            .unwrap_or_else(|| member_ident.to_string());
+        let mut builder = quote!(::genanki_rs::Field::new(#name));
+        if let Some(font) = &spec.font {
+            builder = quote!(#builder.font(#font));
+        }
+        if let Some(size) = &spec.size {
+            builder = quote!(#builder.size(#size));
+        }
+        if spec.rtl {
+            builder = quote!(#builder.rtl(true));
+        }
+        if spec.sticky {
+            builder = quote!(#builder.sticky(true));
+        }
+        field_builders.push(builder);
+        field_values.push(if is_option(&member.ty) {
+            quote!(self.#member_ident.as_ref().map(|value| value.to_string()).unwrap_or_default())
+        } else {
+            quote!(self.#member_ident.to_string())
+        });
+        field_names.push(name);
+    }
+    if field_names.is_empty() {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "a model needs at least one field",
+        ));
+    }
+
+    for template in &model.templates {
+        for format in [&template.qfmt, &template.afmt] {
+            if let Format::Inline(format) = format {
+                check_template_fields(format, &field_names)?;
+            }
+        }
+    }
+
+    let model_name = model
+        .name
+        .as_ref()
+        .map(LitStr::value)
+        .unwrap_or_else(|| ident.to_string());
+    let templates = model.templates.iter().map(|template| {
+        let name = &template.name;
+        let qfmt = template.qfmt.tokens();
+        let afmt = template.afmt.tokens();
+        quote!(::genanki_rs::Template::new(#name).qfmt(#qfmt).afmt(#afmt))
+    });
+    let mut model_tokens = quote!(::genanki_rs::Model::new(
+        #id,
+        #model_name,
+        vec![#(#field_builders),*],
+        vec![#(#templates),*],
+    ));
+    if let Some(css) = &model.css {
+        let css = css.tokens();
+        model_tokens = quote!(#model_tokens.css(#css));
+    }
+    if model.cloze {
+        model_tokens = quote!(#model_tokens.model_type(::genanki_rs::ModelType::Cloze));
+    }
+    if let Some(sort_field) = &model.sort_field {
+        let index = field_names
+            .iter()
+            .position(|name| *name == sort_field.value())
+            .ok_or_else(|| syn::Error::new_spanned(sort_field, "no field with this name"))?;
+        let index = index as i64;
+        model_tokens = quote!(#model_tokens.sort_field_index(#index));
+    }
+
+    let field_count = field_names.len();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::genanki_rs::AnkiNote for #ident #ty_generics #where_clause {
+            const FIELD_NAMES: &'static [&'static str] = &[#(#field_names),*];
+
+            fn model() -> ::genanki_rs::Model {
+                #model_tokens
+            }
+
+            fn fields(&self) -> ::std::vec::Vec<::std::string::String> {
+                let fields: [::std::string::String; #field_count] = [#(#field_values),*];
+                fields.into()
+            }
+
+            fn tags(&self) -> ::std::vec::Vec<::std::string::String> {
+                #tags
+            }
+
+            fn guid(&self) -> ::std::option::Option<::std::string::String> {
+                #guid
+            }
+        }
+    })
+}
+
+fn parse_model_attr(model: &mut ModelSpec, meta: ParseNestedMeta) -> syn::Result<()> {
+    if meta.path.is_ident("id") {
+        model.id = Some(meta.value()?.parse()?);
+    } else if meta.path.is_ident("name") {
+        model.name = Some(meta.value()?.parse()?);
+    } else if meta.path.is_ident("css") {
+        model.css = Some(Format::Inline(meta.value()?.parse()?));
+    } else if meta.path.is_ident("css_file") {
+        model.css = Some(Format::File(meta.value()?.parse()?));
+    } else if meta.path.is_ident("cloze") {
+        model.cloze = parse_flag(&meta)?;
+    } else if meta.path.is_ident("sort_field") {
+        model.sort_field = Some(meta.value()?.parse()?);
+    } else if meta.path.is_ident("template") {
+        let (mut name, mut qfmt, mut afmt) = (None, None, None);
+        meta.parse_nested_meta(|inner| {
+            if inner.path.is_ident("name") {
+                name = Some(inner.value()?.parse()?);
+            } else if inner.path.is_ident("qfmt") {
+                qfmt = Some(Format::Inline(inner.value()?.parse()?));
+            } else if inner.path.is_ident("qfmt_file") {
+                qfmt = Some(Format::File(inner.value()?.parse()?));
+            } else if inner.path.is_ident("afmt") {
+                afmt = Some(Format::Inline(inner.value()?.parse()?));
+            } else if inner.path.is_ident("afmt_file") {
+                afmt = Some(Format::File(inner.value()?.parse()?));
+            } else {
+                return Err(inner.error("unknown template attribute"));
+            }
+            Ok(())
+        })?;
+        let missing = |what| meta.error(format!("template is missing `{}`", what));
+        model.templates.push(TemplateSpec {
+            name: name.ok_or_else(|| missing("name"))?,
+            qfmt: qfmt.ok_or_else(|| missing("qfmt"))?,
+            afmt: afmt.ok_or_else(|| missing("afmt"))?,
+        });
+    } else {
+        return Err(meta.error("unknown model attribute"));
+    }
+    Ok(())
+}
+
+fn parse_field_attr(spec: &mut FieldSpec, meta: ParseNestedMeta) -> syn::Result<()> {
+    if meta.path.is_ident("rename") {
+        spec.rename = Some(meta.value()?.parse()?);
+    } else if meta.path.is_ident("font") {
+        spec.font = Some(meta.value()?.parse()?);
+    } else if meta.path.is_ident("size") {
+        spec.size = Some(meta.value()?.parse()?);
+    } else if meta.path.is_ident("rtl") {
+        spec.rtl = parse_flag(&meta)?;
+    } else if meta.path.is_ident("sticky") {
+        spec.sticky = parse_flag(&meta)?;
+    } else if meta.path.is_ident("tags") {
+        spec.tags = parse_flag(&meta)?;
+    } else if meta.path.is_ident("guid") {
+        spec.guid = parse_flag(&meta)?;
+    } else {
+        return Err(meta.error("unknown field attribute"));
+    }
+    Ok(())
+}
+
+/// Parses `flag` or `flag = true`
+fn parse_flag(meta: &ParseNestedMeta) -> syn::Result<bool> {
+    if meta.input.peek(syn::Token![=]) {
+        Ok(meta.value()?.parse::<LitBool>()?.value)
+    } else {
+        Ok(true)
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+/// Fails if `format` references a field which is neither in `fields` nor provided by Anki
+fn check_template_fields(format: &LitStr, fields: &[String]) -> syn::Result<()> {
+    let value = format.value();
+    for tag in value.split("{{").skip(1) {
+        let Some((tag, _)) = tag.split_once("}}") else {
+            continue;
+        };
+        let tag = tag.trim_start_matches(['{', '#', '^', '/']).trim();
+        if tag.is_empty() || tag.starts_with('!') {
+            continue;
+        }
+        let name = tag.rsplit(':').next().unwrap_or(tag).trim();
+        if !SPECIAL_FIELDS.contains(&name) && !fields.iter().any(|field| field == name) {
+            return Err(syn::Error::new_spanned(
+                format,
+                format!("the template uses the unknown field {:?}", name),
+            ));
+        }
+    }
+    Ok(())
+}
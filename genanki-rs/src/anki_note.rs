@@ -0,0 +1,171 @@
+use crate::model::Model;
+use crate::note::Note;
+use anyhow::Result;
+
+/// A struct whose members are the fields of a note, usually implemented with
+/// `#[derive(AnkiNote)]` from the `derive` feature.
+///
+/// The derive macro generates the `Model` from the struct, so the number and order of the fields
+/// always match the model.
+///
+/// Example:
+///
+/// ```rust
+/// # #[cfg(feature = "derive")]
+/// # fn main() -> anyhow::Result<()> {
+/// use genanki_rs::{AnkiNote, Deck};
+///
+/// #[derive(AnkiNote)]
+/// #[anki(id = 1607392319, name = "Vocabulary")]
+/// #[anki(template(name = "Card 1", qfmt = "{{Word}}", afmt = "{{FrontSide}}<hr id=answer>{{Meaning}}"))]
+/// struct Vocabulary {
+///     #[anki(rename = "Word", font = "Arial", size = 24)]
+///     word: String,
+///     #[anki(rename = "Meaning")]
+///     meaning: String,
+///     #[anki(tags)]
+///     tags: Vec<String>,
+/// }
+///
+/// let mut deck = Deck::new(2059400110, "Vocabulary", "");
+/// let word = Vocabulary {
+///     word: "Hund".to_string(),
+///     meaning: "dog".to_string(),
+///     tags: vec!["animal".to_string()],
+/// };
+/// deck.add_note(word.to_note()?);
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "derive"))]
+/// # fn main() {}
+/// ```
+pub trait AnkiNote {
+    /// Names of the fields of the model, in order
+    const FIELD_NAMES: &'static [&'static str];
+
+    /// Returns the model of the notes
+    fn model() -> Model;
+
+    /// Returns the contents of the fields, in the order of `FIELD_NAMES`
+    fn fields(&self) -> Vec<String>;
+
+    /// Returns the tags of the note
+    fn tags(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Returns the GUID of the note, `None` derives it from the fields
+    fn guid(&self) -> Option<String> {
+        None
+    }
+
+    /// Creates a `Note` of [`AnkiNote::model`]
+    fn to_note(&self) -> Result<Note> {
+        self.to_note_with_model(Self::model())
+    }
+
+    /// Creates a `Note` of `model`, e.g. the generated model with an additional template
+    ///
+    /// Returns `Err` if the fields do not match the model
+    fn to_note_with_model(&self, model: Model) -> Result<Note> {
+        let fields = self.fields();
+        let mut note =
+            Note::new(model, fields.iter().map(String::as_str).collect())?.tags(self.tags());
+        if let Some(guid) = self.guid() {
+            note = note.guid(guid);
+        }
+        Ok(note)
+    }
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use crate::{AnkiNote, ModelType};
+
+    #[derive(AnkiNote)]
+    #[anki(id = 1607392319, name = "German", sort_field = "Word")]
+    #[anki(css = ".card { color: black; }")]
+    #[anki(template(
+        name = "Card 1",
+        qfmt = "{{Audio}}{{Word}}",
+        afmt = "{{FrontSide}}<hr id=answer>{{Translation}}"
+    ))]
+    #[anki(template(
+        name = "Card 2",
+        qfmt = "{{#Translation}}{{Translation}}{{/Translation}}",
+        afmt = "{{FrontSide}}<hr id=answer>{{text:Word}}"
+    ))]
+    struct GermanWord {
+        #[anki(rename = "Audio")]
+        audio: Option<String>,
+        #[anki(rename = "Word", font = "Arial", size = 24, rtl, sticky)]
+        word: String,
+        #[anki(rename = "Translation")]
+        translation: &'static str,
+        #[anki(tags)]
+        tags: Vec<&'static str>,
+        #[anki(guid)]
+        id: u32,
+    }
+
+    #[derive(AnkiNote)]
+    #[anki(id = 1550428389, cloze)]
+    #[anki(template(
+        name = "Cloze",
+        qfmt = "{{cloze:Text}}",
+        afmt = "{{cloze:Text}}<br>{{Extra}}"
+    ))]
+    struct Cloze {
+        #[anki(rename = "Text")]
+        text: String,
+        #[anki(rename = "Extra")]
+        extra: String,
+    }
+
+    #[test]
+    fn derived_model_and_note() {
+        assert_eq!(GermanWord::FIELD_NAMES, ["Audio", "Word", "Translation"]);
+        let model = GermanWord::model();
+        assert_eq!(model.id, 1607392319);
+        let entry = model.to_model_db_entry(0.0, 1).unwrap();
+        assert_eq!(entry.name, "German");
+        assert_eq!(entry.css, ".card { color: black; }");
+        assert_eq!(entry.sortf, 1);
+        assert_eq!(entry.tmpls.len(), 2);
+        let word = &entry.flds[1];
+        assert_eq!(
+            (word.font.as_str(), word.size, word.rtl, word.sticky),
+            ("Arial", 24, true, true)
+        );
+
+        let note = GermanWord {
+            audio: None,
+            word: "Hund".to_string(),
+            translation: "dog",
+            tags: vec!["animal", "A1"],
+            id: 42,
+        }
+        .to_note()
+        .unwrap();
+        assert_eq!(note.fields(), ["", "Hund", "dog"]);
+        assert_eq!(note.get_tags(), ["animal", "A1"]);
+        assert_eq!(note.get_guid(), "42");
+        assert_eq!(note.cards().len(), 2);
+    }
+
+    #[test]
+    fn derived_cloze_model() {
+        assert!(Cloze::model().get_model_type() == ModelType::Cloze);
+        let note = Cloze {
+            text: "{{c1::Berlin}} is the capital of {{c2::Germany}}".to_string(),
+            extra: String::new(),
+        }
+        .to_note()
+        .unwrap();
+        assert_eq!(note.cards().len(), 2);
+        assert_eq!(
+            note.get_guid(),
+            crate::util::guid_for(Cloze::model().id, note.fields())
+        );
+    }
+}
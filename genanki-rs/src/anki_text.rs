@@ -0,0 +1,424 @@
+use crate::Error;
+use crate::csv_import::{CsvImport, ImportedNote, RowError, escape_html};
+use crate::deck::Deck;
+use crate::error::csv_error;
+use crate::model::Model;
+use crate::note::Note;
+use anyhow::{Result, anyhow};
+use csv::{QuoteStyle, ReaderBuilder, StringRecord, WriterBuilder};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// Options set by the `#key:value` lines at the top of a text file, columns counted from 0
+struct Headers {
+    separator: u8,
+    html: bool,
+    notetype: Option<String>,
+    notetype_column: Option<usize>,
+    deck: Option<String>,
+    deck_column: Option<usize>,
+    tags: Vec<String>,
+    tags_column: Option<usize>,
+    guid_column: Option<usize>,
+}
+
+impl Default for Headers {
+    fn default() -> Self {
+        Self {
+            separator: b'\t',
+            html: false,
+            notetype: None,
+            notetype_column: None,
+            deck: None,
+            deck_column: None,
+            tags: vec![],
+            tags_column: None,
+            guid_column: None,
+        }
+    }
+}
+
+impl Headers {
+    fn parse(&mut self, line: &str) -> Result<()> {
+        let Some((key, value)) = line.trim_start_matches('#').split_once(':') else {
+            return Ok(());
+        };
+        let value = value.trim();
+        let column = || -> Result<usize> {
+            match value.parse::<usize>() {
+                Ok(column) if column > 0 => Ok(column - 1),
+                _ => Err(anyhow!("invalid column {:?} in header {:?}", value, line)),
+            }
+        };
+        match key.trim() {
+            "separator" => self.separator = separator(value)?,
+            "html" => self.html = value == "true",
+            "notetype" => self.notetype = Some(value.to_string()),
+            "notetype column" => self.notetype_column = Some(column()?),
+            "deck" => self.deck = Some(value.to_string()),
+            "deck column" => self.deck_column = Some(column()?),
+            "tags" => self.tags = value.split_whitespace().map(str::to_string).collect(),
+            "tags column" => self.tags_column = Some(column()?),
+            "guid column" => self.guid_column = Some(column()?),
+            // Headers which do not affect the notes, e.g. `#columns`, are ignored
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn is_special(&self, column: usize) -> bool {
+        [
+            self.notetype_column,
+            self.deck_column,
+            self.tags_column,
+            self.guid_column,
+        ]
+        .contains(&Some(column))
+    }
+}
+
+/// Returns the separator named by the value of a `#separator` header
+fn separator(value: &str) -> Result<u8> {
+    match value {
+        "Comma" | "comma" => Ok(b','),
+        "Semicolon" | "semicolon" => Ok(b';'),
+        "Tab" | "tab" => Ok(b'\t'),
+        "Space" | "space" => Ok(b' '),
+        "Pipe" | "pipe" => Ok(b'|'),
+        "Colon" | "colon" => Ok(b':'),
+        _ if value.len() == 1 => Ok(value.as_bytes()[0]),
+        _ => Err(anyhow!("unsupported separator {:?}", value)),
+    }
+}
+
+/// Imports text files in the format of Anki's text import, configured by file headers.
+///
+/// The supported headers are `#separator`, `#html`, `#notetype`, `#notetype column`, `#deck`,
+/// `#deck column`, `#tags`, `#tags column` and `#guid column`. The note types named in the file
+/// are looked up by name in the models given to the importer; without a note type header the
+/// first model is used. The remaining columns are the fields of the note type, in order.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, AnkiTextImporter, Deck};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let input = "#separator:Semicolon\n#deck column:3\n#tags:geo\nFrance;Paris;Geo::Europe\n";
+///     let import = AnkiTextImporter::new(vec![basic_model()]).import_reader(input.as_bytes())?;
+///     assert!(import.errors.is_empty());
+///
+///     let decks = import.into_decks(Deck::new(1234, "Geography", ""));
+///     assert_eq!(decks.len(), 2);
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct AnkiTextImporter {
+    models: Vec<Model>,
+}
+
+impl AnkiTextImporter {
+    /// Creates an importer for notes of `models`
+    pub fn new(models: Vec<Model>) -> Self {
+        Self { models }
+    }
+
+    /// Imports the file at `path`
+    ///
+    /// Returns `Err` if the file cannot be read or a header is invalid. Errors in single rows are
+    /// reported in [`CsvImport::errors`] instead.
+    pub fn import_path<P: AsRef<Path>>(&self, path: P) -> Result<CsvImport> {
+        self.import_reader(File::open(path)?)
+    }
+
+    /// Imports text from any reader
+    ///
+    /// Returns `Err` if the input cannot be read or a header is invalid. Errors in single rows
+    /// are reported in [`CsvImport::errors`] instead.
+    pub fn import_reader<R: Read>(&self, reader: R) -> Result<CsvImport> {
+        let mut reader = BufReader::new(reader);
+        let mut headers = Headers::default();
+        let mut header_lines = 0;
+        let mut first_row = String::new();
+        loop {
+            first_row.clear();
+            if reader.read_line(&mut first_row)? == 0 || !first_row.starts_with('#') {
+                break;
+            }
+            headers.parse(first_row.trim_end())?;
+            header_lines += 1;
+        }
+
+        let mut reader = ReaderBuilder::new()
+            .delimiter(headers.separator)
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(first_row.as_bytes().chain(reader));
+        let mut import = CsvImport::default();
+        for (i, record) in reader.records().enumerate() {
+            let fallback_row = header_lines + i + 1;
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    import.errors.push(RowError {
+                        row: fallback_row,
+                        message: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let row = record.position().map_or(fallback_row, |position| {
+                header_lines + position.line() as usize
+            });
+            let deck = match headers.deck_column {
+                Some(column) => record.get(column),
+                None => headers.deck.as_deref(),
+            }
+            .map(str::trim)
+            .filter(|deck| !deck.is_empty())
+            .map(str::to_string);
+            match self.note_from_record(&record, &headers) {
+                Ok(note) => import.notes.push(ImportedNote { row, deck, note }),
+                Err(e) => import.errors.push(RowError {
+                    row,
+                    message: e.to_string(),
+                }),
+            }
+        }
+        Ok(import)
+    }
+
+    fn note_from_record(&self, record: &StringRecord, headers: &Headers) -> Result<Note> {
+        let notetype = match headers.notetype_column {
+            Some(column) => record.get(column),
+            None => headers.notetype.as_deref(),
+        };
+        let model = match notetype {
+            Some(name) => self.models.iter().find(|model| model.name() == name.trim()),
+            None => self.models.first(),
+        }
+        .ok_or_else(|| {
+            anyhow!(Error::UnknownModel(
+                notetype.unwrap_or_default().to_string()
+            ))
+        })?;
+
+        let mut fields = record
+            .iter()
+            .enumerate()
+            .filter(|(column, _)| !headers.is_special(*column))
+            .map(|(_, value)| {
+                if headers.html {
+                    value.to_string()
+                } else {
+                    escape_html(value)
+                }
+            })
+            .collect::<Vec<_>>();
+        let field_count = model.fields().len();
+        if fields[field_count.min(fields.len())..]
+            .iter()
+            .any(|value| !value.is_empty())
+        {
+            return Err(anyhow!(Error::ModelFieldCountMismatch {
+                model_len: field_count,
+                card_len: fields.len(),
+            }));
+        }
+        fields.resize(field_count, String::new());
+
+        let mut tags = headers.tags.iter().map(String::as_str).collect::<Vec<_>>();
+        if let Some(column_tags) = headers.tags_column.and_then(|column| record.get(column)) {
+            tags.extend(column_tags.split_whitespace());
+        }
+        let guid = headers
+            .guid_column
+            .and_then(|column| record.get(column))
+            .map(str::trim)
+            .filter(|guid| !guid.is_empty());
+
+        Note::new_with_options(
+            model.clone(),
+            fields.iter().map(String::as_str).collect(),
+            None,
+            Some(tags),
+            guid,
+        )
+    }
+}
+
+impl Deck {
+    /// Writes the notes of the deck in the format of Anki's text import
+    ///
+    /// Every row contains the GUID, note type and deck of the note followed by its fields and
+    /// tags, with headers telling Anki which column is which. Fields are written as HTML and
+    /// separated by tabs. Notes of note types with fewer fields are padded with empty columns.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{basic_model, AnkiTextImporter, Deck, Note};
+    /// use anyhow::Result;
+    ///
+    /// fn main() -> Result<()> {
+    ///     let mut deck = Deck::new(2059400110, "Vocabulary", "");
+    ///     deck.add_note(Note::new(basic_model(), vec!["Hund", "dog"])?.tags(["animal"]));
+    ///     let mut text = vec![];
+    ///     deck.write_anki_text(&mut text)?;
+    ///
+    ///     let import = AnkiTextImporter::new(vec![basic_model()]).import_reader(&text[..])?;
+    ///     assert_eq!(import.notes[0].deck.as_deref(), Some("Vocabulary"));
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn write_anki_text<W: Write>(&self, mut writer: W) -> Result<()> {
+        let field_count = self
+            .notes()
+            .iter()
+            .map(|note| note.fields().len())
+            .max()
+            .unwrap_or(0);
+        writeln!(writer, "#separator:tab")?;
+        writeln!(writer, "#html:true")?;
+        writeln!(writer, "#guid column:1")?;
+        writeln!(writer, "#notetype column:2")?;
+        writeln!(writer, "#deck column:3")?;
+        writeln!(writer, "#tags column:{}", field_count + 4)?;
+
+        let mut writer = WriterBuilder::new()
+            .delimiter(b'\t')
+            .quote_style(QuoteStyle::Necessary)
+            .flexible(true)
+            .from_writer(writer);
+        for note in self.notes() {
+            let model = note.model();
+            let mut row = vec![
+                note.get_guid(),
+                model.name().to_string(),
+                self.name().to_string(),
+            ];
+            row.extend(note.fields().iter().cloned());
+            row.resize(field_count + 3, String::new());
+            row.push(note.get_tags().join(" "));
+            writer.write_record(&row).map_err(csv_error)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Field, ModelType, Template, basic_model};
+
+    fn cloze_model() -> Model {
+        Model::new(
+            1550428389,
+            "Cloze",
+            vec![
+                Field::new("Text"),
+                Field::new("Extra"),
+                Field::new("Source"),
+            ],
+            vec![
+                Template::new("Cloze")
+                    .qfmt("{{cloze:Text}}")
+                    .afmt("{{cloze:Text}}"),
+            ],
+        )
+        .model_type(ModelType::Cloze)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut deck = Deck::new(2059400110, "German::Vocabulary", "");
+        deck.add_note(
+            Note::new(
+                basic_model(),
+                vec!["Hund", "dog\t<b>\"animal\"</b>\nline 2"],
+            )
+            .unwrap()
+            .guid("hund")
+            .tags(["A1", "animal"]),
+        );
+        deck.add_note(
+            Note::new(cloze_model(), vec!["{{c1::laufen}}", "", "Goethe"])
+                .unwrap()
+                .guid("laufen"),
+        );
+        let mut text = vec![];
+        deck.write_anki_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with(
+            "#separator:tab\n#html:true\n#guid column:1\n#notetype column:2\n#deck column:3\n\
+             #tags column:7\nhund\tBasic (genanki)\tGerman::Vocabulary\tHund\t"
+        ));
+
+        let import = AnkiTextImporter::new(vec![basic_model(), cloze_model()])
+            .import_reader(text.as_bytes())
+            .unwrap();
+        assert!(import.errors.is_empty(), "{:?}", import.errors);
+        let hund = &import.notes[0];
+        assert_eq!(hund.row, 7);
+        assert_eq!(hund.deck.as_deref(), Some("German::Vocabulary"));
+        assert_eq!(hund.note.get_guid(), "hund");
+        assert_eq!(
+            hund.note.fields(),
+            ["Hund", "dog\t<b>\"animal\"</b>\nline 2"]
+        );
+        assert_eq!(hund.note.get_tags(), ["A1", "animal"]);
+        let laufen = &import.notes[1];
+        assert_eq!(laufen.row, 9);
+        assert_eq!(laufen.note.model().name(), "Cloze");
+        assert_eq!(laufen.note.fields(), ["{{c1::laufen}}", "", "Goethe"]);
+    }
+
+    #[test]
+    fn fixed_headers_apply_to_every_row() {
+        let input = "#separator:Semicolon\n\
+                     #html:false\n\
+                     #notetype:Basic (genanki)\n\
+                     #deck:Geography\n\
+                     #tags:imported\n\
+                     #tags column:3\n\
+                     a < b;yes;math\n\
+                     France;Paris\n";
+        let import = AnkiTextImporter::new(vec![cloze_model(), basic_model()])
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert!(import.errors.is_empty());
+        let first = &import.notes[0];
+        assert_eq!(first.row, 7);
+        assert_eq!(first.note.fields(), ["a &lt; b", "yes"]);
+        assert_eq!(first.note.get_tags(), ["imported", "math"]);
+        assert_eq!(first.deck.as_deref(), Some("Geography"));
+        assert_eq!(import.notes[1].note.get_tags(), ["imported"]);
+    }
+
+    #[test]
+    fn reports_row_errors() {
+        let input = "#notetype column:1\n\
+                     Basic (genanki)\tHund\tdog\n\
+                     Unknown\tKatze\tcat\n\
+                     Basic (genanki)\tMaus\tmouse\textra\n";
+        let import = AnkiTextImporter::new(vec![basic_model()])
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(import.notes.len(), 1);
+        assert_eq!(
+            import.errors.iter().map(|e| e.row).collect::<Vec<_>>(),
+            [3, 4]
+        );
+
+        let input = "#separator:Tabs\nHund\tdog\n";
+        assert!(
+            AnkiTextImporter::new(vec![basic_model()])
+                .import_reader(input.as_bytes())
+                .is_err()
+        );
+    }
+}
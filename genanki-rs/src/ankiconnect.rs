@@ -0,0 +1,574 @@
+use crate::Error;
+use crate::deck::Deck;
+use crate::model::{Model, ModelType};
+use crate::note::Note;
+use crate::package::Package;
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value, json};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Version of the AnkiConnect API the requests are written for
+const API_VERSION: i64 = 6;
+
+/// Number of notes of a deck pushed with `push_deck`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PushSummary {
+    /// Notes added as new notes
+    pub added: usize,
+    /// Notes which had a duplicate in the collection, whose fields were updated instead
+    pub updated: usize,
+}
+
+/// Client for [AnkiConnect](https://foosoft.net/projects/anki-connect/), to push decks into a
+/// running Anki without exporting a package.
+///
+/// AnkiConnect identifies notes by their first field instead of their GUID, so a note whose
+/// first field is already used by a note of the same model updates the fields of that note.
+/// Tags of updated notes, card states and decks set by templates are not pushed.
+///
+/// Example:
+///
+/// ```rust,no_run
+/// use genanki_rs::{AnkiConnect, Deck, Note, basic_model};
+/// use anyhow::Result;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<()> {
+///     let mut deck = Deck::new(2059400110, "Vocabulary", "");
+///     deck.add_note(Note::new(basic_model(), vec!["Hund", "dog"])?);
+///     let summary = AnkiConnect::new().push_deck(&deck).await?;
+///     println!("{} added, {} updated", summary.added, summary.updated);
+///     Ok(())
+/// }
+/// ```
+///
+/// The builder has the following default values:
+/// * `url` - `"http://127.0.0.1:8765"`
+/// * `key` - `None`
+/// * `batch_size` - `100`
+#[derive(Clone, Debug)]
+pub struct AnkiConnect {
+    client: reqwest::Client,
+    url: String,
+    key: Option<String>,
+    batch_size: usize,
+}
+
+impl Default for AnkiConnect {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnkiConnect {
+    /// Creates a client for AnkiConnect on its default port
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: "http://127.0.0.1:8765".to_string(),
+            key: None,
+            batch_size: 100,
+        }
+    }
+
+    /// Sets the URL AnkiConnect listens on
+    pub fn url(self, url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets the API key, if AnkiConnect is configured to require one
+    pub fn key(self, key: &str) -> Self {
+        Self {
+            key: Some(key.to_string()),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of notes sent in one request
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            ..self
+        }
+    }
+
+    /// Returns the API version of AnkiConnect
+    pub async fn version(&self) -> Result<i64> {
+        self.invoke("version", json!({})).await
+    }
+
+    /// Creates `model` unless a model of the same name exists, returns whether it was created
+    pub async fn create_model(&self, model: &Model) -> Result<bool> {
+        let names: Vec<String> = self.invoke("modelNames", json!({})).await?;
+        if names.iter().any(|name| name == model.name()) {
+            return Ok(false);
+        }
+        let _: Value = self.invoke("createModel", model_params(model)?).await?;
+        Ok(true)
+    }
+
+    /// Creates a deck named `name` unless it exists, returns the id of the deck
+    pub async fn create_deck(&self, name: &str) -> Result<i64> {
+        self.invoke("createDeck", json!({ "deck": name })).await
+    }
+
+    /// Stores the media file at `path` under its file name, returns the name Anki stored it as
+    pub async fn store_media_file(&self, path: impl AsRef<Path>) -> Result<String> {
+        let path = path.as_ref();
+        let filename = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
+                anyhow!(Error::AnkiConnect(format!(
+                    "{} has no valid file name",
+                    path.display()
+                )))
+            })?;
+        let data = STANDARD.encode(std::fs::read(path)?);
+        self.invoke(
+            "storeMediaFile",
+            json!({ "filename": filename, "data": data }),
+        )
+        .await
+    }
+
+    /// Creates the deck and the models of its notes, then adds the notes in batches
+    ///
+    /// Notes with a duplicate in the collection or earlier in the deck update the fields of the
+    /// duplicate.
+    pub async fn push_deck(&self, deck: &Deck) -> Result<PushSummary> {
+        let mut created = HashSet::new();
+        for note in deck.notes() {
+            let model = note.model();
+            if created.insert(model.name().to_string()) {
+                self.create_model(&model).await?;
+            }
+        }
+        self.create_deck(deck.name()).await?;
+
+        let mut summary = PushSummary::default();
+        for batch in deck.notes().chunks(self.batch_size) {
+            let params = batch
+                .iter()
+                .map(|note| note_params(note, deck.name()))
+                .collect::<Result<Vec<_>>>()?;
+            let checks: Vec<Value> = self
+                .invoke("canAddNotesWithErrorDetail", json!({ "notes": params }))
+                .await?;
+
+            // Anki only checks against the collection, so a note sharing its model and first
+            // field with an earlier note of the batch is updated after that note is added
+            let mut new = vec![];
+            let mut new_keys = HashSet::new();
+            let mut duplicates = vec![];
+            for ((note, params), check) in batch.iter().zip(params).zip(checks) {
+                if check["canAdd"].as_bool().unwrap_or(false) {
+                    let key = (note.model().name().to_string(), note.fields()[0].clone());
+                    if new_keys.insert(key) {
+                        new.push(params);
+                    } else {
+                        duplicates.push((note, params));
+                    }
+                } else {
+                    let error = check["error"].as_str().unwrap_or("cannot add note");
+                    if !error.contains("duplicate") {
+                        return Err(anyhow!(Error::AnkiConnect(error.to_string())));
+                    }
+                    duplicates.push((note, params));
+                }
+            }
+
+            if !new.is_empty() {
+                let ids: Vec<Option<i64>> =
+                    self.invoke("addNotes", json!({ "notes": new })).await?;
+                if ids.iter().any(Option::is_none) {
+                    return Err(anyhow!(Error::AnkiConnect(
+                        "some notes could not be added".to_string()
+                    )));
+                }
+                summary.added += ids.len();
+            }
+            if !duplicates.is_empty() {
+                self.update_duplicates(&duplicates).await?;
+                summary.updated += duplicates.len();
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Stores the media files of `package`, then pushes its decks
+    pub async fn push_package(&self, package: &Package) -> Result<PushSummary> {
+        for path in package.media_files() {
+            self.store_media_file(path).await?;
+        }
+        let mut summary = PushSummary::default();
+        for deck in package.decks() {
+            let pushed = self.push_deck(deck).await?;
+            summary.added += pushed.added;
+            summary.updated += pushed.updated;
+        }
+        Ok(summary)
+    }
+
+    /// Finds the notes sharing the first field with `duplicates` and updates their fields
+    async fn update_duplicates(&self, duplicates: &[(&Note, Value)]) -> Result<()> {
+        let searches = duplicates
+            .iter()
+            .map(|(note, _)| {
+                let model = note.model();
+                let first_field = &model.fields()[0].name;
+                let query = format!(
+                    "{} {}",
+                    search_term("note", model.name()),
+                    search_term(first_field, &note.fields()[0])
+                );
+                json!({ "action": "findNotes", "params": { "query": query } })
+            })
+            .collect::<Vec<_>>();
+        let found: Vec<Vec<i64>> = self.invoke_multi(searches).await?;
+
+        let updates = duplicates
+            .iter()
+            .zip(found)
+            .map(|((note, params), ids)| match ids.first() {
+                Some(id) => Ok(json!({
+                    "action": "updateNoteFields",
+                    "params": { "note": { "id": id, "fields": params["fields"] } }
+                })),
+                None => Err(anyhow!(Error::AnkiConnect(format!(
+                    "no duplicate of the note with the first field {:?} found",
+                    note.fields()[0]
+                )))),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let _: Vec<Value> = self.invoke_multi(updates).await?;
+        Ok(())
+    }
+
+    /// Sends `actions` in a single `multi` request and returns their results
+    async fn invoke_multi<T: DeserializeOwned>(&self, actions: Vec<Value>) -> Result<Vec<T>> {
+        let responses: Vec<Value> = self.invoke("multi", json!({ "actions": actions })).await?;
+        responses.into_iter().map(parse_response).collect()
+    }
+
+    async fn invoke<T: DeserializeOwned>(&self, action: &str, params: Value) -> Result<T> {
+        let mut request = json!({
+            "action": action,
+            "version": API_VERSION,
+            "params": params,
+        });
+        if let Some(key) = &self.key {
+            request["key"] = json!(key);
+        }
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        parse_response(response)
+    }
+}
+
+/// Returns the `result` of an AnkiConnect response, or its `error` as `Err`
+fn parse_response<T: DeserializeOwned>(mut response: Value) -> Result<T> {
+    if let Some(error) = response.get("error").and_then(Value::as_str) {
+        return Err(anyhow!(Error::AnkiConnect(error.to_string())));
+    }
+    Ok(serde_json::from_value(response["result"].take())?)
+}
+
+fn model_params(model: &Model) -> Result<Value> {
+    let entry = model.to_model_db_entry(0.0, 1)?;
+    let templates = entry
+        .tmpls
+        .iter()
+        .map(|template| {
+            json!({
+                "Name": template.name,
+                "Front": template.qfmt,
+                "Back": template.afmt,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "modelName": entry.name,
+        "inOrderFields": entry.flds.iter().map(|field| &field.name).collect::<Vec<_>>(),
+        "css": entry.css,
+        "isCloze": model.get_model_type() == ModelType::Cloze,
+        "cardTemplates": templates,
+    }))
+}
+
+fn note_params(note: &Note, deck_name: &str) -> Result<Value> {
+    let model = note.model();
+    let fields = model
+        .fields()
+        .into_iter()
+        .zip(note.fields())
+        .map(|(field, value)| (field.name, json!(value)))
+        .collect::<Map<_, _>>();
+    Ok(json!({
+        "deckName": deck_name,
+        "modelName": model.name(),
+        "fields": fields,
+        "tags": note.get_tags(),
+        "options": { "allowDuplicate": false },
+    }))
+}
+
+/// Returns a search for `value` in `field`, escaping the characters special to Anki searches
+fn search_term(field: &str, value: &str) -> String {
+    let escape = |text: &str| {
+        text.chars()
+            .flat_map(|c| match c {
+                '\\' | '"' | '*' | '_' => vec!['\\', c],
+                c => vec![c],
+            })
+            .collect::<String>()
+    };
+    format!("\"{}:{}\"", escape(field), escape(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_model;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    type Requests = Arc<Mutex<Vec<Value>>>;
+
+    /// Starts an HTTP server answering AnkiConnect requests with `respond`, returns its URL and
+    /// the requests it received
+    async fn mock_server(respond: fn(&str, &Value) -> Value) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Requests::default();
+        let received = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let received = received.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    loop {
+                        let mut content_length = 0;
+                        let mut line = String::new();
+                        loop {
+                            line.clear();
+                            if stream.read_line(&mut line).await.unwrap() == 0 {
+                                return;
+                            }
+                            let header = line.to_lowercase();
+                            if let Some(length) = header.strip_prefix("content-length:") {
+                                content_length = length.trim().parse().unwrap();
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                        }
+                        let mut body = vec![0; content_length];
+                        stream.read_exact(&mut body).await.unwrap();
+                        let request: Value = serde_json::from_slice(&body).unwrap();
+                        let action = request["action"].as_str().unwrap().to_string();
+                        let response = if action == "multi" {
+                            let responses = request["params"]["actions"]
+                                .as_array()
+                                .unwrap()
+                                .iter()
+                                .map(|inner| {
+                                    respond(inner["action"].as_str().unwrap(), &inner["params"])
+                                })
+                                .collect::<Vec<_>>();
+                            json!({ "result": responses, "error": null })
+                        } else {
+                            respond(&action, &request["params"])
+                        };
+                        received.lock().unwrap().push(request);
+                        let response = response.to_string();
+                        let head = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                            response.len()
+                        );
+                        let stream = stream.get_mut();
+                        stream.write_all(head.as_bytes()).await.unwrap();
+                        stream.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        (url, requests)
+    }
+
+    fn collection(action: &str, params: &Value) -> Value {
+        let result = match action {
+            "modelNames" => json!(["Basic (and reversed card)"]),
+            "createModel" | "updateNoteFields" => json!(null),
+            "createDeck" => json!(2059400110),
+            "canAddNotesWithErrorDetail" => params["notes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|note| match note["fields"]["Front"].as_str() {
+                    Some("Hund") => json!({
+                        "canAdd": false,
+                        "error": "cannot create note because it is a duplicate"
+                    }),
+                    _ => json!({ "canAdd": true }),
+                })
+                .collect(),
+            "addNotes" => {
+                // Like Anki, notes with the first field of an earlier note are not added
+                let mut fronts = HashSet::new();
+                params["notes"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .zip(1i64..)
+                    .map(|(note, id)| fronts.insert(note["fields"]["Front"].clone()).then_some(id))
+                    .collect()
+            }
+            "findNotes" => json!([1496198395707i64]),
+            "storeMediaFile" => params["filename"].clone(),
+            _ => return json!({ "result": null, "error": "unsupported action" }),
+        };
+        json!({ "result": result, "error": null })
+    }
+
+    fn actions(requests: &Requests) -> Vec<String> {
+        requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request["action"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn deck_is_pushed_in_batches() {
+        let (url, requests) = mock_server(collection).await;
+        let mut deck = Deck::new(2059400110, "Vocabulary", "");
+        for (front, back) in [("Hund", "dog"), ("Katze", "cat"), ("Maus", "mouse")] {
+            deck.add_note(
+                Note::new(basic_model(), vec![front, back])
+                    .unwrap()
+                    .tags(["animal"]),
+            );
+        }
+        let client = AnkiConnect::new().url(&url).key("secret").batch_size(2);
+        let summary = client.push_deck(&deck).await.unwrap();
+        assert_eq!(
+            summary,
+            PushSummary {
+                added: 2,
+                updated: 1
+            }
+        );
+        assert_eq!(
+            actions(&requests),
+            [
+                "modelNames",
+                "createModel",
+                "createDeck",
+                "canAddNotesWithErrorDetail",
+                "addNotes",
+                "multi",
+                "multi",
+                "canAddNotesWithErrorDetail",
+                "addNotes",
+            ]
+        );
+
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().all(|request| request["key"] == "secret"));
+        let model = &requests[1]["params"];
+        assert_eq!(model["inOrderFields"], json!(["Front", "Back"]));
+        assert_eq!(model["cardTemplates"][0]["Name"], "Card 1");
+        let search = &requests[5]["params"]["actions"][0]["params"]["query"];
+        assert_eq!(search, r#""note:Basic (genanki)" "Front:Hund""#);
+        let update = &requests[6]["params"]["actions"][0]["params"]["note"];
+        assert_eq!(update["id"], 1496198395707i64);
+        assert_eq!(update["fields"], json!({"Front": "Hund", "Back": "dog"}));
+        let added = &requests[4]["params"]["notes"][0];
+        assert_eq!(added["fields"]["Front"], "Katze");
+        assert_eq!(added["deckName"], "Vocabulary");
+        assert_eq!(added["tags"], json!(["animal"]));
+    }
+
+    #[tokio::test]
+    async fn duplicates_within_a_batch_are_updated() {
+        let (url, requests) = mock_server(collection).await;
+        let mut deck = Deck::new(2059400110, "Vocabulary", "");
+        for (front, back) in [("Katze", "cat"), ("Maus", "mouse"), ("Katze", "kitten")] {
+            deck.add_note(Note::new(basic_model(), vec![front, back]).unwrap());
+        }
+        let client = AnkiConnect::new().url(&url);
+        let summary = client.push_deck(&deck).await.unwrap();
+        assert_eq!(
+            summary,
+            PushSummary {
+                added: 2,
+                updated: 1
+            }
+        );
+
+        let requests = requests.lock().unwrap();
+        let added = requests[4]["params"]["notes"].as_array().unwrap();
+        assert_eq!(added.len(), 2);
+        let search = &requests[5]["params"]["actions"][0]["params"]["query"];
+        assert_eq!(search, r#""note:Basic (genanki)" "Front:Katze""#);
+        let update = &requests[6]["params"]["actions"][0]["params"]["note"];
+        assert_eq!(
+            update["fields"],
+            json!({"Front": "Katze", "Back": "kitten"})
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_are_mapped() {
+        let (url, _) = mock_server(|action, params| match action {
+            "storeMediaFile" => json!({ "result": params["data"], "error": null }),
+            _ => json!({ "result": null, "error": "collection is not available" }),
+        })
+        .await;
+        let client = AnkiConnect::new().url(&url);
+        let error = client.version().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "AnkiConnect request failed: collection is not available"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sound.mp3");
+        std::fs::write(&path, b"ID3").unwrap();
+        assert_eq!(client.store_media_file(&path).await.unwrap(), "SUQz");
+
+        assert!(
+            AnkiConnect::new()
+                .url("http://127.0.0.1:1")
+                .version()
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn search_terms_are_escaped() {
+        assert_eq!(
+            search_term("Front", r#"say "hi" *_\"#),
+            r#""Front:say \"hi\" \*\_\\""#
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use crate::Error;
+use crate::db_entries::{DeckDbEntry, ModelDbEntry};
+use crate::error::{json_error, zip_error};
+use crate::math::report_math_problems;
+use crate::media::media_references;
+use crate::validation::ValidationReport;
+use anyhow::{Result, anyhow};
+use sqlx::SqliteConnection;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
+use zip::ZipArchive;
+
+/// A note as stored in an `.apkg` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApkgNote {
+    pub id: i64,
+    pub guid: String,
+    pub model_id: i64,
+    pub tags: Vec<String>,
+    pub fields: Vec<String>,
+}
+
+/// A card as stored in an `.apkg` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApkgCard {
+    pub id: i64,
+    pub note_id: i64,
+    pub deck_id: i64,
+    pub ord: i64,
+    pub queue: i64,
+    pub due: i64,
+    pub flags: i64,
+}
+
+/// A media file contained in an `.apkg` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// The contents of an `.apkg` file, read back for inspection.
+///
+/// Packages written by genanki-rs as well as legacy exports of Anki (`collection.anki2` or
+/// `collection.anki21`) can be read. The compressed `collection.anki21b` format of recent Anki
+/// versions is not supported.
+///
+/// Example:
+///
+/// ```rust,no_run
+/// use genanki_rs::Apkg;
+/// use anyhow::Result;
+///
+/// #[tokio::main] async fn main() -> Result<()> {
+///     let apkg = Apkg::open("output.apkg").await?;
+///     for deck in apkg.decks.values() {
+///         println!("{}: {} cards", deck.name, apkg.cards_in_deck(deck.id).count());
+///     }
+///     print!("{}", apkg.validate());
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Apkg {
+    pub models: BTreeMap<i64, ModelDbEntry>,
+    pub decks: BTreeMap<i64, DeckDbEntry>,
+    pub notes: Vec<ApkgNote>,
+    pub cards: Vec<ApkgCard>,
+    pub media: Vec<MediaFile>,
+}
+
+impl Apkg {
+    /// Reads the `.apkg` file at `path`
+    ///
+    /// Returns `Err` if the file is not a readable package
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(File::open(path)?).await
+    }
+
+    /// Reads an `.apkg` file from any reader that implements Read and Seek
+    ///
+    /// Returns `Err` if the data is not a readable package
+    pub async fn from_reader<R: Read + Seek>(reader: R) -> Result<Self> {
+        let mut archive = ZipArchive::new(reader).map_err(zip_error)?;
+        let collection_name = ["collection.anki21", "collection.anki2"]
+            .into_iter()
+            .find(|name| archive.index_for_name(name).is_some())
+            .ok_or_else(|| {
+                anyhow!(Error::InvalidPackage(
+                    "no collection.anki2 or collection.anki21 found".to_string()
+                ))
+            })?;
+        let collection = read_entry(&mut archive, collection_name)?;
+
+        let media_map: HashMap<String, String> = match archive.index_for_name("media") {
+            Some(_) => {
+                serde_json::from_slice(&read_entry(&mut archive, "media")?).map_err(json_error)?
+            }
+            None => HashMap::new(),
+        };
+        let mut media = media_map
+            .into_iter()
+            .map(|(index, name)| {
+                let data = read_entry(&mut archive, &index)?;
+                Ok(MediaFile { name, data })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        media.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut db_file = NamedTempFile::new()?;
+        db_file.write_all(&collection)?;
+        db_file.flush()?;
+        let db_file_url = db_file.path().to_str().expect("temp path is valid UTF-8");
+        let pool = sqlx::SqlitePool::connect(db_file_url).await?;
+        let mut conn = pool.acquire().await?;
+        let mut apkg = Self::from_db(&mut conn).await?;
+        drop(conn);
+        pool.close().await;
+
+        apkg.media = media;
+        Ok(apkg)
+    }
+
+    async fn from_db(conn: &mut SqliteConnection) -> Result<Self> {
+        let col = sqlx::query!(
+            r#"
+            SELECT models, decks FROM col
+        "#
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        let models: HashMap<i64, ModelDbEntry> =
+            serde_json::from_str(&col.models).map_err(json_error)?;
+        let decks: HashMap<i64, DeckDbEntry> =
+            serde_json::from_str(&col.decks).map_err(json_error)?;
+
+        let notes = sqlx::query!(
+            r#"
+            SELECT id, guid, mid, tags, flds FROM notes ORDER BY id
+        "#
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|row| ApkgNote {
+            id: row.id,
+            guid: row.guid,
+            model_id: row.mid,
+            tags: row.tags.split_whitespace().map(str::to_string).collect(),
+            fields: row.flds.split('\x1f').map(str::to_string).collect(),
+        })
+        .collect();
+
+        let cards = sqlx::query!(
+            r#"
+            SELECT id, nid, did, ord, queue, due, flags FROM cards ORDER BY id
+        "#
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|row| ApkgCard {
+            id: row.id,
+            note_id: row.nid,
+            deck_id: row.did,
+            ord: row.ord,
+            queue: row.queue,
+            due: row.due,
+            flags: row.flags,
+        })
+        .collect();
+
+        Ok(Self {
+            models: models.into_iter().collect(),
+            decks: decks.into_iter().collect(),
+            notes,
+            cards,
+            media: vec![],
+        })
+    }
+
+    /// Returns all cards which belong to the deck with `deck_id`
+    pub fn cards_in_deck(&self, deck_id: i64) -> impl Iterator<Item = &ApkgCard> {
+        self.cards
+            .iter()
+            .filter(move |card| card.deck_id == deck_id)
+    }
+
+    /// Returns all notes of the model with `model_id`
+    pub fn notes_of_model(&self, model_id: i64) -> impl Iterator<Item = &ApkgNote> {
+        self.notes
+            .iter()
+            .filter(move |note| note.model_id == model_id)
+    }
+
+    /// Returns the deck the first card of `note` belongs to
+    pub fn deck_of_note(&self, note: &ApkgNote) -> Option<&DeckDbEntry> {
+        self.cards
+            .iter()
+            .find(|card| card.note_id == note.id)
+            .and_then(|card| self.decks.get(&card.deck_id))
+    }
+
+    /// Checks the package for inconsistencies Anki would choke on or silently drop
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        let mut guids = HashSet::new();
+        let note_ids = self
+            .notes
+            .iter()
+            .map(|note| note.id)
+            .collect::<HashSet<_>>();
+        let notes_with_cards = self
+            .cards
+            .iter()
+            .map(|card| card.note_id)
+            .collect::<HashSet<_>>();
+        let media_names = self
+            .media
+            .iter()
+            .map(|media| media.name.as_str())
+            .collect::<HashSet<_>>();
+        let mut referenced = HashSet::new();
+        for note in &self.notes {
+            let location = format!("note {}", note.guid);
+            if !guids.insert(note.guid.as_str()) {
+                report.error(&location, "GUID is used by more than one note");
+            }
+            match self.models.get(&note.model_id) {
+                Some(model) if model.flds.len() != note.fields.len() => report.error(
+                    &location,
+                    format!(
+                        "has {} fields but model {:?} has {}",
+                        note.fields.len(),
+                        model.name,
+                        model.flds.len()
+                    ),
+                ),
+                Some(model) => report_math_problems(
+                    &mut report,
+                    &location,
+                    model.flds.iter().map(|field| field.name.as_str()),
+                    &note.fields,
+                ),
+                None => report.error(
+                    &location,
+                    format!("refers to missing model {}", note.model_id),
+                ),
+            }
+            if !notes_with_cards.contains(&note.id) {
+                report.warning(&location, "note has no cards");
+            }
+            for field in &note.fields {
+                for reference in media_references(field) {
+                    if !media_names.contains(reference.as_str()) {
+                        report.warning(
+                            &location,
+                            format!("references missing media file {:?}", reference),
+                        );
+                    }
+                    referenced.insert(reference);
+                }
+            }
+        }
+
+        for card in &self.cards {
+            let location = format!("card {}", card.id);
+            if !note_ids.contains(&card.note_id) {
+                report.error(
+                    &location,
+                    format!("refers to missing note {}", card.note_id),
+                );
+            }
+            if !self.decks.contains_key(&card.deck_id) {
+                report.error(
+                    &location,
+                    format!("refers to missing deck {}", card.deck_id),
+                );
+            }
+        }
+
+        for model in self.models.values() {
+            for template in &model.tmpls {
+                referenced.extend(media_references(&template.qfmt));
+                referenced.extend(media_references(&template.afmt));
+            }
+        }
+        for media in &self.media {
+            if !media.name.starts_with('_') && !referenced.contains(&media.name) {
+                report.warning(
+                    format!("media {}", media.name),
+                    "media file is not referenced by any note",
+                );
+            }
+        }
+        report
+    }
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>> {
+    let mut entry = archive.by_name(name).map_err(zip_error)?;
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Deck, Note, Package, basic_and_reversed_card_model, basic_model};
+    use sqlx::{Pool, Sqlite};
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn reads_back_written_package(pool: Pool<Sqlite>) {
+        let media_dir = TempDir::new().unwrap();
+        let sound = media_dir.path().join("sound.mp3");
+        std::fs::write(&sound, b"mp3").unwrap();
+        let unused = media_dir.path().join("unused.jpg");
+        std::fs::write(&unused, b"jpg").unwrap();
+
+        let mut deck = Deck::new(1234, "Example", "");
+        deck.add_note(
+            Note::new(basic_model(), vec!["a [sound:sound.mp3]", "b"])
+                .unwrap()
+                .tags(["x", "y"]),
+        );
+        deck.add_note(
+            Note::new(
+                basic_and_reversed_card_model(),
+                vec!["c", "d <img src=\"gone.png\">"],
+            )
+            .unwrap(),
+        );
+        let mut package = Package::new(
+            vec![deck],
+            vec![sound.to_str().unwrap(), unused.to_str().unwrap()],
+        )
+        .unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        package
+            .write_maybe_timestamp(None, &mut conn)
+            .await
+            .unwrap();
+        let mut buffer = Cursor::new(vec![]);
+        let connect_options = pool.connect_options();
+        package
+            .write_to_zip(&mut buffer, connect_options.get_filename())
+            .unwrap();
+        buffer.set_position(0);
+
+        let apkg = Apkg::from_reader(buffer).await.unwrap();
+        assert_eq!(apkg.models.len(), 2);
+        assert_eq!(apkg.decks[&1234].name, "Example");
+        assert_eq!(apkg.cards_in_deck(1234).count(), 3);
+        assert_eq!(apkg.notes.len(), 2);
+        assert_eq!(apkg.notes[0].tags, ["x", "y"]);
+        assert_eq!(apkg.notes[0].fields, ["a [sound:sound.mp3]", "b"]);
+        assert_eq!(apkg.notes_of_model(1559383000).count(), 1);
+        assert_eq!(apkg.deck_of_note(&apkg.notes[1]).unwrap().id, 1234);
+        assert_eq!(
+            apkg.media
+                .iter()
+                .map(|media| media.name.as_str())
+                .collect::<Vec<_>>(),
+            ["sound.mp3", "unused.jpg"]
+        );
+
+        let report = apkg.validate();
+        assert!(report.is_ok());
+        let warnings = report
+            .warnings()
+            .map(|issue| issue.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            [
+                "references missing media file \"gone.png\"",
+                "media file is not referenced by any note"
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_reports_broken_references() {
+        let mut apkg = Apkg::default();
+        apkg.notes.push(ApkgNote {
+            id: 1,
+            guid: "abc".to_string(),
+            model_id: 42,
+            tags: vec![],
+            fields: vec![],
+        });
+        apkg.cards.push(ApkgCard {
+            id: 7,
+            note_id: 2,
+            deck_id: 3,
+            ord: 0,
+            queue: 0,
+            due: 0,
+            flags: 0,
+        });
+        let report = apkg.validate();
+        let errors = report
+            .errors()
+            .map(|issue| issue.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "error: note abc: refers to missing model 42",
+                "error: card 7: refers to missing note 2",
+                "error: card 7: refers to missing deck 3"
+            ]
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Command-line interface of genanki-rs, built with the `cli` feature.
+
+use anyhow::{Result, anyhow};
+use clap::{Parser, Subcommand};
+use genanki_rs::{Apkg, ApkgNote, PackageDiff, Project, ValidationReport};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    name = "genanki",
+    version,
+    about = "Build, inspect and convert Anki packages"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build an .apkg file from a source directory or a single CSV, TSV or JSON note file
+    Build {
+        /// Source directory, manifest or note file
+        source: PathBuf,
+        /// Path of the package to write
+        #[arg(short, long)]
+        output: PathBuf,
+        #[command(flatten)]
+        note_file: NoteFileArgs,
+        /// Modification time of the written notes, cards, decks and models in seconds since the
+        /// epoch, the current time by default
+        #[arg(long)]
+        timestamp: Option<f64>,
+    },
+    /// Print the decks, models and media of an .apkg file
+    Inspect {
+        /// Package to inspect
+        apkg: PathBuf,
+    },
+    /// Check an .apkg file or a source for problems, failing if errors are found
+    Validate {
+        /// Package, source directory, manifest or note file
+        input: PathBuf,
+        #[command(flatten)]
+        note_file: NoteFileArgs,
+    },
+    /// Show what changed between two .apkg files, failing if they differ
+    Diff {
+        /// Previous version of the package
+        old: PathBuf,
+        /// New version of the package
+        new: PathBuf,
+    },
+    /// Convert between sources and packages, chosen by the file extensions
+    ///
+    /// A source is converted to .apkg, an .apkg file to .csv, .tsv or .json notes.
+    Convert {
+        /// Input package, source directory, manifest or note file
+        input: PathBuf,
+        /// Output file ending in .apkg, .csv, .tsv or .json
+        output: PathBuf,
+        #[command(flatten)]
+        note_file: NoteFileArgs,
+    },
+}
+
+#[derive(clap::Args)]
+struct NoteFileArgs {
+    /// Built-in model of the notes in a single note file
+    #[arg(long, default_value = "basic")]
+    model: String,
+    /// Deck name of the notes in a single note file, defaults to the file name
+    #[arg(long)]
+    deck: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    match Cli::parse().command {
+        Command::Build {
+            source,
+            output,
+            note_file,
+            timestamp,
+        } => build(&source, &output, &note_file, timestamp).await,
+        Command::Inspect { apkg } => {
+            inspect(&Apkg::open(apkg).await?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Validate { input, note_file } => {
+            let report = if is_apkg(&input) {
+                Apkg::open(&input).await?.validate()
+            } else {
+                load_project(&input, &note_file)?.build()?.1
+            };
+            Ok(print_report(&report))
+        }
+        Command::Diff { old, new } => {
+            let diff = PackageDiff::new(&Apkg::open(old).await?, &Apkg::open(new).await?);
+            print!("{}", diff);
+            Ok(if diff.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            })
+        }
+        Command::Convert {
+            input,
+            output,
+            note_file,
+        } => {
+            if extension(&output) == "apkg" {
+                build(&input, &output, &note_file, None).await
+            } else if is_apkg(&input) {
+                export_notes(&Apkg::open(&input).await?, &output)?;
+                Ok(ExitCode::SUCCESS)
+            } else {
+                Err(anyhow!(
+                    "cannot convert {:?} to {:?}, one of them must be an .apkg file",
+                    input,
+                    output
+                ))
+            }
+        }
+    }
+}
+
+async fn build(
+    source: &Path,
+    output: &Path,
+    note_file: &NoteFileArgs,
+    timestamp: Option<f64>,
+) -> Result<ExitCode> {
+    let (mut package, report) = load_project(source, note_file)?.build()?;
+    let status = print_report(&report);
+    if report.is_ok() {
+        let output = output
+            .to_str()
+            .ok_or_else(|| anyhow!("{:?} is not a valid output path", output))?;
+        package.generate_anki(output, timestamp).await?;
+    }
+    Ok(status)
+}
+
+fn load_project(source: &Path, note_file: &NoteFileArgs) -> Result<Project> {
+    if source.is_dir() {
+        return Project::load(source);
+    }
+    match source.file_name().and_then(|name| name.to_str()) {
+        Some("genanki.toml" | "genanki.json") => {
+            Project::load(source.parent().unwrap_or(Path::new(".")))
+        }
+        _ => {
+            let deck = match &note_file.deck {
+                Some(deck) => deck.clone(),
+                None => source
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or("Default")
+                    .to_string(),
+            };
+            Project::from_note_file(source, &deck, &note_file.model)
+        }
+    }
+}
+
+fn inspect(apkg: &Apkg) {
+    println!("Decks:");
+    for deck in apkg.decks.values() {
+        println!(
+            "  {}  {}  ({} cards)",
+            deck.id,
+            deck.name,
+            apkg.cards_in_deck(deck.id).count()
+        );
+    }
+    println!("Models:");
+    for (&id, model) in &apkg.models {
+        println!(
+            "  {}  {}  ({} notes)",
+            id,
+            model.name,
+            apkg.notes_of_model(id).count()
+        );
+        let fields = model.flds.iter().map(|field| field.name.as_str());
+        println!("    fields: {}", fields.collect::<Vec<_>>().join(", "));
+        let templates = model.tmpls.iter().map(|template| template.name.as_str());
+        println!(
+            "    templates: {}",
+            templates.collect::<Vec<_>>().join(", ")
+        );
+    }
+    println!("Notes: {}", apkg.notes.len());
+    println!("Cards: {}", apkg.cards.len());
+    let media_size: usize = apkg.media.iter().map(|file| file.data.len()).sum();
+    println!("Media: {} files, {} bytes", apkg.media.len(), media_size);
+    for file in &apkg.media {
+        println!("  {}  {} bytes", file.name, file.data.len());
+    }
+}
+
+fn print_report(report: &ValidationReport) -> ExitCode {
+    if report.issues.is_empty() {
+        println!("No problems found");
+    } else {
+        print!("{}", report);
+    }
+    if report.is_ok() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn export_notes(apkg: &Apkg, output: &Path) -> Result<()> {
+    let deck_name = |note: &ApkgNote| {
+        apkg.deck_of_note(note)
+            .map(|deck| deck.name.clone())
+            .unwrap_or_default()
+    };
+    match extension(output).as_str() {
+        "json" => {
+            let notes = apkg
+                .notes
+                .iter()
+                .map(|note| {
+                    let model = apkg.models.get(&note.model_id);
+                    let fields = match model {
+                        Some(model) => serde_json::Value::Object(
+                            model
+                                .flds
+                                .iter()
+                                .map(|field| field.name.clone())
+                                .zip(note.fields.iter().cloned().map(Into::into))
+                                .collect(),
+                        ),
+                        None => note.fields.clone().into(),
+                    };
+                    serde_json::json!({
+                        "model": model.map(|model| model.name.clone()),
+                        "fields": fields,
+                        "tags": note.tags,
+                        "guid": note.guid,
+                        "deck": deck_name(note),
+                    })
+                })
+                .collect::<Vec<_>>();
+            std::fs::write(output, serde_json::to_string_pretty(&notes)?)?;
+        }
+        ext @ ("csv" | "tsv") => {
+            let model = match apkg.models.values().collect::<Vec<_>>().as_slice() {
+                [model] => *model,
+                models => {
+                    return Err(anyhow!(
+                        "CSV export needs a package with one model, found {}",
+                        models.len()
+                    ));
+                }
+            };
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(if ext == "tsv" { b'\t' } else { b',' })
+                .from_path(output)?;
+            let mut header = model
+                .flds
+                .iter()
+                .map(|field| field.name.clone())
+                .collect::<Vec<_>>();
+            header.extend(["Tags", "Guid", "Deck"].map(String::from));
+            writer.write_record(&header)?;
+            for note in &apkg.notes {
+                let mut record = note.fields.clone();
+                record.resize(model.flds.len(), String::new());
+                record.extend([note.tags.join(" "), note.guid.clone(), deck_name(note)]);
+                writer.write_record(&record)?;
+            }
+            writer.flush()?;
+        }
+        _ => return Err(anyhow!("unsupported output format {:?}", output)),
+    }
+    Ok(())
+}
+
+fn is_apkg(path: &Path) -> bool {
+    extension(path) == "apkg"
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase()
+}
@@ -1,5 +1,9 @@
 mod field;
+mod note;
 mod template;
+mod tts;
 
 pub use field::Field;
+pub use note::NoteBuilder;
 pub use template::Template;
+pub use tts::Tts;
@@ -0,0 +1,115 @@
+use crate::Error;
+use crate::model::Model;
+use crate::note::Note;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+
+/// Builder for a `Note` which sets fields by name instead of by position.
+///
+/// A `NoteBuilder` is created with `Note::builder`.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_and_reversed_card_model, Note};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let note = Note::builder(basic_and_reversed_card_model())
+///         .field("Back", "Paris")
+///         .field("Front", "What is the capital of France?")
+///         .build()?;
+///     Ok(())
+/// }
+/// ```
+///
+/// Fields which are not set are empty.
+#[derive(Clone)]
+pub struct NoteBuilder {
+    model: Model,
+    fields: HashMap<String, String>,
+}
+
+impl NoteBuilder {
+    pub(crate) fn new(model: Model) -> Self {
+        Self {
+            model,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Sets the field `name` to `value`, replacing an earlier value
+    pub fn field(mut self, name: &str, value: impl ToString) -> Self {
+        self.fields.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets several fields from `(name, value)` pairs
+    pub fn fields(
+        mut self,
+        fields: impl IntoIterator<Item = (impl ToString, impl ToString)>,
+    ) -> Self {
+        self.fields.extend(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        self
+    }
+
+    /// Creates the note
+    ///
+    /// Returns `Err` if a field name is not a field of the model, or if the fields are invalid
+    pub fn build(mut self) -> Result<Note> {
+        let fields = self
+            .model
+            .fields()
+            .iter()
+            .map(|field| self.fields.remove(&field.name).unwrap_or_default())
+            .collect::<Vec<_>>();
+        if let Some(name) = self.fields.keys().min() {
+            return Err(anyhow!(Error::UnknownField {
+                model: self.model.name().to_string(),
+                field: name.clone(),
+            }));
+        }
+        Note::new(self.model, fields.iter().map(String::as_str).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{basic_model, basic_optional_reversed_card_model};
+
+    #[test]
+    fn fields_are_set_by_name() {
+        let note = Note::builder(basic_optional_reversed_card_model())
+            .field("Back", "Paris")
+            .fields([("Front", "Capital of France?"), ("Reverse", "y")])
+            .build()
+            .unwrap();
+        assert_eq!(note.fields(), ["Capital of France?", "Paris", "y"]);
+        assert_eq!(note.cards().len(), 2);
+
+        let note = Note::builder(basic_model())
+            .field("Front", "only the front")
+            .build()
+            .unwrap();
+        assert_eq!(note.fields(), ["only the front", ""]);
+    }
+
+    #[test]
+    fn unknown_field_is_an_error() {
+        let error = Note::builder(basic_model())
+            .field("Front", "Hund")
+            .field("Backk", "dog")
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "model \"Basic (genanki)\" has no field named \"Backk\""
+        );
+    }
+}
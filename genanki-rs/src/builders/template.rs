@@ -1,4 +1,5 @@
-use crate::db_entries::Tmpl;
+use crate::db_entries::{DeckDbEntry, Tmpl};
+use crate::deck::Deck;
 
 /// Template to be fed into a `Model`.
 /// A Template represents the structure of `Notes` (Flashcards) in the deck and can be created using
@@ -12,11 +13,24 @@ use crate::db_entries::Tmpl;
 /// let template2 = Template::new("Card 2").qfmt("{{Back}}").afmt("{{FrontSide}}\n\n<hr id=answer>\n\n{{Front}}");
 /// ```
 ///
+/// Cards of a template can be placed in another deck than the one their note is added to, e.g. to
+/// collect all reversed cards in a separate deck:
+/// ```rust
+/// use genanki_rs::{Deck, Template};
+///
+/// let reverse_deck = Deck::new(2059400111, "Lang::Reverse", "");
+/// let template = Template::new("Card 2")
+///     .qfmt("{{Back}}")
+///     .afmt("{{FrontSide}}\n\n<hr id=answer>\n\n{{Front}}")
+///     .deck(&reverse_deck);
+/// ```
+///
 #[derive(Clone)]
 pub struct Template {
     name: String,
     qfmt: Option<String>,
     did: Option<usize>,
+    deck: Option<DeckDbEntry>,
     bafmt: Option<String>,
     afmt: Option<String>,
     bqfmt: Option<String>,
@@ -29,6 +43,7 @@ impl Template {
             name: name.to_string(),
             qfmt: None,
             did: None,
+            deck: None,
             bafmt: None,
             afmt: None,
             bqfmt: None,
@@ -41,12 +56,30 @@ impl Template {
         self
     }
 
-    /// Sets the deck id of the currently created `Template`
+    /// Sets the id of the deck cards of the currently created `Template` are placed in
+    ///
+    /// The deck has to be part of the `Package` or already exist in the collection, otherwise
+    /// writing the package fails. Use [`Template::deck`] to create the deck along with the cards.
     pub fn did(mut self, did: usize) -> Self {
         self.did = Some(did);
+        self.deck = None;
         self
     }
 
+    /// Places the cards of the currently created `Template` in `deck` instead of the deck their
+    /// note is added to
+    ///
+    /// `deck` is written to the collection together with the cards, its notes are ignored.
+    pub fn deck(mut self, deck: &Deck) -> Self {
+        self.did = Some(deck.id() as usize);
+        self.deck = Some(deck.to_deck_db_entry());
+        self
+    }
+
+    pub(crate) fn override_deck(&self) -> Option<&DeckDbEntry> {
+        self.deck.as_ref()
+    }
+
     /// Sets the browser answer format of the currently created `Template`
     pub fn bafmt(mut self, bafmt: &str) -> Self {
         self.bafmt = Some(bafmt.to_string());
@@ -0,0 +1,94 @@
+use std::fmt::Write;
+
+/// Builder for the `{{tts ...}}` tags which make Anki read a field aloud with text-to-speech.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{Template, Tts};
+///
+/// let tts = Tts::new("de_DE").voices(["Apple_Anna", "Microsoft_Hedda"]).speed(0.8);
+/// assert_eq!(
+///     tts.tag("Front"),
+///     "{{tts de_DE voices=Apple_Anna,Microsoft_Hedda speed=0.8:Front}}"
+/// );
+///
+/// let template = Template::new("Card 1")
+///     .qfmt(&format!("{{{{Front}}}}{}", tts.tag("Front")))
+///     .afmt(r#"{{FrontSide}}<hr id="answer">{{Back}}"#);
+/// ```
+///
+/// The builder has the following default values:
+/// * `voices` - none, Anki picks a voice for the language
+/// * `speed` - none, Anki uses the normal speed
+/// * `cloze_only` - `false`
+#[derive(Clone, Debug)]
+pub struct Tts {
+    lang: String,
+    voices: Vec<String>,
+    speed: Option<f64>,
+    cloze_only: bool,
+}
+
+impl Tts {
+    /// Creates a new builder for the language `lang`, e.g. `de_DE` or `ja_JP`
+    pub fn new(lang: &str) -> Self {
+        Self {
+            lang: lang.to_string(),
+            voices: vec![],
+            speed: None,
+            cloze_only: false,
+        }
+    }
+
+    /// Sets the voices to try in order, e.g. `Apple_Anna`
+    pub fn voices(self, voices: impl IntoIterator<Item = impl ToString>) -> Self {
+        Self {
+            voices: voices.into_iter().map(|voice| voice.to_string()).collect(),
+            ..self
+        }
+    }
+
+    /// Sets the speed relative to the normal speed of the voice
+    pub fn speed(self, speed: f64) -> Self {
+        Self {
+            speed: Some(speed),
+            ..self
+        }
+    }
+
+    /// Sets whether only the active cloze deletion of a cloze field is read
+    pub fn cloze_only(self, cloze_only: bool) -> Self {
+        Self { cloze_only, ..self }
+    }
+
+    /// Returns the tag reading `field`, to be placed in a template
+    pub fn tag(&self, field: &str) -> String {
+        let mut tag = format!("{{{{tts {}", self.lang);
+        if !self.voices.is_empty() {
+            let _ = write!(tag, " voices={}", self.voices.join(","));
+        }
+        if let Some(speed) = self.speed {
+            let _ = write!(tag, " speed={}", speed);
+        }
+        if self.cloze_only {
+            tag.push_str(":cloze-only");
+        }
+        let _ = write!(tag, ":{}}}}}", field);
+        tag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags() {
+        assert_eq!(Tts::new("ja_JP").tag("Reading"), "{{tts ja_JP:Reading}}");
+        assert_eq!(
+            Tts::new("en_US").cloze_only(true).tag("Text"),
+            "{{tts en_US:cloze-only:Text}}"
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use super::{Field, Model, Template};
 use crate::model::ModelType;
+use crate::util::id_for;
 
 /// Returns a basic Front/Back `Model`.
 ///
@@ -281,6 +282,460 @@ pub fn cloze_model() -> Model {
     )
 }
 
+/// Returns a `Model` for cards with clozes and an extra field shown on the back.
+///
+/// ```rust
+/// use genanki_rs::cloze_with_back_extra_model;
+/// let my_model = cloze_with_back_extra_model();
+/// ```
+///
+/// is equivalent to
+/// ```rust
+/// use genanki_rs::{Model, Field, Template, ModelType};
+/// let my_model = Model::new_with_options(
+///         1550428389,
+///         "Cloze with Back Extra (genanki)",
+///         vec![
+///             Field::new("Text").font("Arial"),
+///             Field::new("Back Extra").font("Arial"),
+///         ],
+///         vec![
+///             Template::new("Cloze")
+///                 .qfmt("{{cloze:Text}}")
+///                 .afmt("{{cloze:Text}}<br>\n{{Back Extra}}"),
+///         ],
+///         Some(
+///             ".card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n color: black;\n background-color: white;\n}\n\n \
+///              .cloze {\n font-weight: bold;\n color: blue;\n}\n.nightMode .cloze {\n color: lightblue;\n}",
+///         ),
+///         Some(ModelType::Cloze),
+///         None,
+///         None,
+///         None,
+///     );
+/// ```
+pub fn cloze_with_back_extra_model() -> Model {
+    Model::new_with_options(
+        1550428389,
+        "Cloze with Back Extra (genanki)",
+        vec![
+            Field::new("Text").font("Arial"),
+            Field::new("Back Extra").font("Arial"),
+        ],
+        vec![
+            Template::new("Cloze")
+                .qfmt("{{cloze:Text}}")
+                .afmt("{{cloze:Text}}<br>\n{{Back Extra}}"),
+        ],
+        Some(
+            ".card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n color: black;\n background-color: white;\n}\n\n \
+             .cloze {\n font-weight: bold;\n color: blue;\n}\n.nightMode .cloze {\n color: lightblue;\n}",
+        ),
+        Some(ModelType::Cloze),
+        None,
+        None,
+        None,
+    )
+}
+
+/// Returns a `Model` for cards where you type in the answer in both directions.
+///
+/// ```rust
+/// use genanki_rs::basic_type_in_the_answer_reversed_model;
+/// let my_model = basic_type_in_the_answer_reversed_model();
+/// ```
+///
+/// is equivalent to
+/// ```rust
+/// use genanki_rs::{Model, Field, Template};
+/// let my_model = Model::new_with_options(
+///         1171932207,
+///         "Basic (type in the answer) reversed (genanki)",
+///         vec![
+///             Field::new("Front").font("Arial"),
+///             Field::new("Back").font("Arial"),
+///         ],
+///         vec![
+///             Template::new("Card 1")
+///                 .qfmt("{{Front}}\n\n{{type:Back}}")
+///                 .afmt("{{Front}}\n\n<hr id=answer>\n\n{{type:Back}}"),
+///             Template::new("Card 2")
+///                 .qfmt("{{Back}}\n\n{{type:Front}}")
+///                 .afmt("{{Back}}\n\n<hr id=answer>\n\n{{type:Front}}"),
+///         ],
+///         Some(
+///             ".card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n color: black;\n background-color: white;\n}\n",
+///         ),
+///         None,
+///         None,
+///         None,
+///         None,
+///     );
+/// ```
+pub fn basic_type_in_the_answer_reversed_model() -> Model {
+    Model::new_with_options(
+        1171932207,
+        "Basic (type in the answer) reversed (genanki)",
+        vec![
+            Field::new("Front").font("Arial"),
+            Field::new("Back").font("Arial"),
+        ],
+        vec![
+            Template::new("Card 1")
+                .qfmt("{{Front}}\n\n{{type:Back}}")
+                .afmt("{{Front}}\n\n<hr id=answer>\n\n{{type:Back}}"),
+            Template::new("Card 2")
+                .qfmt("{{Back}}\n\n{{type:Front}}")
+                .afmt("{{Back}}\n\n<hr id=answer>\n\n{{type:Front}}"),
+        ],
+        Some(
+            ".card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n color: black;\n background-color: white;\n}\n",
+        ),
+        None,
+        None,
+        None,
+        None,
+    )
+}
+
+const IMAGE_OCCLUSION_FRONT: &str = r#"{{#Header}}<div>{{Header}}</div>{{/Header}}
+<div style="display: none">{{cloze:Occlusion}}</div>
+<div id="err"></div>
+<div id="image-occlusion-container">
+    {{Image}}
+    <canvas id="image-occlusion-canvas"></canvas>
+</div>
+<script>
+try {
+    anki.imageOcclusion.setup();
+} catch (exc) {
+    document.getElementById("err").innerHTML = `Error loading image occlusion. Is your Anki version up to date?<br><br>${exc}`;
+}
+</script>
+"#;
+
+const IMAGE_OCCLUSION_BACK: &str = r#"
+<div><button id="toggle">Toggle Masks</button></div>
+{{#Back Extra}}<div>{{Back Extra}}</div>{{/Back Extra}}
+"#;
+
+/// Returns a `Model` for image occlusion, matching the native Image Occlusion note type of
+/// Anki 23.10 and later.
+///
+/// ```rust
+/// use genanki_rs::image_occlusion_model;
+/// let my_model = image_occlusion_model();
+/// ```
+///
+/// The model is a cloze model with the fields `Occlusion`, `Image`, `Header`, `Back Extra` and
+/// `Comments`. `Occlusion` holds the shapes as `{{c1::image-occlusion:rect:...}}` clozes, which
+/// are most easily written with [`ImageOcclusion`](crate::ImageOcclusion), and `Image` holds an
+/// `<img>` tag of the occluded image. The masks are drawn by the image occlusion script bundled
+/// with Anki, so older Anki versions only show the image.
+pub fn image_occlusion_model() -> Model {
+    Model::new_with_options(
+        1686291717,
+        "Image Occlusion (genanki)",
+        vec![
+            Field::new("Occlusion"),
+            Field::new("Image"),
+            Field::new("Header"),
+            Field::new("Back Extra"),
+            Field::new("Comments"),
+        ],
+        vec![
+            Template::new("Image Occlusion")
+                .qfmt(IMAGE_OCCLUSION_FRONT)
+                .afmt(&format!(
+                    "{}{}",
+                    IMAGE_OCCLUSION_FRONT, IMAGE_OCCLUSION_BACK
+                )),
+        ],
+        Some(
+            "#image-occlusion-canvas {\n --inactive-shape-color: #ffeba2;\n --active-shape-color: #ff8e8e;\n \
+             --inactive-shape-border: 1px #212121;\n --active-shape-border: 1px #212121;\n \
+             --highlight-shape-color: #ff8e8e00;\n --highlight-shape-border: 1px #ff8e8e;\n}\n\n\
+             .card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n color: black;\n background-color: white;\n}\n",
+        ),
+        Some(ModelType::Cloze),
+        None,
+        None,
+        None,
+    )
+}
+
+/// Returns the `Model` built by [`VocabularyModel`] with its default options.
+///
+/// ```rust
+/// use genanki_rs::vocabulary_model;
+/// let my_model = vocabulary_model();
+/// ```
+///
+/// is equivalent to
+/// ```rust
+/// use genanki_rs::VocabularyModel;
+/// let my_model = VocabularyModel::new().build();
+/// ```
+pub fn vocabulary_model() -> Model {
+    VocabularyModel::new().build()
+}
+
+const VOCABULARY_CSS: &str = ".card {
+ font-family: arial;
+ font-size: 20px;
+ text-align: center;
+ color: black;
+ background-color: white;
+}
+.word {
+ font-size: 32px;
+}
+.pos, .example {
+ font-size: 16px;
+ color: #555;
+}
+.example {
+ font-style: italic;
+ margin-top: 12px;
+}
+.image img {
+ max-width: 100%;
+ max-height: 240px;
+}
+.gender-m, .gender-der {
+ color: #1565c0;
+}
+.gender-f, .gender-die {
+ color: #c62828;
+}
+.gender-n, .gender-das {
+ color: #2e7d32;
+}
+.nightMode.card, .night_mode .card {
+ color: #e0e0e0;
+ background-color: #202124;
+}
+.nightMode .pos, .nightMode .example {
+ color: #aaa;
+}
+.nightMode .gender-m, .nightMode .gender-der {
+ color: #90caf9;
+}
+.nightMode .gender-f, .nightMode .gender-die {
+ color: #ef9a9a;
+}
+.nightMode .gender-n, .nightMode .gender-das {
+ color: #a5d6a7;
+}
+";
+
+/// Builder for a `Model` of vocabulary notes, with a card asking for the translation of a word
+/// and optionally a reversed card asking for the word.
+///
+/// The model always has the fields `Word` and `Translation`. `Example`, `Audio`, `Image`,
+/// `Gender` and `PartOfSpeech` can be left out. `Audio` and `Image` hold `[sound:...]` and
+/// `<img>` references, the `Gender` (e.g. `m`, `f`, `n` or `der`, `die`, `das`) colors the
+/// word. The styling supports Anki's night mode.
+///
+/// ```rust
+/// use genanki_rs::{Note, VocabularyModel};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let model = VocabularyModel::new()
+///         .image(false)
+///         .part_of_speech(false)
+///         .build();
+///     let note = Note::new(
+///         model,
+///         vec!["Hund", "dog", "Der Hund bellt.", "[sound:hund.mp3]", "der"],
+///     )?;
+///     Ok(())
+/// }
+/// ```
+///
+/// Unless set, the model id is derived from the name and the chosen fields, so differently
+/// configured models do not collide in a collection.
+///
+/// The builder has the following default values:
+/// * `id` - derived from `name` and the fields
+/// * `name` - `"Vocabulary (genanki)"`
+/// * `example` - `true`
+/// * `audio` - `true`
+/// * `image` - `true`
+/// * `gender` - `true`
+/// * `part_of_speech` - `true`
+/// * `reversed` - `true`
+#[derive(Clone, Debug)]
+pub struct VocabularyModel {
+    id: Option<i64>,
+    name: String,
+    example: bool,
+    audio: bool,
+    image: bool,
+    gender: bool,
+    part_of_speech: bool,
+    reversed: bool,
+}
+
+impl Default for VocabularyModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VocabularyModel {
+    /// Creates a new builder with the default options
+    pub fn new() -> Self {
+        Self {
+            id: None,
+            name: "Vocabulary (genanki)".to_string(),
+            example: true,
+            audio: true,
+            image: true,
+            gender: true,
+            part_of_speech: true,
+            reversed: true,
+        }
+    }
+
+    /// Sets the model id
+    pub fn id(self, id: i64) -> Self {
+        Self {
+            id: Some(id),
+            ..self
+        }
+    }
+
+    /// Sets the model name
+    pub fn name(self, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets whether the model has an `Example` field for an example sentence
+    pub fn example(self, example: bool) -> Self {
+        Self { example, ..self }
+    }
+
+    /// Sets whether the model has an `Audio` field for the pronunciation
+    pub fn audio(self, audio: bool) -> Self {
+        Self { audio, ..self }
+    }
+
+    /// Sets whether the model has an `Image` field
+    pub fn image(self, image: bool) -> Self {
+        Self { image, ..self }
+    }
+
+    /// Sets whether the model has a `Gender` field
+    pub fn gender(self, gender: bool) -> Self {
+        Self { gender, ..self }
+    }
+
+    /// Sets whether the model has a `PartOfSpeech` field
+    pub fn part_of_speech(self, part_of_speech: bool) -> Self {
+        Self {
+            part_of_speech,
+            ..self
+        }
+    }
+
+    /// Sets whether a second card asks for the word given its translation
+    pub fn reversed(self, reversed: bool) -> Self {
+        Self { reversed, ..self }
+    }
+
+    /// Returns the names of the fields in the order `Note::new` expects them
+    pub fn field_names(&self) -> Vec<&'static str> {
+        let optional = [
+            (self.example, "Example"),
+            (self.audio, "Audio"),
+            (self.image, "Image"),
+            (self.gender, "Gender"),
+            (self.part_of_speech, "PartOfSpeech"),
+        ];
+        let mut names = vec!["Word", "Translation"];
+        names.extend(
+            optional
+                .into_iter()
+                .filter(|(enabled, _)| *enabled)
+                .map(|(_, name)| name),
+        );
+        names
+    }
+
+    /// Creates the `Model`
+    pub fn build(&self) -> Model {
+        let section = |enabled: bool, name: &str, content: &str| {
+            if enabled {
+                format!("{{{{#{name}}}}}{content}{{{{/{name}}}}}\n")
+            } else {
+                String::new()
+            }
+        };
+        let word_class = if self.gender {
+            "word gender-{{Gender}}"
+        } else {
+            "word"
+        };
+        let word = format!(
+            "<div class=\"{}\">{{{{Word}}}}</div>\n{}",
+            word_class,
+            section(
+                self.part_of_speech,
+                "PartOfSpeech",
+                "<div class=\"pos\">{{PartOfSpeech}}</div>"
+            )
+        );
+        let translation = "<div class=\"translation\">{{Translation}}</div>\n";
+        let audio = section(self.audio, "Audio", "{{Audio}}");
+        let image = section(self.image, "Image", "<div class=\"image\">{{Image}}</div>");
+        let example = section(
+            self.example,
+            "Example",
+            "<div class=\"example\">{{Example}}</div>",
+        );
+        let answer = "{{FrontSide}}\n\n<hr id=answer>\n\n";
+
+        let mut templates = vec![
+            Template::new("Recognition")
+                .qfmt(&format!("{}{}", word, audio))
+                .afmt(&format!("{}{}{}{}", answer, translation, image, example)),
+        ];
+        if self.reversed {
+            templates.push(
+                Template::new("Production")
+                    .qfmt(&format!("{}{}", translation, image))
+                    .afmt(&format!("{}{}{}{}", answer, word, audio, example)),
+            );
+        }
+
+        let field_names = self.field_names();
+        let id = self.id.unwrap_or_else(|| {
+            id_for(&format!(
+                "{}\x1f{}\x1f{}",
+                self.name,
+                field_names.join("\x1f"),
+                self.reversed
+            ))
+        });
+        Model::new_with_options(
+            id,
+            &self.name,
+            field_names.into_iter().map(Field::new).collect(),
+            templates,
+            Some(VOCABULARY_CSS),
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::{Deck, Note};
@@ -288,6 +743,44 @@ mod tests {
     use sqlx::{Pool, Sqlite};
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn vocabulary_model_fields_and_cards() {
+        let full = VocabularyModel::new();
+        assert_eq!(
+            full.field_names(),
+            [
+                "Word",
+                "Translation",
+                "Example",
+                "Audio",
+                "Image",
+                "Gender",
+                "PartOfSpeech"
+            ]
+        );
+        let note = Note::new(full.build(), vec!["Hund", "dog", "", "", "", "der", ""]).unwrap();
+        assert_eq!(note.cards().len(), 2);
+
+        let minimal = VocabularyModel::new()
+            .example(false)
+            .audio(false)
+            .image(false)
+            .gender(false)
+            .part_of_speech(false)
+            .reversed(false);
+        assert_eq!(minimal.field_names(), ["Word", "Translation"]);
+        let model = minimal.build();
+        assert_ne!(model.id, full.build().id);
+        assert!(
+            model
+                .templates()
+                .iter()
+                .all(|template| !template.qfmt.contains("Gender"))
+        );
+        let note = Note::new(model, vec!["Katze", "cat"]).unwrap();
+        assert_eq!(note.cards().len(), 1);
+    }
+
     #[sqlx::test(fixtures("anki"))]
     async fn builtin_models(pool: Pool<Sqlite>) {
         let mut my_deck = Deck::new(1598559905, "Country Capitals", "");
@@ -319,6 +812,40 @@ mod tests {
             )
             .unwrap(),
         );
+        my_deck.add_note(
+            Note::new(
+                image_occlusion_model(),
+                vec![
+                    "{{c1::image-occlusion:rect:left=0.1:top=0.1:width=0.2:height=0.2:oi=1}}",
+                    "<img src=\"europe.png\">",
+                    "Europe",
+                    "",
+                    "",
+                ],
+            )
+            .unwrap(),
+        );
+        my_deck.add_note(
+            Note::new(
+                cloze_with_back_extra_model(),
+                vec!["{{c1::Berlin}} is the capital of Germany", "since 1990"],
+            )
+            .unwrap(),
+        );
+        my_deck.add_note(
+            Note::new(
+                basic_type_in_the_answer_reversed_model(),
+                vec!["Japan", "Tokyo"],
+            )
+            .unwrap(),
+        );
+        my_deck.add_note(
+            Note::new(
+                VocabularyModel::new().build(),
+                vec!["Hund", "dog", "", "", "", "der", "noun"],
+            )
+            .unwrap(),
+        );
 
         let out_file = NamedTempFile::new().unwrap().into_temp_path();
         let mut conn = pool.acquire().await.unwrap();
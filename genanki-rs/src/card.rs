@@ -1,15 +1,143 @@
+use crate::scheduling::{MemoryState, Review, Scheduling};
 use anyhow::Result;
 use sqlx::SqliteConnection;
 
+/// Colour flag of a card, as shown in the browser and the reviewer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flag {
+    Red = 1,
+    Orange = 2,
+    Green = 3,
+    Blue = 4,
+    Pink = 5,
+    Turquoise = 6,
+    Purple = 7,
+}
+
+/// State a card is created in, set per card with `Note::card_state` or `Note::cloze_state`.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_and_reversed_card_model, CardState, Flag, Note};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let note = Note::new(basic_and_reversed_card_model(), vec!["Hund", "dog"])?
+///         .card_state(0, CardState::new().flag(Flag::Blue).position(1))?
+///         .card_state(1, CardState::new().suspended(true))?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The builder has the following default values:
+/// * `suspended` - `false`
+/// * `buried` - `false`
+/// * `flag` - `None`
+/// * `position` - `0`
+/// * `scheduling` - `Scheduling::New`
+/// * `reps` - `0`
+/// * `lapses` - `0`
+/// * `reviews` - `[]`
+/// * `memory_state` - `None`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CardState {
+    suspended: bool,
+    buried: bool,
+    flag: Option<Flag>,
+    position: i64,
+    scheduling: Scheduling,
+    reps: i64,
+    lapses: i64,
+    reviews: Vec<Review>,
+    memory_state: Option<MemoryState>,
+}
+
+impl CardState {
+    /// Creates a new state with the default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the card is suspended, which takes precedence over `buried`
+    pub fn suspended(self, suspended: bool) -> Self {
+        Self { suspended, ..self }
+    }
+
+    /// Sets whether the card is buried until the next day
+    pub fn buried(self, buried: bool) -> Self {
+        Self { buried, ..self }
+    }
+
+    /// Sets the colour flag
+    pub fn flag(self, flag: Flag) -> Self {
+        Self {
+            flag: Some(flag),
+            ..self
+        }
+    }
+
+    /// Sets the position of the card in the new card queue, lower positions are shown first
+    pub fn position(self, position: i64) -> Self {
+        Self { position, ..self }
+    }
+
+    /// Sets the scheduling state, see [`Scheduling`]
+    pub fn scheduling(self, scheduling: Scheduling) -> Self {
+        Self { scheduling, ..self }
+    }
+
+    /// Sets how often the card has been answered
+    pub fn reps(self, reps: i64) -> Self {
+        Self { reps, ..self }
+    }
+
+    /// Sets how often the card has been forgotten after graduating
+    pub fn lapses(self, lapses: i64) -> Self {
+        Self { lapses, ..self }
+    }
+
+    /// Adds an entry to the review history of the card
+    pub fn review(mut self, review: Review) -> Self {
+        self.reviews.push(review);
+        self
+    }
+
+    /// Sets the FSRS memory state, see [`MemoryState`]
+    pub fn memory_state(self, memory_state: MemoryState) -> Self {
+        Self {
+            memory_state: Some(memory_state),
+            ..self
+        }
+    }
+
+    fn queue(&self) -> i64 {
+        if self.suspended {
+            -1
+        } else if self.buried {
+            -3
+        } else {
+            self.scheduling.queue()
+        }
+    }
+
+    fn flags(&self) -> i64 {
+        self.flag.map_or(0, |flag| flag as i64)
+    }
+}
+
 #[derive(Clone)]
 pub struct Card {
     pub ord: i64,
-    pub suspend: bool,
+    pub state: CardState,
 }
 
 impl Card {
     pub fn new(ord: i64, suspend: bool) -> Self {
-        Self { ord, suspend }
+        Self {
+            ord,
+            state: CardState::new().suspended(suspend),
+        }
     }
     #[allow(dead_code)]
     pub fn ord(&self) -> i64 {
@@ -22,11 +150,31 @@ impl Card {
         deck_id: i64,
         note_id: usize,
     ) -> Result<()> {
-        let queue = if self.suspend { -1 } else { 0 };
+        let state = &self.state;
+        let collection_created = match state.scheduling {
+            Scheduling::Review { .. } => {
+                sqlx::query_scalar!("SELECT crt FROM col")
+                    .fetch_one(&mut *conn)
+                    .await?
+            }
+            _ => 0,
+        };
+        let card_type = state.scheduling.card_type();
+        let queue = state.queue();
+        let due = state.scheduling.due(state.position, collection_created);
+        let interval = state.scheduling.interval();
+        let factor = state.scheduling.factor();
+        let left = state.scheduling.left();
+        let flags = state.flags();
+        let data = state
+            .memory_state
+            .as_ref()
+            .map(MemoryState::card_data)
+            .unwrap_or_default();
         let note_id = note_id as i64;
         let timestamp = timestamp as i64;
 
-        sqlx::query!(
+        let card_id = sqlx::query!(
             r#"
                 INSERT INTO cards (nid, did, ord, mod, usn, type, queue, due, ivl, factor, reps, lapses, left, odue, odid, flags, data)
                 VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
@@ -36,19 +184,48 @@ impl Card {
             self.ord,         // ord
             timestamp,        // mod
             -1,               // usn
-            0,                // type (=0 for non-Cloze)
+            card_type,        // type
             queue,            // queue
-            0,                // due
-            0,                // ivl
-            0,                // factor
-            0,                // reps
-            0,                // lapses
-            0,                // left
+            due,              // due
+            interval,         // ivl
+            factor,           // factor
+            state.reps,       // reps
+            state.lapses,     // lapses
+            left,             // left
             0,                // odue
             0,                // odid
-            0,                // flags
-            "",               // data
-        ).execute(conn).await?;
+            flags,            // flags
+            data,             // data
+        ).execute(&mut *conn).await?.last_insert_rowid();
+
+        for review in &state.reviews {
+            let (id, ease, ivl, last_ivl, factor, time, kind) = (
+                review.time(),
+                review.rating(),
+                review.get_interval(),
+                review.get_last_interval(),
+                review.factor(),
+                review.get_duration(),
+                review.get_kind(),
+            );
+            sqlx::query!(
+                r#"
+                    INSERT INTO revlog (id, cid, usn, ease, ivl, lastivl, factor, time, type)
+                    VALUES(?,?,?,?,?,?,?,?,?)
+                "#,
+                id,       // id
+                card_id,  // cid
+                -1,       // usn
+                ease,     // ease
+                ivl,      // ivl
+                last_ivl, // lastivl
+                factor,   // factor
+                time,     // time
+                kind,     // type
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
 
         Ok(())
     }
@@ -0,0 +1,460 @@
+use crate::Error;
+use crate::conflict::ConflictPolicy;
+use crate::deck::Deck;
+use crate::error::json_error;
+use crate::package::Package;
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Schema version of the collections written by this crate
+const SCHEMA_VERSION: i64 = 11;
+
+/// Handle to an Anki collection file, e.g. the `collection.anki2` of a profile
+///
+/// Notes are upserted by GUID: by default a note whose GUID is already in the collection
+/// replaces the fields and tags of the existing note, keeping its cards and their scheduling. An
+/// existing note of another model is an error, see [`ConflictPolicy`]. Models and decks are
+/// merged into the existing ones, and everything written is marked as changed for the next sync.
+///
+/// The fields or templates of a model already in the collection can only change if all its notes
+/// in the collection are written again with the new fields, e.g. converted with a
+/// [`ModelMigration`](crate::ModelMigration). Otherwise writing fails, as Anki cannot open notes
+/// whose field count does not match their model.
+///
+/// Only collections with schema version 11 are supported. Recent Anki versions upgrade
+/// collections to a newer schema, which has to be downgraded first, e.g. with
+/// `col.close(downgrade=True)` of the Python library. Anki must not have the collection open
+/// while it is written.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{Collection, Deck, Note, basic_model};
+/// use anyhow::Result;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<()> {
+///     let dir = tempfile::tempdir()?;
+///     let path = dir.path().join("collection.anki2");
+///     Collection::create(&path).await?.close().await;
+///
+///     let mut deck = Deck::new(2059400110, "Vocabulary", "");
+///     deck.add_note(Note::new(basic_model(), vec!["Hund", "dog"])?.guid("hund"));
+///     let mut collection = Collection::open(&path).await?;
+///     collection.write_decks(vec![deck], None).await?;
+///     collection.close().await;
+///     Ok(())
+/// }
+/// ```
+///
+/// The builder has the following default values:
+/// * `conflict_policy` - `ConflictPolicy::Overwrite`
+pub struct Collection {
+    pool: SqlitePool,
+    conflict_policy: ConflictPolicy,
+}
+
+impl Collection {
+    /// Opens the existing collection at `path`
+    ///
+    /// Returns `Err` if the file does not exist or is not a collection with schema version 11
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let options = SqliteConnectOptions::new().filename(path);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        let version = sqlx::query_scalar!(
+            r#"
+                SELECT ver FROM col
+        "#
+        )
+        .fetch_one(&pool)
+        .await?;
+        if version != SCHEMA_VERSION {
+            pool.close().await;
+            return Err(anyhow!(Error::UnsupportedCollection(version)));
+        }
+        Ok(Self {
+            pool,
+            conflict_policy: ConflictPolicy::default(),
+        })
+    }
+
+    /// Creates an empty collection at `path`, containing only the default deck
+    pub async fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        init_collection(&mut *pool.acquire().await?).await?;
+        Ok(Self {
+            pool,
+            conflict_policy: ConflictPolicy::default(),
+        })
+    }
+
+    /// Sets what happens to notes whose GUID is already in the collection
+    pub fn conflict_policy(self, conflict_policy: ConflictPolicy) -> Self {
+        Self {
+            conflict_policy,
+            ..self
+        }
+    }
+
+    /// Writes the notes, models and decks of `decks` into the collection
+    ///
+    /// Everything is written in a single transaction, so the collection is left unchanged if
+    /// writing fails. If `timestamp` is `None` the current time is used.
+    ///
+    /// Returns `Err` with `Error::OutdatedNotes` if a model in the collection changed its fields
+    /// or templates while some of its notes were not rewritten to match
+    pub async fn write_decks(&mut self, decks: Vec<Deck>, timestamp: Option<f64>) -> Result<()> {
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64(),
+        };
+        let mut tx = self.pool.begin().await?;
+        let models_before = models(&mut tx).await?;
+        Package::new(decks, vec![])?
+            .conflict_policy(self.conflict_policy)
+            .write_maybe_timestamp(Some(timestamp), &mut tx)
+            .await?;
+        let models_after = models(&mut tx).await?;
+
+        // Anki needs a full sync if fields or templates of a model already in the collection
+        // change, which it requests when the schema modification time is newer than the last sync
+        let mut schema_changed = false;
+        for (id, before) in &models_before {
+            if let Some(after) = models_after.get(id)
+                && !same_schema(before, after)
+            {
+                check_notes_match_model(&mut tx, *id, after).await?;
+                schema_changed = true;
+            }
+        }
+        let modified = (timestamp * 1000.0) as i64;
+        sqlx::query!(
+            r#"
+                UPDATE col SET mod = ?
+        "#,
+            modified
+        )
+        .execute(&mut *tx)
+        .await?;
+        if schema_changed {
+            sqlx::query!(
+                r#"
+                    UPDATE col SET scm = ?
+            "#,
+                modified
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Closes the collection, waiting for pending writes to finish
+    pub async fn close(self) {
+        self.pool.close().await;
+    }
+}
+
+/// Creates the tables of a collection and fills `col` with the defaults
+pub(super) async fn init_collection(conn: &mut SqliteConnection) -> Result<()> {
+    sqlx::migrate!().run(&mut *conn).await?;
+    sqlx::query_file!("fixtures/anki.sql")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+async fn models(conn: &mut SqliteConnection) -> Result<HashMap<i64, Value>> {
+    let models = sqlx::query_scalar!(
+        r#"
+            SELECT models FROM col
+    "#
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(serde_json::from_str(&models).map_err(json_error)?)
+}
+
+/// Fails if notes of the model with `id` have another number of fields than `model` or cards
+/// of templates it no longer has
+async fn check_notes_match_model(
+    conn: &mut SqliteConnection,
+    id: i64,
+    model: &Value,
+) -> Result<()> {
+    let count = |key: &str| model[key].as_array().map_or(0, Vec::len);
+    let (field_count, template_count) = (count("flds"), count("tmpls") as i64);
+    let notes = sqlx::query!(
+        r#"
+            SELECT id, flds FROM notes WHERE mid = ?
+    "#,
+        id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut outdated = 0;
+    for note in notes {
+        // Cloze models have a single template, their cards are numbered by cloze deletion
+        let cards_outdated = model["type"] == 0
+            && sqlx::query_scalar!(
+                r#"
+                    SELECT COUNT(*) FROM cards WHERE nid = ? AND ord >= ?
+            "#,
+                note.id,
+                template_count
+            )
+            .fetch_one(&mut *conn)
+            .await?
+                > 0;
+        if note.flds.split('\x1f').count() != field_count || cards_outdated {
+            outdated += 1;
+        }
+    }
+    if outdated > 0 {
+        return Err(anyhow!(Error::OutdatedNotes {
+            model: model["name"].as_str().unwrap_or_default().to_string(),
+            notes: outdated,
+        }));
+    }
+    Ok(())
+}
+
+/// Returns whether two versions of a model have the same fields, templates and type
+fn same_schema(before: &Value, after: &Value) -> bool {
+    let names = |model: &Value, key: &str| -> Vec<Value> {
+        model[key]
+            .as_array()
+            .map(|entries| entries.iter().map(|entry| entry["name"].clone()).collect())
+            .unwrap_or_default()
+    };
+    before["type"] == after["type"]
+        && names(before, "flds") == names(after, "flds")
+        && names(before, "tmpls") == names(after, "tmpls")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Field, Model, Note, Template, basic_model};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    async fn new_collection() -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("collection.anki2");
+        Collection::create(&path).await.unwrap().close().await;
+        (dir, path)
+    }
+
+    fn deck(notes: Vec<Note>) -> Deck {
+        let mut deck = Deck::new(2059400110, "Vocabulary", "");
+        for note in notes {
+            deck.add_note(note);
+        }
+        deck
+    }
+
+    #[tokio::test]
+    async fn notes_are_upserted_by_guid() {
+        let (_dir, path) = new_collection().await;
+        let mut collection = Collection::open(&path).await.unwrap();
+        let hund = Note::new(basic_model(), vec!["Hund", "dog"])
+            .unwrap()
+            .guid("hund");
+        let katze = Note::new(basic_model(), vec!["Katze", "cat"])
+            .unwrap()
+            .guid("katze");
+        collection
+            .write_decks(vec![deck(vec![hund, katze])], Some(1425279151.0))
+            .await
+            .unwrap();
+        sqlx::query!("UPDATE cards SET reps = 7, usn = 3")
+            .execute(&collection.pool)
+            .await
+            .unwrap();
+
+        let hund = Note::new(basic_model(), vec!["Hund", "dog, hound"])
+            .unwrap()
+            .guid("hund")
+            .tags(["animal"]);
+        collection
+            .write_decks(vec![deck(vec![hund])], Some(1425280000.0))
+            .await
+            .unwrap();
+
+        let notes = sqlx::query!("SELECT guid, flds, tags, mod FROM notes ORDER BY id")
+            .fetch_all(&collection.pool)
+            .await
+            .unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].guid, "hund");
+        assert_eq!(notes[0].flds, "Hund\x1fdog, hound");
+        assert_eq!(notes[0].tags, " animal ");
+        assert_eq!(notes[0].r#mod, 1425280000);
+        assert_eq!(notes[1].flds, "Katze\x1fcat");
+
+        let reps = sqlx::query_scalar!("SELECT reps FROM cards ORDER BY id")
+            .fetch_all(&collection.pool)
+            .await
+            .unwrap();
+        assert_eq!(reps, [7, 7]);
+
+        let col = sqlx::query!("SELECT mod, scm FROM col")
+            .fetch_one(&collection.pool)
+            .await
+            .unwrap();
+        assert_eq!(col.r#mod, 1425280000000);
+        assert_ne!(col.scm, 1425280000000);
+        collection.close().await;
+    }
+
+    fn basic_with_example() -> Model {
+        Model::new(
+            basic_model().id,
+            "Basic",
+            vec![
+                Field::new("Front"),
+                Field::new("Back"),
+                Field::new("Example"),
+            ],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Front}}")
+                    .afmt("{{FrontSide}}<hr id=answer>{{Back}}"),
+            ],
+        )
+    }
+
+    #[tokio::test]
+    async fn existing_decks_and_models_are_merged() {
+        let (_dir, path) = new_collection().await;
+        let mut collection = Collection::open(&path).await.unwrap();
+        let note = Note::new(basic_model(), vec!["Hund", "dog"])
+            .unwrap()
+            .guid("hund");
+        collection
+            .write_decks(vec![deck(vec![note])], Some(1425279151.0))
+            .await
+            .unwrap();
+
+        // Keys written by newer Anki versions are kept
+        let decks = sqlx::query_scalar!("SELECT decks FROM col")
+            .fetch_one(&collection.pool)
+            .await
+            .unwrap();
+        let mut decks: Value = serde_json::from_str(&decks).unwrap();
+        decks["2059400110"]["browserCollapsed"] = true.into();
+        let decks = decks.to_string();
+        sqlx::query!("UPDATE col SET decks = ?", decks)
+            .execute(&collection.pool)
+            .await
+            .unwrap();
+
+        // The existing note is written again with the new fields of its model
+        let model = basic_with_example();
+        let hund = Note::new(model.clone(), vec!["Hund", "dog", "Der Hund bellt."])
+            .unwrap()
+            .guid("hund");
+        let katze = Note::new(model, vec!["Katze", "cat", "Die Katze schläft."]).unwrap();
+        let mut renamed = Deck::new(2059400110, "Wortschatz", "");
+        renamed.add_note(hund);
+        renamed.add_note(katze);
+        collection
+            .write_decks(vec![renamed], Some(1425280000.0))
+            .await
+            .unwrap();
+
+        let col = sqlx::query!("SELECT decks, models, scm FROM col")
+            .fetch_one(&collection.pool)
+            .await
+            .unwrap();
+        let decks: Value = serde_json::from_str(&col.decks).unwrap();
+        assert_eq!(decks["2059400110"]["name"], "Wortschatz");
+        assert_eq!(decks["2059400110"]["browserCollapsed"], true);
+        assert_eq!(decks["1"]["name"], "Default");
+        let models: Value = serde_json::from_str(&col.models).unwrap();
+        let model = &models[basic_model().id.to_string()];
+        assert_eq!(model["flds"].as_array().unwrap().len(), 3);
+        assert_eq!(col.scm, 1425280000000);
+        let fields = sqlx::query_scalar!("SELECT flds FROM notes ORDER BY id")
+            .fetch_all(&collection.pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            fields,
+            [
+                "Hund\x1fdog\x1fDer Hund bellt.",
+                "Katze\x1fcat\x1fDie Katze schläft."
+            ]
+        );
+        collection.close().await;
+    }
+
+    #[tokio::test]
+    async fn changed_models_with_outdated_notes_are_rejected() {
+        let (_dir, path) = new_collection().await;
+        let mut collection = Collection::open(&path).await.unwrap();
+        let note = Note::new(basic_model(), vec!["Hund", "dog"]).unwrap();
+        collection
+            .write_decks(vec![deck(vec![note])], Some(1425279151.0))
+            .await
+            .unwrap();
+
+        let note = Note::new(basic_with_example(), vec!["Katze", "cat", ""]).unwrap();
+        let error = collection
+            .write_decks(vec![deck(vec![note])], Some(1425280000.0))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::OutdatedNotes { notes: 1, .. })
+        ));
+
+        // Nothing of the failed write is kept
+        let models = sqlx::query_scalar!("SELECT models FROM col")
+            .fetch_one(&collection.pool)
+            .await
+            .unwrap();
+        let models: Value = serde_json::from_str(&models).unwrap();
+        let model = &models[basic_model().id.to_string()];
+        assert_eq!(model["flds"].as_array().unwrap().len(), 2);
+        let notes = sqlx::query_scalar!("SELECT COUNT(*) FROM notes")
+            .fetch_one(&collection.pool)
+            .await
+            .unwrap();
+        assert_eq!(notes, 1);
+        collection.close().await;
+    }
+
+    #[tokio::test]
+    async fn other_schema_versions_are_rejected() {
+        let (_dir, path) = new_collection().await;
+        let collection = Collection::open(&path).await.unwrap();
+        sqlx::query!("UPDATE col SET ver = 18")
+            .execute(&collection.pool)
+            .await
+            .unwrap();
+        collection.close().await;
+        assert!(Collection::open(&path).await.is_err());
+        assert!(
+            Collection::open(path.with_extension("missing"))
+                .await
+                .is_err()
+        );
+    }
+}
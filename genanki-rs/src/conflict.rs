@@ -0,0 +1,162 @@
+/// What happens when a note is written while a note with the same GUID already exists, either
+/// written earlier from the same package or already in the target collection.
+///
+/// The cards of the existing note keep their scheduling with every policy, only cards of new
+/// templates or cloze deletions are added. When the note is replaced, its cards move to the deck
+/// it is written to, so a note added to several decks ends up in the last one. A note is never
+/// moved to another model, so unless the note is skipped, an existing note of another model fails
+/// with `Error::DuplicateGuid`.
+///
+/// The auto-generated GUID is a hash of the model id and the fields, so notes of the same model
+/// with identical fields are the same note.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keeps the existing note unchanged
+    Skip,
+    /// Replaces the fields and tags of the existing note
+    #[default]
+    Overwrite,
+    /// Replaces the fields of the existing note and keeps its tags in addition to the tags of the
+    /// new note
+    MergeTags,
+    /// Fails with `Error::DuplicateGuid`
+    Error,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Deck, Note, Package, basic_and_reversed_card_model, basic_model};
+    use sqlx::{Pool, Sqlite};
+
+    fn deck(id: i64, fields: Vec<&str>, tag: &str) -> Deck {
+        let mut deck = Deck::new(id, "Shared", "");
+        deck.add_note(
+            Note::new(basic_model(), fields)
+                .unwrap()
+                .guid("hund")
+                .tags([tag]),
+        );
+        deck
+    }
+
+    async fn write_twice(pool: &Pool<Sqlite>, policy: ConflictPolicy) -> Result<(), String> {
+        let mut package = Package::new(
+            vec![
+                deck(2059400110, vec!["Hund", "dog"], "A1"),
+                deck(2059400111, vec!["Hund", "hound"], "animal"),
+            ],
+            vec![],
+        )
+        .unwrap()
+        .conflict_policy(policy);
+        let mut conn = pool.acquire().await.unwrap();
+        package
+            .write_maybe_timestamp(Some(1425279151.0), &mut conn)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn notes(pool: &Pool<Sqlite>) -> Vec<(String, String)> {
+        sqlx::query!("SELECT flds, tags FROM notes")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|note| (note.flds, note.tags))
+            .collect()
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn skip_keeps_the_first_note(pool: Pool<Sqlite>) {
+        write_twice(&pool, ConflictPolicy::Skip).await.unwrap();
+        assert_eq!(
+            notes(&pool).await,
+            [("Hund\x1fdog".to_string(), " A1 ".to_string())]
+        );
+        let decks = sqlx::query_scalar!("SELECT did FROM cards")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(decks, [2059400110]);
+        let tags = sqlx::query_scalar!("SELECT tags FROM col")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(tags, r#"{"A1":-1}"#);
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn overwrite_replaces_fields_and_tags(pool: Pool<Sqlite>) {
+        write_twice(&pool, ConflictPolicy::Overwrite).await.unwrap();
+        assert_eq!(
+            notes(&pool).await,
+            [("Hund\x1fhound".to_string(), " animal ".to_string())]
+        );
+        let decks = sqlx::query_scalar!("SELECT did FROM cards")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(decks, [2059400111]);
+        let csum = sqlx::query_scalar!("SELECT csum FROM notes")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(csum, 243334449);
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn merge_tags_keeps_existing_tags(pool: Pool<Sqlite>) {
+        write_twice(&pool, ConflictPolicy::MergeTags).await.unwrap();
+        assert_eq!(
+            notes(&pool).await,
+            [("Hund\x1fhound".to_string(), " A1 animal ".to_string())]
+        );
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn error_rejects_duplicate_guids(pool: Pool<Sqlite>) {
+        let error = write_twice(&pool, ConflictPolicy::Error).await.unwrap_err();
+        assert!(error.contains("\"hund\""), "{}", error);
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn same_fields_of_different_models_are_separate_notes(pool: Pool<Sqlite>) {
+        let mut deck = Deck::new(2059400110, "Shared", "");
+        deck.add_note(Note::new(basic_model(), vec!["Hund", "dog"]).unwrap());
+        deck.add_note(Note::new(basic_and_reversed_card_model(), vec!["Hund", "dog"]).unwrap());
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        package
+            .write_maybe_timestamp(Some(1425279151.0), &mut conn)
+            .await
+            .unwrap();
+        let cards = sqlx::query_scalar!("SELECT COUNT(*) FROM cards")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(cards, 3);
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn same_guid_of_different_models_is_rejected(pool: Pool<Sqlite>) {
+        let mut deck = Deck::new(2059400110, "Shared", "");
+        deck.add_note(
+            Note::new(basic_model(), vec!["Hund", "dog"])
+                .unwrap()
+                .guid("hund"),
+        );
+        deck.add_note(
+            Note::new(basic_and_reversed_card_model(), vec!["Hund", "dog"])
+                .unwrap()
+                .guid("hund"),
+        );
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let error = package
+            .write_maybe_timestamp(Some(1425279151.0), &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("GUID"), "{}", error);
+    }
+}
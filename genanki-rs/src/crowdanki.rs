@@ -0,0 +1,523 @@
+use crate::Error;
+use crate::db_entries::ModelDbEntry;
+use crate::deck::Deck;
+use crate::deck_config::DeckConfig;
+use crate::error::json_error;
+use crate::model::Model;
+use crate::note::Note;
+use crate::package::Package;
+use crate::util::id_for;
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// File CrowdAnki stores the deck tree, models, options and notes in
+const DECK_FILE: &str = "deck.json";
+/// Folder next to `deck.json` CrowdAnki stores the media files in
+const MEDIA_DIR: &str = "media";
+
+/// Options of the default preset of a new collection, which presets override
+fn default_config() -> Value {
+    json!({
+        "autoplay": true,
+        "id": 1,
+        "lapse": {"delays": [10], "leechAction": 0, "leechFails": 8, "minInt": 1, "mult": 0},
+        "maxTaken": 60,
+        "name": "Default",
+        "new": {
+            "bury": true,
+            "delays": [1, 10],
+            "initialFactor": 2500,
+            "ints": [1, 4, 7],
+            "order": 1,
+            "perDay": 20,
+            "separate": true
+        },
+        "replayq": true,
+        "rev": {
+            "bury": true,
+            "ease4": 1.3,
+            "fuzz": 0.05,
+            "ivlFct": 1,
+            "maxIvl": 36500,
+            "minSpace": 1,
+            "perDay": 100
+        },
+        "timer": 0
+    })
+}
+
+/// Returns the `crowdanki_uuid` of the object of `kind` with `id`
+///
+/// The ids are kept in the uuids so they survive a round trip through CrowdAnki.
+fn uuid(kind: &str, id: i64) -> String {
+    format!("genanki-{}-{}", kind, id)
+}
+
+/// Returns the id of the object of `kind` with `uuid`, derived from the uuid if it was not
+/// written by this crate
+fn id_from_uuid(kind: &str, uuid: &str) -> i64 {
+    uuid.strip_prefix(&format!("genanki-{}-", kind))
+        .and_then(|id| id.parse().ok())
+        .unwrap_or_else(|| id_for(uuid))
+}
+
+#[derive(Deserialize)]
+struct CrowdAnkiDeck {
+    crowdanki_uuid: String,
+    name: String,
+    #[serde(default)]
+    desc: String,
+    deck_config_uuid: Option<String>,
+    #[serde(default)]
+    deck_configurations: Vec<Value>,
+    #[serde(default)]
+    note_models: Vec<Value>,
+    #[serde(default)]
+    notes: Vec<CrowdAnkiNote>,
+    #[serde(default)]
+    media_files: Vec<String>,
+    #[serde(default)]
+    children: Vec<CrowdAnkiDeck>,
+}
+
+#[derive(Deserialize)]
+struct CrowdAnkiNote {
+    guid: String,
+    note_model_uuid: String,
+    fields: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl Package {
+    /// Writes the package in the layout of the [CrowdAnki](https://github.com/Stvad/CrowdAnki)
+    /// add-on: a `deck.json` with the deck tree, models, options and notes, and a `media` folder
+    ///
+    /// The output only depends on the content of the package, so it can be kept in version
+    /// control and reviewed as a text diff.
+    ///
+    /// Returns `Err` if the decks of the package do not share a single root deck, e.g. `German`
+    /// for `German::Nouns` and `German::Verbs`
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{basic_model, Deck, Note, Package};
+    /// use anyhow::Result;
+    ///
+    /// fn main() -> Result<()> {
+    ///     let mut deck = Deck::new(2059400110, "German::Nouns", "");
+    ///     deck.add_note(Note::new(basic_model(), vec!["Hund", "dog"])?);
+    ///     let dir = tempfile::tempdir()?;
+    ///     Package::new(vec![deck], vec![])?.write_crowdanki(dir.path())?;
+    ///
+    ///     let package = Package::read_crowdanki(dir.path())?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn write_crowdanki(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        let roots = self
+            .decks()
+            .iter()
+            .map(|deck| root_name(deck.name()))
+            .collect::<BTreeSet<_>>();
+        let root = match roots.len() {
+            1 => roots.into_iter().next().expect("one root"),
+            _ => {
+                return Err(anyhow!(Error::InvalidPackage(format!(
+                    "CrowdAnki needs a single root deck, found {:?}",
+                    roots
+                ))));
+            }
+        };
+
+        let mut models = BTreeMap::new();
+        let mut configs = BTreeMap::new();
+        configs.insert(1, crowdanki_config(default_config(), 1));
+        for deck in self.decks() {
+            for note in deck.notes() {
+                let model = note.model();
+                models.insert(model.id, model);
+            }
+            if let Some(config) = deck.config() {
+                configs.insert(config.id(), config_json(config));
+            }
+        }
+
+        let mut media_files = vec![];
+        if !self.media_files().is_empty() {
+            fs::create_dir_all(dir.join(MEDIA_DIR))?;
+        }
+        for path in self.media_files() {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| anyhow!("media path {:?} has no valid file name", path))?;
+            fs::copy(path, dir.join(MEDIA_DIR).join(name))?;
+            media_files.push(name.to_string());
+        }
+        media_files.sort();
+
+        let mut deck = self.deck_json(root)?;
+        deck["deck_configurations"] = json!(configs.into_values().collect::<Vec<_>>());
+        deck["note_models"] = json!(
+            models
+                .values()
+                .map(model_json)
+                .collect::<Result<Vec<_>>>()?
+        );
+        deck["media_files"] = json!(media_files);
+
+        fs::create_dir_all(dir)?;
+        let json = serde_json::to_string_pretty(&deck).map_err(json_error)?;
+        fs::write(dir.join(DECK_FILE), json + "\n")?;
+        Ok(())
+    }
+
+    /// Reads a package written by [`Package::write_crowdanki`] or exported by CrowdAnki
+    ///
+    /// Every deck of the tree becomes a deck of the package, including decks without notes.
+    ///
+    /// Returns `Err` if `deck.json` is missing or invalid, or if a note uses an unknown model
+    pub fn read_crowdanki(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let json = fs::read_to_string(dir.join(DECK_FILE))?;
+        let root: CrowdAnkiDeck = serde_json::from_str(&json).map_err(json_error)?;
+
+        let mut models = BTreeMap::new();
+        for model in &root.note_models {
+            let uuid = model["crowdanki_uuid"].as_str().unwrap_or_default();
+            let entry: ModelDbEntry = serde_json::from_value(model.clone()).map_err(json_error)?;
+            let id = id_from_uuid("model", uuid);
+            models.insert(uuid.to_string(), Model::from_db_entry(id, &entry));
+        }
+        let mut configs = BTreeMap::new();
+        for config in &root.deck_configurations {
+            let uuid = config["crowdanki_uuid"].as_str().unwrap_or_default();
+            let id = id_from_uuid("config", uuid);
+            if id != 1 {
+                configs.insert(uuid.to_string(), DeckConfig::from_json(id, config)?);
+            }
+        }
+
+        let mut decks = vec![];
+        read_deck(&root, None, &models, &configs, &mut decks)?;
+        let media_files = root
+            .media_files
+            .iter()
+            .map(|name| {
+                let path = dir.join(MEDIA_DIR).join(name);
+                path.to_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow!("media path {:?} is not valid UTF-8", path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Package::new(decks, media_files.iter().map(String::as_str).collect())
+    }
+
+    /// Returns the CrowdAnki deck named `name` with its notes and subdecks
+    fn deck_json(&self, name: &str) -> Result<Value> {
+        let deck = self.decks().iter().find(|deck| deck.name() == name);
+        let id = deck.map_or_else(|| id_for(name), Deck::id);
+        let config_id = deck.and_then(Deck::config).map_or(1, DeckConfig::id);
+        let notes = deck
+            .map(|deck| deck.notes().iter().map(note_json).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let prefix = format!("{}::", name);
+        let children = self
+            .decks()
+            .iter()
+            .filter_map(|deck| deck.name().strip_prefix(&prefix))
+            .map(|rest| format!("{}{}", prefix, root_name(rest)))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|child| self.deck_json(&child))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(json!({
+            "__type__": "Deck",
+            "children": children,
+            "crowdanki_uuid": uuid("deck", id),
+            "deck_config_uuid": uuid("config", config_id),
+            "desc": deck.map(Deck::description).unwrap_or_default(),
+            "dyn": 0,
+            "extendNew": 10,
+            "extendRev": 50,
+            "name": name,
+            "notes": notes,
+        }))
+    }
+}
+
+/// Returns the top level of the deck name `name`
+fn root_name(name: &str) -> &str {
+    name.split("::").next().unwrap_or(name)
+}
+
+fn read_deck(
+    deck: &CrowdAnkiDeck,
+    parent: Option<&str>,
+    models: &BTreeMap<String, Model>,
+    configs: &BTreeMap<String, DeckConfig>,
+    decks: &mut Vec<Deck>,
+) -> Result<()> {
+    // CrowdAnki writes the full name, but accept names relative to the parent as well
+    let name = match parent {
+        Some(parent) if !deck.name.starts_with(&format!("{}::", parent)) => {
+            format!("{}::{}", parent, deck.name)
+        }
+        _ => deck.name.clone(),
+    };
+    let mut read = Deck::new(
+        id_from_uuid("deck", &deck.crowdanki_uuid),
+        &name,
+        &deck.desc,
+    );
+    if let Some(config) = deck
+        .deck_config_uuid
+        .as_ref()
+        .and_then(|uuid| configs.get(uuid))
+    {
+        read.set_config(config.clone());
+    }
+    for note in &deck.notes {
+        let model = models
+            .get(&note.note_model_uuid)
+            .ok_or_else(|| anyhow!(Error::UnknownModel(note.note_model_uuid.clone())))?;
+        let fields = note.fields.iter().map(String::as_str).collect();
+        read.add_note(
+            Note::new(model.clone(), fields)?
+                .guid(&note.guid)
+                .tags(&note.tags),
+        );
+    }
+    decks.push(read);
+    for child in &deck.children {
+        read_deck(child, Some(&name), models, configs, decks)?;
+    }
+    Ok(())
+}
+
+fn note_json(note: &Note) -> Value {
+    json!({
+        "__type__": "Note",
+        "data": "",
+        "fields": note.fields(),
+        "flags": 0,
+        "guid": note.get_guid(),
+        "note_model_uuid": uuid("model", note.model().id),
+        "tags": note.get_tags(),
+    })
+}
+
+fn model_json(model: &Model) -> Result<Value> {
+    let mut json = serde_json::to_value(model.to_model_db_entry(0.0, 1)?).map_err(json_error)?;
+    let object = json.as_object_mut().expect("models are objects");
+    for key in ["id", "mod", "usn", "did"] {
+        object.remove(key);
+    }
+    for template in object["tmpls"].as_array_mut().into_iter().flatten() {
+        template["did"] = Value::Null;
+    }
+    object.insert("__type__".to_string(), json!("NoteModel"));
+    object.insert("crowdanki_uuid".to_string(), json!(uuid("model", model.id)));
+    Ok(json)
+}
+
+fn config_json(config: &DeckConfig) -> Value {
+    let mut json = config.to_json(&default_config(), 0.0);
+    // Whole minutes are written like Anki does, so reading and writing again gives the same file
+    for group in ["new", "lapse"] {
+        for step in json[group]["delays"].as_array_mut().into_iter().flatten() {
+            if let Some(minutes) = step.as_f64()
+                && minutes.fract() == 0.0
+            {
+                *step = json!(minutes as i64);
+            }
+        }
+    }
+    crowdanki_config(json, config.id())
+}
+
+fn crowdanki_config(mut config: Value, id: i64) -> Value {
+    let object = config.as_object_mut().expect("deck options are objects");
+    for key in ["id", "mod", "usn"] {
+        object.remove(key);
+    }
+    object.insert("__type__".to_string(), json!("DeckConfig"));
+    object.insert("crowdanki_uuid".to_string(), json!(uuid("config", id)));
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Field, Template, basic_model};
+
+    fn package(dir: &Path) -> Package {
+        let image = dir.join("hund.jpg");
+        fs::write(&image, b"JPEG").unwrap();
+        let cloze = Model::new(
+            1550428389,
+            "Cloze",
+            vec![Field::new("Text").font("Arial"), Field::new("Extra")],
+            vec![
+                Template::new("Cloze")
+                    .qfmt("{{cloze:Text}}")
+                    .afmt("{{cloze:Text}}<br>{{Extra}}"),
+            ],
+        )
+        .model_type(crate::ModelType::Cloze)
+        .css(".card { color: black; }");
+
+        let mut nouns = Deck::new(2059400110, "German::Nouns", "Nouns of the B1 list");
+        nouns.add_note(
+            Note::new(basic_model(), vec!["Hund", r#"dog <img src="hund.jpg">"#])
+                .unwrap()
+                .guid("hund")
+                .tags(["B1", "animal"]),
+        );
+        nouns.set_config(
+            DeckConfig::new(1675120101, "Nouns")
+                .unwrap()
+                .new_per_day(30),
+        );
+        let mut verbs = Deck::new(2059400111, "German::Verbs", "");
+        verbs.add_note(
+            Note::new(cloze, vec!["{{c1::laufen}} - to run", ""])
+                .unwrap()
+                .guid("laufen"),
+        );
+        Package::new(vec![nouns, verbs], vec![image.to_str().unwrap()]).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let export = dir.path().join("export");
+        package(dir.path()).write_crowdanki(&export).unwrap();
+
+        let json: Value =
+            serde_json::from_str(&fs::read_to_string(export.join(DECK_FILE)).unwrap()).unwrap();
+        assert_eq!(json["name"], "German");
+        assert_eq!(json["notes"], json!([]));
+        assert_eq!(json["children"][0]["name"], "German::Nouns");
+        assert_eq!(
+            json["children"][0]["crowdanki_uuid"],
+            "genanki-deck-2059400110"
+        );
+        assert_eq!(json["children"][0]["notes"][0]["guid"], "hund");
+        assert_eq!(
+            json["children"][0]["notes"][0]["tags"],
+            json!(["B1", "animal"])
+        );
+        assert_eq!(json["children"][1]["deck_config_uuid"], "genanki-config-1");
+        assert_eq!(json["note_models"][0]["type"], 1);
+        assert_eq!(json["note_models"][1]["name"], "Basic (genanki)");
+        let configs = json["deck_configurations"].as_array().unwrap();
+        assert_eq!(configs[1]["new"]["perDay"], 30);
+        assert_eq!(configs[1]["rev"]["maxIvl"], 36500);
+        assert_eq!(json["media_files"], json!(["hund.jpg"]));
+        assert_eq!(
+            fs::read(export.join(MEDIA_DIR).join("hund.jpg")).unwrap(),
+            b"JPEG"
+        );
+
+        let package = Package::read_crowdanki(&export).unwrap();
+        let names = package
+            .decks()
+            .iter()
+            .map(|deck| deck.name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["German", "German::Nouns", "German::Verbs"]);
+        let nouns = &package.decks()[1];
+        assert_eq!(nouns.id(), 2059400110);
+        assert_eq!(nouns.description(), "Nouns of the B1 list");
+        assert_eq!(nouns.config().unwrap().id(), 1675120101);
+        assert_eq!(nouns.notes()[0].get_guid(), "hund");
+        assert_eq!(nouns.notes()[0].model().id, basic_model().id);
+
+        // Writing the read package again gives the same files
+        let again = dir.path().join("again");
+        package.write_crowdanki(&again).unwrap();
+        assert_eq!(
+            fs::read_to_string(again.join(DECK_FILE)).unwrap(),
+            fs::read_to_string(export.join(DECK_FILE)).unwrap()
+        );
+    }
+
+    #[test]
+    fn crowdanki_exports_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let deck = json!({
+            "__type__": "Deck",
+            "crowdanki_uuid": "0c3f6b8e-4b4a-11ef-9c7e-0242ac120002",
+            "deck_config_uuid": "0c3f6b8e-4b4a-11ef-9c7e-0242ac120003",
+            "deck_configurations": [{
+                "__type__": "DeckConfig",
+                "crowdanki_uuid": "0c3f6b8e-4b4a-11ef-9c7e-0242ac120003",
+                "name": "Languages",
+                "new": {"delays": [1, 10, 60], "perDay": 15},
+                "newMix": 0
+            }],
+            "name": "Languages",
+            "note_models": [{
+                "__type__": "NoteModel",
+                "crowdanki_uuid": "0c3f6b8e-4b4a-11ef-9c7e-0242ac120004",
+                "flds": [{"name": "Front", "ord": 0}, {"name": "Back", "ord": 1}],
+                "name": "Basic",
+                "tmpls": [{"name": "Card 1", "qfmt": "{{Front}}", "afmt": "{{Back}}", "ord": 0}],
+                "type": 0,
+                "originalStockKind": 1
+            }],
+            "notes": [],
+            "children": [{
+                "__type__": "Deck",
+                "crowdanki_uuid": "0c3f6b8e-4b4a-11ef-9c7e-0242ac120005",
+                "name": "French",
+                "notes": [{
+                    "__type__": "Note",
+                    "fields": ["chien", "dog"],
+                    "guid": "Ot0!xywPWG",
+                    "note_model_uuid": "0c3f6b8e-4b4a-11ef-9c7e-0242ac120004",
+                    "tags": ["animal"]
+                }]
+            }]
+        });
+        fs::write(dir.path().join(DECK_FILE), deck.to_string()).unwrap();
+
+        let package = Package::read_crowdanki(dir.path()).unwrap();
+        let french = &package.decks()[1];
+        assert_eq!(french.name(), "Languages::French");
+        assert_eq!(french.notes()[0].fields(), ["chien", "dog"]);
+        assert_eq!(french.notes()[0].get_tags(), ["animal"]);
+        let config = package.decks()[0].config().unwrap();
+        assert_eq!(config.to_json(&default_config(), 0.0)["new"]["perDay"], 15);
+        assert!(french.config().is_none());
+    }
+
+    #[test]
+    fn unknown_models_and_several_roots_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let deck = json!({
+            "crowdanki_uuid": "a",
+            "name": "Languages",
+            "notes": [{"fields": ["chien"], "guid": "x", "note_model_uuid": "missing"}]
+        });
+        fs::write(dir.path().join(DECK_FILE), deck.to_string()).unwrap();
+        assert!(Package::read_crowdanki(dir.path()).is_err());
+
+        let package = Package::new(
+            vec![Deck::new(1, "German", ""), Deck::new(2, "French", "")],
+            vec![],
+        )
+        .unwrap();
+        assert!(package.write_crowdanki(dir.path().join("out")).is_err());
+    }
+}
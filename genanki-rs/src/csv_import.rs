@@ -0,0 +1,568 @@
+use crate::Error;
+use crate::deck::Deck;
+use crate::error::csv_error;
+use crate::model::Model;
+use crate::note::Note;
+use crate::util::id_for;
+use anyhow::{Result, anyhow};
+use csv::{ReaderBuilder, StringRecord};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Refers to a column of the input, either by its zero-based position or by its header name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl From<usize> for Column {
+    fn from(index: usize) -> Self {
+        Column::Index(index)
+    }
+}
+
+impl From<&str> for Column {
+    fn from(name: &str) -> Self {
+        Column::Name(name.to_string())
+    }
+}
+
+impl From<String> for Column {
+    fn from(name: String) -> Self {
+        Column::Name(name)
+    }
+}
+
+/// Imports the rows of a CSV/TSV file as `Note`s of a single `Model`.
+///
+/// Each model field is read from a column, selected by header name or by index. Unless mapped
+/// explicitly, fields are matched by name if the file has headers, which then have to name every
+/// field of the model. Without headers they are read by position, skipping the tags, GUID and deck
+/// columns.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, CsvImporter, Deck};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let input = "Question,Answer,Tags\nCapital of France?,Paris,geo europe\n";
+///     let import = CsvImporter::new(basic_model())
+///         .field("Front", "Question")
+///         .field("Back", "Answer")
+///         .tags_column("Tags")
+///         .import_reader(input.as_bytes())?;
+///     assert!(import.errors.is_empty());
+///
+///     let decks = import.into_decks(Deck::new(1234, "Geography", ""));
+///     assert_eq!(decks.len(), 1);
+///     Ok(())
+/// }
+/// ```
+///
+/// The importer has the following default values:
+/// * `delimiter` - `,`
+/// * `has_headers` - `true`
+/// * `escape_html` - `false`
+/// * `newlines_to_br` - `false`
+#[derive(Clone)]
+pub struct CsvImporter {
+    model: Model,
+    delimiter: u8,
+    has_headers: bool,
+    fields: Vec<(String, Column)>,
+    tags_column: Option<Column>,
+    guid_column: Option<Column>,
+    deck_column: Option<Column>,
+    escape_html: bool,
+    newlines_to_br: bool,
+}
+
+impl CsvImporter {
+    /// Creates a new importer for comma separated input producing notes of `model`
+    pub fn new(model: Model) -> Self {
+        Self {
+            model,
+            delimiter: b',',
+            has_headers: true,
+            fields: vec![],
+            tags_column: None,
+            guid_column: None,
+            deck_column: None,
+            escape_html: false,
+            newlines_to_br: false,
+        }
+    }
+
+    /// Creates a new importer for tab separated input producing notes of `model`
+    pub fn tsv(model: Model) -> Self {
+        Self::new(model).delimiter(b'\t')
+    }
+
+    /// Sets the column delimiter
+    pub fn delimiter(self, delimiter: u8) -> Self {
+        Self { delimiter, ..self }
+    }
+
+    /// Sets whether the first row contains column names
+    pub fn has_headers(self, has_headers: bool) -> Self {
+        Self {
+            has_headers,
+            ..self
+        }
+    }
+
+    /// Reads the model field named `field` from `column`
+    pub fn field(mut self, field: &str, column: impl Into<Column>) -> Self {
+        self.fields.push((field.to_string(), column.into()));
+        self
+    }
+
+    /// Reads whitespace separated tags from `column`
+    pub fn tags_column(self, column: impl Into<Column>) -> Self {
+        Self {
+            tags_column: Some(column.into()),
+            ..self
+        }
+    }
+
+    /// Reads the note GUID from `column`
+    ///
+    /// Rows with an empty GUID get an auto-generated one.
+    pub fn guid_column(self, column: impl Into<Column>) -> Self {
+        Self {
+            guid_column: Some(column.into()),
+            ..self
+        }
+    }
+
+    /// Reads the name of the deck a note should be routed to from `column`
+    ///
+    /// See [`CsvImport::into_decks`].
+    pub fn deck_column(self, column: impl Into<Column>) -> Self {
+        Self {
+            deck_column: Some(column.into()),
+            ..self
+        }
+    }
+
+    /// Sets whether `&`, `<`, `>` and `"` in field values are escaped, so plain text is shown
+    /// literally instead of being interpreted as HTML
+    pub fn escape_html(self, escape_html: bool) -> Self {
+        Self {
+            escape_html,
+            ..self
+        }
+    }
+
+    /// Sets whether line breaks in field values are converted to `<br>`
+    pub fn newlines_to_br(self, newlines_to_br: bool) -> Self {
+        Self {
+            newlines_to_br,
+            ..self
+        }
+    }
+
+    /// Imports the file at `path`
+    ///
+    /// Returns `Err` if the file cannot be read or a mapped column does not exist. Errors in single
+    /// rows are reported in [`CsvImport::errors`] instead.
+    pub fn import_path<P: AsRef<Path>>(&self, path: P) -> Result<CsvImport> {
+        self.import_reader(File::open(path)?)
+    }
+
+    /// Imports CSV/TSV data from any reader
+    ///
+    /// Returns `Err` if the input cannot be read or a mapped column does not exist. Errors in
+    /// single rows are reported in [`CsvImport::errors`] instead.
+    pub fn import_reader<R: Read>(&self, reader: R) -> Result<CsvImport> {
+        let mut reader = ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .flexible(true)
+            .from_reader(reader);
+        let headers = if self.has_headers {
+            Some(reader.headers().map_err(csv_error)?.clone())
+        } else {
+            None
+        };
+
+        let resolve = |column: &Option<Column>| {
+            column
+                .as_ref()
+                .map(|column| resolve_column(column, headers.as_ref()))
+                .transpose()
+        };
+        let tags_column = resolve(&self.tags_column)?;
+        let guid_column = resolve(&self.guid_column)?;
+        let deck_column = resolve(&self.deck_column)?;
+        let special_columns = [tags_column, guid_column, deck_column]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let field_columns = self.field_columns(headers.as_ref(), &special_columns)?;
+
+        let mut import = CsvImport::default();
+        for (i, record) in reader.records().enumerate() {
+            let fallback_row = i + 1 + usize::from(self.has_headers);
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    let row = e
+                        .position()
+                        .map_or(fallback_row, |position| position.line() as usize);
+                    import.errors.push(RowError {
+                        row,
+                        message: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let row = record
+                .position()
+                .map_or(fallback_row, |position| position.line() as usize);
+            match self.note_from_record(&record, &field_columns, tags_column, guid_column) {
+                Ok(note) => import.notes.push(ImportedNote {
+                    row,
+                    deck: deck_column
+                        .and_then(|column| record.get(column))
+                        .map(str::trim)
+                        .filter(|deck| !deck.is_empty())
+                        .map(str::to_string),
+                    note,
+                }),
+                Err(e) => import.errors.push(RowError {
+                    row,
+                    message: e.to_string(),
+                }),
+            }
+        }
+        Ok(import)
+    }
+
+    fn field_columns(
+        &self,
+        headers: Option<&StringRecord>,
+        special_columns: &[usize],
+    ) -> Result<Vec<Option<usize>>> {
+        let model_fields = self.model.fields();
+        if self.fields.is_empty() {
+            return match headers {
+                Some(headers) => model_fields
+                    .iter()
+                    .map(|field| {
+                        headers
+                            .iter()
+                            .position(|header| header.trim() == field.name)
+                            .map(Some)
+                            .ok_or_else(|| {
+                                anyhow!(Error::UnknownField {
+                                    model: self.model.name().to_string(),
+                                    field: field.name.clone(),
+                                })
+                            })
+                    })
+                    .collect(),
+                None => Ok((0..)
+                    .filter(|column| !special_columns.contains(column))
+                    .take(model_fields.len())
+                    .map(Some)
+                    .collect()),
+            };
+        }
+
+        for (field, _) in &self.fields {
+            if !model_fields.iter().any(|f| &f.name == field) {
+                return Err(anyhow!(Error::UnknownField {
+                    model: self.model.name().to_string(),
+                    field: field.clone(),
+                }));
+            }
+        }
+        model_fields
+            .iter()
+            .map(|field| {
+                self.fields
+                    .iter()
+                    .find(|(name, _)| name == &field.name)
+                    .map(|(_, column)| resolve_column(column, headers))
+                    .transpose()
+            })
+            .collect()
+    }
+
+    fn note_from_record(
+        &self,
+        record: &StringRecord,
+        field_columns: &[Option<usize>],
+        tags_column: Option<usize>,
+        guid_column: Option<usize>,
+    ) -> Result<Note> {
+        let fields = field_columns
+            .iter()
+            .map(|&column| match column {
+                Some(column) => record
+                    .get(column)
+                    .map(|value| self.convert_value(value))
+                    .ok_or_else(|| anyhow!("row has no column {}", column)),
+                None => Ok(String::new()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let tags = tags_column
+            .and_then(|column| record.get(column))
+            .map(|tags| tags.split_whitespace().collect::<Vec<_>>());
+        let guid = guid_column
+            .and_then(|column| record.get(column))
+            .map(str::trim)
+            .filter(|guid| !guid.is_empty());
+
+        Note::new_with_options(
+            self.model.clone(),
+            fields.iter().map(String::as_str).collect(),
+            None,
+            tags,
+            guid,
+        )
+    }
+
+    fn convert_value(&self, value: &str) -> String {
+        let value = if self.escape_html {
+            escape_html(value)
+        } else {
+            value.to_string()
+        };
+        if self.newlines_to_br {
+            value.replace("\r\n", "<br>").replace('\n', "<br>")
+        } else {
+            value
+        }
+    }
+}
+
+fn resolve_column(column: &Column, headers: Option<&StringRecord>) -> Result<usize> {
+    match column {
+        Column::Index(index) => Ok(*index),
+        Column::Name(name) => headers
+            .and_then(|headers| headers.iter().position(|header| header.trim() == name))
+            .ok_or_else(|| anyhow!(Error::UnknownColumn(name.clone()))),
+    }
+}
+
+pub(super) fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A `Note` read from a CSV/TSV row
+#[derive(Clone)]
+pub struct ImportedNote {
+    /// Line of the input the note was read from, starting at 1
+    pub row: usize,
+    /// Deck name read from the deck column, if any
+    pub deck: Option<String>,
+    pub note: Note,
+}
+
+/// A row which could not be turned into a `Note`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowError {
+    /// Line of the input, starting at 1
+    pub row: usize,
+    pub message: String,
+}
+
+impl Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "row {}: {}", self.row, self.message)
+    }
+}
+
+/// Result of a [`CsvImporter`] run
+#[derive(Clone, Default)]
+pub struct CsvImport {
+    pub notes: Vec<ImportedNote>,
+    pub errors: Vec<RowError>,
+}
+
+impl CsvImport {
+    /// Distributes the imported notes into decks.
+    ///
+    /// Notes without a deck name, or whose deck name equals the name of `default_deck`, are added
+    /// to `default_deck`. Every other deck name gets its own `Deck` with an id derived from the
+    /// name, so names like `Languages::German` end up as subdecks in Anki. `default_deck` is
+    /// always returned first, followed by the other decks in order of their first appearance.
+    pub fn into_decks(self, default_deck: Deck) -> Vec<Deck> {
+        let mut decks = vec![default_deck];
+        for imported in self.notes {
+            let name = imported.deck.unwrap_or_else(|| decks[0].name().to_string());
+            let index = match decks.iter().position(|deck| deck.name() == name) {
+                Some(index) => index,
+                None => {
+                    decks.push(Deck::new(id_for(&name), &name, ""));
+                    decks.len() - 1
+                }
+            };
+            decks[index].add_note(imported.note);
+        }
+        decks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Field, Template, basic_model};
+
+    fn three_field_model() -> Model {
+        Model::new(
+            1607392320,
+            "Three fields",
+            vec![
+                Field::new("Word"),
+                Field::new("Meaning"),
+                Field::new("Extra"),
+            ],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Word}}")
+                    .afmt("{{FrontSide}}<hr id=answer>{{Meaning}}"),
+            ],
+        )
+    }
+
+    #[test]
+    fn maps_fields_by_header_name() {
+        let input = "Back,Front\nParis,France\n";
+        let import = CsvImporter::new(basic_model())
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert!(import.errors.is_empty());
+        assert_eq!(import.notes.len(), 1);
+        assert_eq!(import.notes[0].note.fields(), ["France", "Paris"]);
+    }
+
+    #[test]
+    fn maps_fields_by_position_without_headers() {
+        let input = "France\tParis\nItaly\tRome\n";
+        let import = CsvImporter::tsv(basic_model())
+            .has_headers(false)
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(import.notes.len(), 2);
+        assert_eq!(import.notes[1].row, 2);
+        assert_eq!(import.notes[1].note.fields(), ["Italy", "Rome"]);
+    }
+
+    #[test]
+    fn positional_fields_skip_special_columns() {
+        let input = "geo\tFrance\tParis\n";
+        let import = CsvImporter::tsv(basic_model())
+            .has_headers(false)
+            .tags_column(0)
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(import.notes[0].note.fields(), ["France", "Paris"]);
+        assert_eq!(import.notes[0].note.get_tags(), ["geo"]);
+    }
+
+    #[test]
+    fn headers_missing_a_field_are_an_error() {
+        let input = "front,back\nFrance,Paris\n";
+        let Err(error) = CsvImporter::new(basic_model()).import_reader(input.as_bytes()) else {
+            panic!("headers without the model fields were accepted");
+        };
+        assert!(error.to_string().contains("\"Front\""), "{}", error);
+    }
+
+    #[test]
+    fn unmapped_fields_are_empty() {
+        let input = "a;b;c\nHund;dog;ignored\n";
+        let import = CsvImporter::new(three_field_model())
+            .delimiter(b';')
+            .field("Word", 0)
+            .field("Meaning", "b")
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(import.notes[0].note.fields(), ["Hund", "dog", ""]);
+    }
+
+    #[test]
+    fn tags_guid_and_deck_columns() {
+        let input = "Front,Back,Tags,Guid,Deck\n\
+                     France,Paris,geo europe,abc,Geo::Europe\n\
+                     Peru,Lima,,,\n";
+        let import = CsvImporter::new(basic_model())
+            .tags_column("Tags")
+            .guid_column("Guid")
+            .deck_column(4)
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert!(import.errors.is_empty());
+        let first = &import.notes[0];
+        assert_eq!(first.note.get_tags(), ["geo", "europe"]);
+        assert_eq!(first.note.get_guid(), "abc");
+        assert_eq!(first.deck.as_deref(), Some("Geo::Europe"));
+        let second = &import.notes[1];
+        assert!(second.note.get_tags().is_empty());
+        assert_ne!(second.note.get_guid(), "");
+        assert_eq!(second.deck, None);
+
+        let decks = import.into_decks(Deck::new(1, "Geo", ""));
+        assert_eq!(
+            decks.iter().map(|deck| deck.name()).collect::<Vec<_>>(),
+            ["Geo", "Geo::Europe"]
+        );
+        assert_eq!(decks[1].id(), id_for("Geo::Europe"));
+    }
+
+    #[test]
+    fn escapes_html_and_converts_newlines() {
+        let input = "Front,Back\n\"a < b\",\"line 1\nline 2\"\n";
+        let import = CsvImporter::new(basic_model())
+            .escape_html(true)
+            .newlines_to_br(true)
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(
+            import.notes[0].note.fields(),
+            ["a &lt; b", "line 1<br>line 2"]
+        );
+    }
+
+    #[test]
+    fn reports_row_errors() {
+        let input = "Front,Back,Tags\nok,fine,\nshort\nbad,tags,\"a b\"\n";
+        let import = CsvImporter::new(basic_model())
+            .field("Front", "Front")
+            .field("Back", "Back")
+            .import_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(import.notes.len(), 2);
+        assert_eq!(import.errors.len(), 1);
+        assert_eq!(import.errors[0].row, 3);
+    }
+
+    #[test]
+    fn unknown_field_or_column_is_an_error() {
+        let input = "Front,Back\na,b\n";
+        assert!(
+            CsvImporter::new(basic_model())
+                .field("Question", 0)
+                .import_reader(input.as_bytes())
+                .is_err()
+        );
+        assert!(
+            CsvImporter::new(basic_model())
+                .field("Front", "Question")
+                .import_reader(input.as_bytes())
+                .is_err()
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use std::fmt::Display;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use thiserror::Error;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct DeckDbEntry {
     pub collapsed: bool,
     pub conf: i64,
@@ -29,11 +30,13 @@ pub struct DeckDbEntry {
     pub usn: i64,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct ModelDbEntry {
     pub vers: Vec<Option<serde_json::Value>>,
     pub name: String,
     pub tags: Vec<Option<serde_json::Value>>,
+    #[serde(deserialize_with = "null_as_default")]
     pub did: i64,
     pub usn: i64,
     pub req: Vec<(usize, String, Vec<usize>)>,
@@ -46,13 +49,15 @@ pub struct ModelDbEntry {
     pub latex_post: String,
     #[serde(rename = "type")]
     pub model_db_entry_type: i64,
+    #[serde(deserialize_with = "string_or_number")]
     pub id: String,
     pub css: String,
     #[serde(rename = "latexPre")]
     pub latex_pre: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
 pub struct Fld {
     pub name: String,
     pub media: Vec<Option<serde_json::Value>>,
@@ -63,7 +68,8 @@ pub struct Fld {
     pub size: i64,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Error)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Error)]
+#[serde(default)]
 pub struct Tmpl {
     pub name: String,
     pub qfmt: String,
@@ -79,3 +85,27 @@ impl Display for Tmpl {
         write!(f, "{:?}", self)
     }
 }
+
+/// Anki itself writes `null` for some numbers genanki always sets, e.g. the deck of a model
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Model ids are written as strings by genanki but as numbers by Anki
+fn string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => Ok(s),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        other => Err(serde::de::Error::custom(format!(
+            "expected a model id, found {}",
+            other
+        ))),
+    }
+}
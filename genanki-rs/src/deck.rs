@@ -1,11 +1,16 @@
 use super::Package;
-use crate::db_entries::{DeckDbEntry, ModelDbEntry};
+use crate::conflict::ConflictPolicy;
+use crate::db_entries::DeckDbEntry;
+use crate::deck_config::DeckConfig;
 use crate::error::json_error;
 use crate::model::Model;
 use crate::note::Note;
+use crate::tags::register_tags;
 use anyhow::Result;
+use serde_json::{Value, json};
 use sqlx::SqliteConnection;
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
 /// A flashcard deck which can be written into an .apkg file.
@@ -16,6 +21,7 @@ pub struct Deck {
     description: String,
     notes: Vec<Note>,
     models: HashMap<i64, Model>,
+    config: Option<DeckConfig>,
 }
 
 impl Deck {
@@ -29,6 +35,7 @@ impl Deck {
             description: description.to_string(),
             notes: vec![],
             models: HashMap::new(),
+            config: None,
         }
     }
 
@@ -51,14 +58,77 @@ impl Deck {
         self.notes.push(note);
     }
 
+    /// Sets the options preset of the deck, see [`DeckConfig`]
+    pub fn set_config(&mut self, config: DeckConfig) {
+        self.config = Some(config);
+    }
+
+    pub(super) fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(super) fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub(super) fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    pub(super) fn config(&self) -> Option<&DeckConfig> {
+        self.config.as_ref()
+    }
+
+    /// Removes the notes for which `keep` returns `false`
+    pub(super) fn retain_notes(&mut self, keep: impl FnMut(&Note) -> bool) {
+        self.notes.retain(keep);
+    }
+
+    /// Replaces every field of every note by the result of `map`
+    pub(super) fn map_fields(&mut self, map: impl Fn(&str) -> String) -> Result<()> {
+        for note in &mut self.notes {
+            note.map_fields(&map)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of the decks all cards of this deck are placed in, including the template
+    /// deck overrides of its models
+    pub(super) fn card_deck_ids(&self) -> HashSet<i64> {
+        let mut ids = HashSet::from([self.id]);
+        for note in &self.notes {
+            let model = note.model();
+            ids.extend(
+                model
+                    .templates()
+                    .iter()
+                    .filter_map(|template| template.did.map(|did| did as i64)),
+            );
+        }
+        ids
+    }
+
+    /// Returns the ids of the decks written to the collection along with this deck
+    pub(super) fn written_deck_ids(&self) -> HashSet<i64> {
+        let mut ids = HashSet::from([self.id]);
+        for note in &self.notes {
+            ids.extend(note.model().override_decks().iter().map(|deck| deck.id));
+        }
+        ids
+    }
+
     fn add_model(&mut self, model: Model) {
         self.models.insert(model.id, model);
     }
 
-    fn to_deck_db_entry(&self) -> DeckDbEntry {
+    pub(super) fn to_deck_db_entry(&self) -> DeckDbEntry {
         DeckDbEntry {
             collapsed: false,
-            conf: 1,
+            conf: self.config.as_ref().map_or(1, DeckConfig::id),
             desc: self.description.clone(),
             deck_db_entry_dyn: 0,
             extend_new: 0,
@@ -84,6 +154,7 @@ impl Deck {
         &mut self,
         conn: &mut SqliteConnection,
         timestamp: f64,
+        policy: ConflictPolicy,
     ) -> Result<()> {
         let rec = sqlx::query!(
             r#"
@@ -93,10 +164,36 @@ impl Deck {
         .fetch_one(&mut *conn)
         .await?;
 
-        let mut decks: HashMap<i64, DeckDbEntry> =
+        // Entries are kept as JSON so keys of existing decks unknown to `DeckDbEntry` survive
+        let mut decks: HashMap<i64, Value> =
             serde_json::from_str(&rec.decks).map_err(json_error)?;
 
-        decks.insert(self.id, self.to_deck_db_entry());
+        match decks.get_mut(&self.id) {
+            Some(existing) => {
+                existing["name"] = json!(self.name);
+                existing["desc"] = json!(self.description);
+                existing["mod"] = json!(timestamp as i64);
+                existing["usn"] = json!(-1);
+                if let Some(config) = &self.config {
+                    existing["conf"] = json!(config.id());
+                }
+            }
+            None => {
+                decks.insert(self.id, serde_json::to_value(self.to_deck_db_entry())?);
+            }
+        }
+        for note in &self.notes {
+            for deck in note.model().override_decks() {
+                if let Entry::Vacant(entry) = decks.entry(deck.id) {
+                    entry.insert(serde_json::to_value(deck)?);
+                }
+            }
+        }
+
+        if let Some(config) = &self.config {
+            self.write_config_to_db(&mut *conn, config, timestamp)
+                .await?;
+        }
 
         let decks_string = serde_json::to_string(&decks)?.clone();
         sqlx::query!(
@@ -116,13 +213,19 @@ impl Deck {
         .fetch_one(&mut *conn)
         .await?;
 
-        let mut models: HashMap<i64, ModelDbEntry> =
+        let mut models: HashMap<i64, Value> =
             serde_json::from_str(&models_json_str.models).map_err(json_error)?;
         for note in self.notes.clone().iter() {
             self.add_model(note.model());
         }
         for (i, model) in &mut self.models {
-            models.insert(*i, model.to_model_db_entry(timestamp, self.id)?);
+            let entry = serde_json::to_value(model.to_model_db_entry(timestamp, self.id)?)?;
+            match (models.get_mut(i), entry) {
+                (Some(Value::Object(existing)), Value::Object(entry)) => existing.extend(entry),
+                (_, entry) => {
+                    models.insert(*i, entry);
+                }
+            }
         }
         let models_string = serde_json::to_string(&models)?.clone();
         let _ = sqlx::query!(
@@ -134,9 +237,47 @@ impl Deck {
         .execute(&mut *conn)
         .await?;
 
-        for note in &mut self.notes {
-            note.write_to_db(&mut *conn, timestamp, self.id).await?;
+        let mut tags = vec![];
+        for note in &self.notes {
+            if note
+                .write_to_db(&mut *conn, timestamp, self.id, policy)
+                .await?
+            {
+                tags.extend(note.get_tags().iter().map(String::as_str));
+            }
         }
+        register_tags(&mut *conn, tags).await?;
+        Ok(())
+    }
+
+    async fn write_config_to_db(
+        &self,
+        conn: &mut SqliteConnection,
+        config: &DeckConfig,
+        timestamp: f64,
+    ) -> Result<()> {
+        let rec = sqlx::query!(
+            r#"
+            SELECT dconf FROM col
+        "#
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let mut configs: HashMap<String, Value> =
+            serde_json::from_str(&rec.dconf).map_err(json_error)?;
+        let base = configs.get("1").cloned().unwrap_or_default();
+        configs.insert(config.id().to_string(), config.to_json(&base, timestamp));
+
+        let configs_string = serde_json::to_string(&configs)?;
+        sqlx::query!(
+            r#"
+                UPDATE col SET dconf = ?
+        "#,
+            configs_string
+        )
+        .execute(&mut *conn)
+        .await?;
         Ok(())
     }
 
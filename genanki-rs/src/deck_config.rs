@@ -0,0 +1,280 @@
+use crate::Error;
+use crate::scheduling::check_retention;
+use anyhow::{Result, anyhow};
+use serde_json::{Value, json};
+
+/// Deck options preset, shared by the decks it is set on with `Deck::set_config`.
+///
+/// Options which are not set are copied from the default preset of the collection.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{Deck, DeckConfig};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let config = DeckConfig::new(1675120101, "Vocabulary")?
+///         .new_per_day(30)
+///         .learning_steps(vec![1.0, 10.0, 60.0])
+///         .desired_retention(0.85)?;
+///     let mut deck = Deck::new(2059400110, "Vocabulary", "");
+///     deck.set_config(config);
+///     Ok(())
+/// }
+/// ```
+///
+/// The builder has the following default values:
+/// * `new_per_day` - `None`
+/// * `reviews_per_day` - `None`
+/// * `learning_steps` - `None`
+/// * `relearning_steps` - `None`
+/// * `desired_retention` - `None`
+/// * `fsrs_params` - `None`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeckConfig {
+    id: i64,
+    name: String,
+    new_per_day: Option<i64>,
+    reviews_per_day: Option<i64>,
+    learning_steps: Option<Vec<f64>>,
+    relearning_steps: Option<Vec<f64>>,
+    desired_retention: Option<f64>,
+    fsrs_params: Option<Vec<f64>>,
+}
+
+impl DeckConfig {
+    /// Creates a preset with an `id` and a `name`
+    ///
+    /// Returns `Err` if `id` is 1, which is the default preset of every collection
+    pub fn new(id: i64, name: &str) -> Result<Self> {
+        if id == 1 {
+            return Err(anyhow!(Error::ReservedDeckConfigId(id)));
+        }
+        Ok(Self {
+            id,
+            name: name.to_string(),
+            new_per_day: None,
+            reviews_per_day: None,
+            learning_steps: None,
+            relearning_steps: None,
+            desired_retention: None,
+            fsrs_params: None,
+        })
+    }
+
+    /// Sets the maximum number of new cards introduced per day
+    pub fn new_per_day(self, new_per_day: i64) -> Self {
+        Self {
+            new_per_day: Some(new_per_day),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of reviews per day
+    pub fn reviews_per_day(self, reviews_per_day: i64) -> Self {
+        Self {
+            reviews_per_day: Some(reviews_per_day),
+            ..self
+        }
+    }
+
+    /// Sets the learning steps in minutes
+    pub fn learning_steps(self, learning_steps: Vec<f64>) -> Self {
+        Self {
+            learning_steps: Some(learning_steps),
+            ..self
+        }
+    }
+
+    /// Sets the relearning steps in minutes
+    pub fn relearning_steps(self, relearning_steps: Vec<f64>) -> Self {
+        Self {
+            relearning_steps: Some(relearning_steps),
+            ..self
+        }
+    }
+
+    /// Sets the retention FSRS schedules the cards for
+    ///
+    /// Returns `Err` if the retention is not between 0 and 1
+    pub fn desired_retention(self, desired_retention: f64) -> Result<Self> {
+        check_retention(desired_retention)?;
+        Ok(Self {
+            desired_retention: Some(desired_retention),
+            ..self
+        })
+    }
+
+    /// Sets the FSRS parameters, 17 for FSRS-4.5, 19 for FSRS-5 or 21 for FSRS-6
+    ///
+    /// Returns `Err` for any other number of parameters
+    pub fn fsrs_params(self, fsrs_params: Vec<f64>) -> Result<Self> {
+        if params_key(&fsrs_params).is_none() {
+            return Err(anyhow!(Error::InvalidScheduling(format!(
+                "expected 17, 19 or 21 FSRS parameters, got {}",
+                fsrs_params.len()
+            ))));
+        }
+        Ok(Self {
+            fsrs_params: Some(fsrs_params),
+            ..self
+        })
+    }
+
+    pub(super) fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Reads a preset from an entry of `col.dconf`, taking every option it sets
+    pub(super) fn from_json(id: i64, config: &Value) -> Result<Self> {
+        let numbers = |value: &Value| -> Option<Vec<f64>> {
+            value
+                .as_array()
+                .map(|steps| steps.iter().filter_map(Value::as_f64).collect())
+        };
+        let mut parsed = Self::new(id, config["name"].as_str().unwrap_or_default())?;
+        parsed.new_per_day = config["new"]["perDay"].as_i64();
+        parsed.reviews_per_day = config["rev"]["perDay"].as_i64();
+        parsed.learning_steps = numbers(&config["new"]["delays"]);
+        parsed.relearning_steps = numbers(&config["lapse"]["delays"]);
+        if let Some(retention) = config["desiredRetention"].as_f64() {
+            parsed = parsed.desired_retention(retention)?;
+        }
+        for key in ["fsrsParams6", "fsrsParams5", "fsrsWeights"] {
+            if let Some(params) = numbers(&config[key])
+                && !params.is_empty()
+            {
+                parsed = parsed.fsrs_params(params)?;
+                break;
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Returns the entry of `col.dconf`, based on the default preset `base`
+    pub(super) fn to_json(&self, base: &Value, timestamp: f64) -> Value {
+        let mut config = base.clone();
+        config["id"] = json!(self.id);
+        config["name"] = json!(self.name);
+        config["mod"] = json!(timestamp as i64);
+        config["usn"] = json!(-1);
+        if let Some(new_per_day) = self.new_per_day {
+            config["new"]["perDay"] = json!(new_per_day);
+        }
+        if let Some(reviews_per_day) = self.reviews_per_day {
+            config["rev"]["perDay"] = json!(reviews_per_day);
+        }
+        if let Some(steps) = &self.learning_steps {
+            config["new"]["delays"] = json!(steps);
+        }
+        if let Some(steps) = &self.relearning_steps {
+            config["lapse"]["delays"] = json!(steps);
+        }
+        if let Some(retention) = self.desired_retention {
+            config["desiredRetention"] = json!(retention);
+        }
+        if let Some(params) = &self.fsrs_params
+            && let Some(key) = params_key(params)
+        {
+            config[key] = json!(params);
+        }
+        config
+    }
+}
+
+/// Returns the key Anki stores parameters of the FSRS version matching `params` under
+fn params_key(params: &[f64]) -> Option<&'static str> {
+    match params.len() {
+        17 => Some("fsrsWeights"),
+        19 => Some("fsrsParams5"),
+        21 => Some("fsrsParams6"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConflictPolicy;
+    use crate::{CardState, Deck, MemoryState, Note, basic_model};
+    use sqlx::{Pool, Sqlite};
+
+    #[test]
+    fn options_override_the_default_preset() {
+        let base = json!({
+            "id": 1,
+            "name": "Default",
+            "new": {"perDay": 20, "delays": [1, 10]},
+            "rev": {"perDay": 100},
+            "lapse": {"delays": [10]},
+        });
+        let config = DeckConfig::new(42, "FSRS")
+            .unwrap()
+            .new_per_day(5)
+            .relearning_steps(vec![5.0])
+            .desired_retention(0.8)
+            .unwrap()
+            .fsrs_params(vec![0.5; 19])
+            .unwrap()
+            .to_json(&base, 1000.0);
+        assert_eq!(config["id"], 42);
+        assert_eq!(config["name"], "FSRS");
+        assert_eq!(config["new"]["perDay"], 5);
+        assert_eq!(config["new"]["delays"], json!([1, 10]));
+        assert_eq!(config["rev"]["perDay"], 100);
+        assert_eq!(config["lapse"]["delays"], json!([5.0]));
+        assert_eq!(config["desiredRetention"], 0.8);
+        assert_eq!(config["fsrsParams5"], json!(vec![0.5; 19]));
+
+        let error = DeckConfig::new(1, "Default").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::ReservedDeckConfigId(1))
+        ));
+        let config = DeckConfig::new(42, "FSRS").unwrap();
+        assert!(config.clone().fsrs_params(vec![0.5; 18]).is_err());
+        assert!(config.desired_retention(0.0).is_err());
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn config_and_memory_state_are_written(pool: Pool<Sqlite>) {
+        let memory = MemoryState::new(30.25, 4.5)
+            .unwrap()
+            .desired_retention(0.85)
+            .unwrap();
+        let note = Note::new(basic_model(), vec!["Hund", "dog"])
+            .unwrap()
+            .card_state(0, CardState::new().memory_state(memory))
+            .unwrap();
+        let mut deck = Deck::new(2059400110, "FSRS", "");
+        deck.add_note(note);
+        deck.set_config(
+            DeckConfig::new(1675120101, "FSRS")
+                .unwrap()
+                .desired_retention(0.85)
+                .unwrap(),
+        );
+        let mut conn = pool.acquire().await.unwrap();
+        deck.write_to_db(&mut conn, 1425279151.0, ConflictPolicy::Overwrite)
+            .await
+            .unwrap();
+
+        let col = sqlx::query!("SELECT decks, dconf FROM col")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        let decks: Value = serde_json::from_str(&col.decks).unwrap();
+        assert_eq!(decks["2059400110"]["conf"], 1675120101);
+        let configs: Value = serde_json::from_str(&col.dconf).unwrap();
+        assert_eq!(configs["1675120101"]["desiredRetention"], 0.85);
+        assert_eq!(configs["1675120101"]["rev"]["maxIvl"], 36500);
+        assert_eq!(configs["1"]["name"], "Default");
+
+        let data = sqlx::query_scalar!("SELECT data FROM cards")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(data, r#"{"d":4.5,"dr":0.85,"s":30.25}"#);
+    }
+}
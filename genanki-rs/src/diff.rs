@@ -0,0 +1,513 @@
+use crate::apkg::{Apkg, ApkgNote};
+use crate::db_entries::ModelDbEntry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+/// Changes of a note which exists in both packages, matched by GUID
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NoteChange {
+    pub guid: String,
+    /// Names of the fields whose content changed
+    pub fields: Vec<String>,
+    /// Old and new tags, if they changed
+    pub tags: Option<(Vec<String>, Vec<String>)>,
+    /// Old and new deck name, if the note moved
+    pub deck: Option<(String, String)>,
+    /// Old and new model name, if the note changed its model
+    pub model: Option<(String, String)>,
+}
+
+/// Changes of a model which exists in both packages, matched by id
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelChange {
+    pub id: i64,
+    pub name: String,
+    /// Human readable descriptions of the changes, e.g. `css changed`
+    pub changes: Vec<String>,
+}
+
+/// Differences between two versions of a package, e.g. before publishing a regenerated deck.
+///
+/// Notes are matched by GUID, models by id and media files by name, so a diff is only meaningful
+/// if the generator keeps GUIDs and model ids stable. The `Display` implementation prints one line
+/// per change followed by a summary, which is suitable for CI logs.
+///
+/// Example:
+///
+/// ```rust,no_run
+/// use genanki_rs::{Apkg, PackageDiff};
+/// use anyhow::Result;
+///
+/// #[tokio::main] async fn main() -> Result<()> {
+///     let old = Apkg::open("published.apkg").await?;
+///     let new = Apkg::open("output.apkg").await?;
+///     let diff = PackageDiff::new(&old, &new);
+///     print!("{}", diff);
+///     if !diff.is_empty() {
+///         std::process::exit(1);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackageDiff {
+    pub added_notes: Vec<ApkgNote>,
+    pub removed_notes: Vec<ApkgNote>,
+    pub modified_notes: Vec<NoteChange>,
+    /// Names of the models only in the new package
+    pub added_models: Vec<String>,
+    /// Names of the models only in the old package
+    pub removed_models: Vec<String>,
+    pub modified_models: Vec<ModelChange>,
+    /// Names of the decks only in the new package
+    pub added_decks: Vec<String>,
+    /// Names of the decks only in the old package
+    pub removed_decks: Vec<String>,
+    pub added_media: Vec<String>,
+    pub removed_media: Vec<String>,
+    /// Names of the media files whose content changed
+    pub changed_media: Vec<String>,
+}
+
+impl PackageDiff {
+    /// Computes the changes from `old` to `new`
+    pub fn new(old: &Apkg, new: &Apkg) -> Self {
+        let mut diff = Self::default();
+
+        let old_notes = notes_by_guid(old);
+        let new_notes = notes_by_guid(new);
+        for (guid, &new_note) in &new_notes {
+            match old_notes.get(guid) {
+                Some(&old_note) => {
+                    let change = note_change(old, old_note, new, new_note);
+                    if change != NoteChange::default() {
+                        diff.modified_notes.push(NoteChange {
+                            guid: guid.to_string(),
+                            ..change
+                        });
+                    }
+                }
+                None => diff.added_notes.push(new_note.clone()),
+            }
+        }
+        diff.removed_notes = old_notes
+            .iter()
+            .filter(|(guid, _)| !new_notes.contains_key(*guid))
+            .map(|(_, &note)| note.clone())
+            .collect();
+
+        for (id, new_model) in &new.models {
+            match old.models.get(id) {
+                Some(old_model) => {
+                    let changes = model_changes(old_model, new_model);
+                    if !changes.is_empty() {
+                        diff.modified_models.push(ModelChange {
+                            id: *id,
+                            name: new_model.name.clone(),
+                            changes,
+                        });
+                    }
+                }
+                None => diff.added_models.push(new_model.name.clone()),
+            }
+        }
+        diff.removed_models = old
+            .models
+            .iter()
+            .filter(|(id, _)| !new.models.contains_key(*id))
+            .map(|(_, model)| model.name.clone())
+            .collect();
+
+        let old_decks = old
+            .decks
+            .values()
+            .map(|deck| &deck.name)
+            .collect::<Vec<_>>();
+        let new_decks = new
+            .decks
+            .values()
+            .map(|deck| &deck.name)
+            .collect::<Vec<_>>();
+        diff.added_decks = new_decks
+            .iter()
+            .filter(|name| !old_decks.contains(name))
+            .map(|name| name.to_string())
+            .collect();
+        diff.removed_decks = old_decks
+            .iter()
+            .filter(|name| !new_decks.contains(name))
+            .map(|name| name.to_string())
+            .collect();
+
+        let old_media = old
+            .media
+            .iter()
+            .map(|file| (file.name.as_str(), &file.data))
+            .collect::<BTreeMap<_, _>>();
+        for file in &new.media {
+            match old_media.get(file.name.as_str()) {
+                Some(&data) if *data != file.data => diff.changed_media.push(file.name.clone()),
+                Some(_) => {}
+                None => diff.added_media.push(file.name.clone()),
+            }
+        }
+        let new_media = new
+            .media
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        diff.removed_media = old_media
+            .keys()
+            .filter(|name| !new_media.contains(name))
+            .map(|name| name.to_string())
+            .collect();
+
+        diff
+    }
+
+    /// Returns true if both packages have the same content
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn notes_by_guid(apkg: &Apkg) -> BTreeMap<&str, &ApkgNote> {
+    apkg.notes
+        .iter()
+        .map(|note| (note.guid.as_str(), note))
+        .collect()
+}
+
+fn note_change(old: &Apkg, old_note: &ApkgNote, new: &Apkg, new_note: &ApkgNote) -> NoteChange {
+    let field_names = new
+        .models
+        .get(&new_note.model_id)
+        .map(|model| {
+            model
+                .flds
+                .iter()
+                .map(|field| field.name.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let field_count = old_note.fields.len().max(new_note.fields.len());
+    let fields = (0..field_count)
+        .filter(|&i| old_note.fields.get(i) != new_note.fields.get(i))
+        .map(|i| {
+            field_names
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| format!("field {}", i + 1))
+        })
+        .collect();
+
+    let deck_name = |apkg: &Apkg, note: &ApkgNote| {
+        apkg.deck_of_note(note)
+            .map(|deck| deck.name.clone())
+            .unwrap_or_default()
+    };
+    let model_name = |apkg: &Apkg, note: &ApkgNote| {
+        apkg.models
+            .get(&note.model_id)
+            .map(|model| model.name.clone())
+            .unwrap_or_else(|| note.model_id.to_string())
+    };
+    let changed = |old: String, new: String| (old != new).then_some((old, new));
+
+    NoteChange {
+        guid: String::new(),
+        fields,
+        tags: (old_note.tags != new_note.tags)
+            .then(|| (old_note.tags.clone(), new_note.tags.clone())),
+        deck: changed(deck_name(old, old_note), deck_name(new, new_note)),
+        model: (old_note.model_id != new_note.model_id)
+            .then(|| (model_name(old, old_note), model_name(new, new_note))),
+    }
+}
+
+fn model_changes(old: &ModelDbEntry, new: &ModelDbEntry) -> Vec<String> {
+    let mut changes = vec![];
+    if old.name != new.name {
+        changes.push(format!("renamed from {:?}", old.name));
+    }
+    if old.model_db_entry_type != new.model_db_entry_type {
+        changes.push("type changed".to_string());
+    }
+
+    let old_fields = old.flds.iter().map(|field| &field.name).collect::<Vec<_>>();
+    let new_fields = new.flds.iter().map(|field| &field.name).collect::<Vec<_>>();
+    for name in new_fields.iter().filter(|name| !old_fields.contains(name)) {
+        changes.push(format!("field {:?} added", name));
+    }
+    for name in old_fields.iter().filter(|name| !new_fields.contains(name)) {
+        changes.push(format!("field {:?} removed", name));
+    }
+    let common = |fields: &[&String], other: &[&String]| {
+        fields
+            .iter()
+            .filter(|name| other.contains(name))
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+    };
+    if common(&old_fields, &new_fields) != common(&new_fields, &old_fields) {
+        changes.push("fields reordered".to_string());
+    }
+    if old.sortf != new.sortf {
+        changes.push("sort field changed".to_string());
+    }
+
+    let old_templates = old
+        .tmpls
+        .iter()
+        .map(|template| (&template.name, template))
+        .collect::<HashMap<_, _>>();
+    for template in &new.tmpls {
+        let Some(old_template) = old_templates.get(&template.name) else {
+            changes.push(format!("template {:?} added", template.name));
+            continue;
+        };
+        let sides = [
+            ("front", &old_template.qfmt, &template.qfmt),
+            ("back", &old_template.afmt, &template.afmt),
+            ("browser front", &old_template.bqfmt, &template.bqfmt),
+            ("browser back", &old_template.bafmt, &template.bafmt),
+        ];
+        for (side, old, new) in sides {
+            if old != new {
+                changes.push(format!("template {:?} {} changed", template.name, side));
+            }
+        }
+    }
+    for template in &old.tmpls {
+        if !new.tmpls.iter().any(|new| new.name == template.name) {
+            changes.push(format!("template {:?} removed", template.name));
+        }
+    }
+
+    if old.css != new.css {
+        changes.push("css changed".to_string());
+    }
+    if old.latex_pre != new.latex_pre || old.latex_post != new.latex_post {
+        changes.push("LaTeX preamble changed".to_string());
+    }
+    changes
+}
+
+fn describe_note(note: &ApkgNote) -> String {
+    let first_field = note.fields.first().map(String::as_str).unwrap_or_default();
+    let mut preview = first_field.chars().take(40).collect::<String>();
+    if preview.len() < first_field.len() {
+        preview.push_str("...");
+    }
+    format!("note {} {:?}", note.guid, preview)
+}
+
+impl Display for PackageDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        for note in &self.added_notes {
+            writeln!(f, "+ {}", describe_note(note))?;
+        }
+        for note in &self.removed_notes {
+            writeln!(f, "- {}", describe_note(note))?;
+        }
+        for change in &self.modified_notes {
+            let mut parts = vec![];
+            if !change.fields.is_empty() {
+                parts.push(format!("fields {} changed", change.fields.join(", ")));
+            }
+            if let Some((old, new)) = &change.tags {
+                parts.push(format!("tags {:?} -> {:?}", old.join(" "), new.join(" ")));
+            }
+            if let Some((old, new)) = &change.deck {
+                parts.push(format!("deck {:?} -> {:?}", old, new));
+            }
+            if let Some((old, new)) = &change.model {
+                parts.push(format!("model {:?} -> {:?}", old, new));
+            }
+            writeln!(f, "~ note {}: {}", change.guid, parts.join("; "))?;
+        }
+        for name in &self.added_models {
+            writeln!(f, "+ model {:?}", name)?;
+        }
+        for name in &self.removed_models {
+            writeln!(f, "- model {:?}", name)?;
+        }
+        for change in &self.modified_models {
+            writeln!(
+                f,
+                "~ model {:?}: {}",
+                change.name,
+                change.changes.join("; ")
+            )?;
+        }
+        for name in &self.added_decks {
+            writeln!(f, "+ deck {:?}", name)?;
+        }
+        for name in &self.removed_decks {
+            writeln!(f, "- deck {:?}", name)?;
+        }
+        for name in &self.added_media {
+            writeln!(f, "+ media {}", name)?;
+        }
+        for name in &self.removed_media {
+            writeln!(f, "- media {}", name)?;
+        }
+        for name in &self.changed_media {
+            writeln!(f, "~ media {}", name)?;
+        }
+        writeln!(
+            f,
+            "notes: {} added, {} removed, {} modified; models: {} added, {} removed, {} modified; \
+             decks: {} added, {} removed; media: {} added, {} removed, {} changed",
+            self.added_notes.len(),
+            self.removed_notes.len(),
+            self.modified_notes.len(),
+            self.added_models.len(),
+            self.removed_models.len(),
+            self.modified_models.len(),
+            self.added_decks.len(),
+            self.removed_decks.len(),
+            self.added_media.len(),
+            self.removed_media.len(),
+            self.changed_media.len(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apkg::{ApkgCard, MediaFile};
+    use crate::db_entries::{DeckDbEntry, Fld, Tmpl};
+
+    fn package() -> Apkg {
+        let model = ModelDbEntry {
+            name: "Basic".to_string(),
+            id: "1".to_string(),
+            flds: vec![
+                Fld {
+                    name: "Front".to_string(),
+                    ..Default::default()
+                },
+                Fld {
+                    name: "Back".to_string(),
+                    ..Default::default()
+                },
+            ],
+            tmpls: vec![Tmpl {
+                name: "Card 1".to_string(),
+                qfmt: "{{Front}}".to_string(),
+                afmt: "{{Back}}".to_string(),
+                ..Default::default()
+            }],
+            css: ".card {}".to_string(),
+            ..Default::default()
+        };
+        let deck = DeckDbEntry {
+            id: 10,
+            name: "Words".to_string(),
+            ..Default::default()
+        };
+        let note = |id: i64, guid: &str, front: &str| ApkgNote {
+            id,
+            guid: guid.to_string(),
+            model_id: 1,
+            tags: vec!["a".to_string()],
+            fields: vec![front.to_string(), "back".to_string()],
+        };
+        let card = |id: i64| ApkgCard {
+            id,
+            note_id: id,
+            deck_id: 10,
+            ord: 0,
+            queue: 0,
+            due: 0,
+            flags: 0,
+        };
+        Apkg {
+            models: [(1, model)].into(),
+            decks: [(10, deck)].into(),
+            notes: vec![note(1, "g1", "Hund"), note(2, "g2", "Katze")],
+            cards: vec![card(1), card(2)],
+            media: vec![MediaFile {
+                name: "a.mp3".to_string(),
+                data: b"a".to_vec(),
+            }],
+        }
+    }
+
+    #[test]
+    fn identical_packages_have_no_differences() {
+        let diff = PackageDiff::new(&package(), &package());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no differences\n");
+    }
+
+    #[test]
+    fn reports_note_model_and_media_changes() {
+        let old = package();
+        let mut new = package();
+        new.notes[0].fields[1] = "dog".to_string();
+        new.notes[0].tags.push("b".to_string());
+        new.notes.remove(1);
+        new.notes.push(ApkgNote {
+            id: 3,
+            guid: "g3".to_string(),
+            model_id: 1,
+            tags: vec![],
+            fields: vec!["Maus".to_string(), "mouse".to_string()],
+        });
+        let model = new.models.get_mut(&1).unwrap();
+        model.css = ".card { color: red }".to_string();
+        model.tmpls[0].afmt = "{{FrontSide}}<hr>{{Back}}".to_string();
+        model.flds.push(Fld {
+            name: "Example".to_string(),
+            ..Default::default()
+        });
+        new.media[0].data = b"b".to_vec();
+        new.media.push(MediaFile {
+            name: "b.mp3".to_string(),
+            data: vec![],
+        });
+
+        let diff = PackageDiff::new(&old, &new);
+        assert_eq!(diff.added_notes[0].guid, "g3");
+        assert_eq!(diff.removed_notes[0].guid, "g2");
+        assert_eq!(
+            diff.modified_notes,
+            [NoteChange {
+                guid: "g1".to_string(),
+                fields: vec!["Back".to_string()],
+                tags: Some((
+                    vec!["a".to_string()],
+                    vec!["a".to_string(), "b".to_string()]
+                )),
+                deck: None,
+                model: None,
+            }]
+        );
+        assert_eq!(
+            diff.modified_models[0].changes,
+            [
+                "field \"Example\" added",
+                "template \"Card 1\" back changed",
+                "css changed"
+            ]
+        );
+        assert_eq!(diff.added_media, ["b.mp3"]);
+        assert_eq!(diff.changed_media, ["a.mp3"]);
+        assert!(diff.removed_media.is_empty());
+
+        let output = diff.to_string();
+        assert!(output.contains("+ note g3 \"Maus\"\n"));
+        assert!(output.contains("- note g2 \"Katze\"\n"));
+        assert!(output.contains("~ note g1: fields Back changed; tags \"a\" -> \"a b\"\n"));
+        assert!(output.ends_with(
+            "notes: 1 added, 1 removed, 1 modified; models: 0 added, 0 removed, 1 modified; \
+             decks: 0 added, 0 removed; media: 1 added, 0 removed, 1 changed\n"
+        ));
+    }
+}
@@ -38,6 +38,38 @@ pub enum Error {
     ModelFieldCountMismatch { model_len: usize, card_len: usize },
     #[error("One of the tags contains whitespace, this is not allowed!")]
     TagContainsWhitespace,
+    #[error("invalid tag {tag:?}: {reason}")]
+    InvalidTag { tag: String, reason: String },
+    #[error("a note with the GUID {0:?} was already written")]
+    DuplicateGuid(String),
+    #[error("model {model:?} has no field named {field:?}")]
+    UnknownField { model: String, field: String },
+    #[error("the input has no column named {0:?}")]
+    UnknownColumn(String),
+    #[error("no model named {0:?} is defined")]
+    UnknownModel(String),
+    #[error("the note has no card with ordinal {0}")]
+    NoSuchCard(i64),
+    #[error(
+        "a template places cards in deck {0}, which is neither part of the package nor the collection"
+    )]
+    UnknownDeck(i64),
+    #[error("invalid occlusion shape: {0}")]
+    InvalidShape(String),
+    #[error("invalid package: {0}")]
+    InvalidPackage(String),
+    #[error("invalid scheduling: {0}")]
+    InvalidScheduling(String),
+    #[error("deck config id {0} is reserved for the default preset")]
+    ReservedDeckConfigId(i64),
+    #[error(
+        "the fields or templates of model {model:?} changed, but {notes} notes in the collection still use the old ones"
+    )]
+    OutdatedNotes { model: String, notes: usize },
+    #[error("collection has schema version {0}, only version 11 is supported")]
+    UnsupportedCollection(i64),
+    #[error("AnkiConnect request failed: {0}")]
+    AnkiConnect(String),
     #[error(transparent)]
     Io(#[from] std::io::Error),
     /// Indicates an error with the underlying template system
@@ -58,6 +90,22 @@ pub enum Error {
     /// client code.
     #[error(transparent)]
     Zip(Box<dyn std::error::Error + Send + Sync>),
+    /// Indicates an error while reading CSV/TSV input
+    ///
+    /// Currently the argument is a `csv::Error`, but it is
+    /// cast to a Box<dyn std::error::Error> so that we can change
+    /// the underlying library in the future if needed without breaking
+    /// client code.
+    #[error(transparent)]
+    Csv(Box<dyn std::error::Error + Send + Sync>),
+    /// Indicates an invalid project manifest
+    ///
+    /// Currently the argument is a `toml::de::Error` or a `serde_json::Error`, but it is
+    /// cast to a Box<dyn std::error::Error> so that we can change
+    /// the underlying library in the future if needed without breaking
+    /// client code.
+    #[error(transparent)]
+    Manifest(Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl From<Infallible> for Error {
@@ -78,3 +126,11 @@ pub(crate) fn template_error(e: ramhorns::Error) -> Error {
 pub(crate) fn zip_error(e: ZipError) -> Error {
     Error::Zip(Box::new(e))
 }
+
+pub(crate) fn csv_error(e: csv::Error) -> Error {
+    Error::Csv(Box::new(e))
+}
+
+pub(crate) fn manifest_error(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Error::Manifest(Box::new(e))
+}
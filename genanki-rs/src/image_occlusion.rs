@@ -0,0 +1,349 @@
+use crate::Error;
+use crate::builtin_models::image_occlusion_model;
+use crate::note::Note;
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+
+/// A mask covering part of an image.
+///
+/// Coordinates are relative to the size of the image, so `0.0` is the left or top edge and `1.0`
+/// the right or bottom edge.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    /// Rectangle with its top left corner at `left`/`top`
+    Rect {
+        left: f64,
+        top: f64,
+        width: f64,
+        height: f64,
+    },
+    /// Ellipse inside the bounding box with its top left corner at `left`/`top` and radii `rx`
+    /// and `ry`
+    Ellipse {
+        left: f64,
+        top: f64,
+        rx: f64,
+        ry: f64,
+    },
+    /// Polygon through the given `(x, y)` points
+    Polygon { points: Vec<(f64, f64)> },
+}
+
+impl Shape {
+    /// Creates a rectangle
+    pub fn rect(left: f64, top: f64, width: f64, height: f64) -> Self {
+        Self::Rect {
+            left,
+            top,
+            width,
+            height,
+        }
+    }
+
+    /// Creates an ellipse
+    pub fn ellipse(left: f64, top: f64, rx: f64, ry: f64) -> Self {
+        Self::Ellipse { left, top, rx, ry }
+    }
+
+    /// Creates a polygon
+    pub fn polygon(points: Vec<(f64, f64)>) -> Self {
+        Self::Polygon { points }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let (coordinates, extents) = match self {
+            Shape::Rect {
+                left,
+                top,
+                width,
+                height,
+            } => (
+                vec![*left, *top, left + width, top + height],
+                vec![*width, *height],
+            ),
+            Shape::Ellipse { left, top, rx, ry } => (
+                vec![*left, *top, left + 2.0 * rx, top + 2.0 * ry],
+                vec![*rx, *ry],
+            ),
+            Shape::Polygon { points } => {
+                if points.len() < 3 {
+                    return Err(anyhow!(Error::InvalidShape(
+                        "a polygon needs at least 3 points".to_string()
+                    )));
+                }
+                (points.iter().flat_map(|&(x, y)| [x, y]).collect(), vec![])
+            }
+        };
+        if extents.iter().any(|&extent| extent <= 0.0) {
+            return Err(anyhow!(Error::InvalidShape(format!(
+                "{:?} has no area",
+                self
+            ))));
+        }
+        if coordinates
+            .iter()
+            .any(|coordinate| !(0.0..=1.0).contains(coordinate))
+        {
+            return Err(anyhow!(Error::InvalidShape(format!(
+                "{:?} is not inside the image",
+                self
+            ))));
+        }
+        Ok(())
+    }
+
+    fn markup(&self) -> String {
+        match self {
+            Shape::Rect {
+                left,
+                top,
+                width,
+                height,
+            } => format!(
+                "rect:left={}:top={}:width={}:height={}",
+                number(*left),
+                number(*top),
+                number(*width),
+                number(*height)
+            ),
+            Shape::Ellipse { left, top, rx, ry } => format!(
+                "ellipse:left={}:top={}:rx={}:ry={}",
+                number(*left),
+                number(*top),
+                number(*rx),
+                number(*ry)
+            ),
+            Shape::Polygon { points } => {
+                let left = points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+                let top = points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+                let points = points
+                    .iter()
+                    .map(|&(x, y)| format!("{},{}", number(x), number(y)))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!(
+                    "polygon:left={}:top={}:points={}",
+                    number(left),
+                    number(top),
+                    points
+                )
+            }
+        }
+    }
+}
+
+/// Formats a coordinate with the precision Anki uses
+fn number(value: f64) -> String {
+    let formatted = format!("{:.4}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Describes an image occlusion note: an image and groups of masks, where each group becomes one
+/// card.
+///
+/// Groups are numbered from 1 like the clozes of a cloze note. All shapes of a group are revealed
+/// together, e.g. the outline of a country split into several polygons.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{ImageOcclusion, Shape};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let note = ImageOcclusion::new("heart.png")
+///         .header("Chambers of the heart")
+///         .shape(1, Shape::rect(0.1, 0.2, 0.3, 0.1))
+///         .shape(2, Shape::ellipse(0.5, 0.5, 0.1, 0.05))
+///         .shape(2, Shape::polygon(vec![(0.7, 0.1), (0.9, 0.1), (0.8, 0.3)]))
+///         .to_note()?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The image itself has to be added to the media files of the `Package`.
+///
+/// The builder has the following default values:
+/// * `header` - `""`
+/// * `back_extra` - `""`
+/// * `comments` - `""`
+/// * `hide_all_guess_one` - `true`
+#[derive(Clone, Debug)]
+pub struct ImageOcclusion {
+    image: String,
+    header: String,
+    back_extra: String,
+    comments: String,
+    hide_all_guess_one: bool,
+    groups: BTreeMap<usize, Vec<Shape>>,
+}
+
+impl ImageOcclusion {
+    /// Creates an occlusion note for the media file named `image`
+    pub fn new(image: &str) -> Self {
+        Self {
+            image: image.to_string(),
+            header: String::new(),
+            back_extra: String::new(),
+            comments: String::new(),
+            hide_all_guess_one: true,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the header shown above the image
+    pub fn header(self, header: &str) -> Self {
+        Self {
+            header: header.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets the extra information shown on the back
+    pub fn back_extra(self, back_extra: &str) -> Self {
+        Self {
+            back_extra: back_extra.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets the comments, which are not shown on the cards
+    pub fn comments(self, comments: &str) -> Self {
+        Self {
+            comments: comments.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets whether all masks are shown while asking for one group ("Hide All, Guess One"), or
+    /// only the masks of the group asked for ("Hide One, Guess One")
+    pub fn hide_all_guess_one(self, hide_all_guess_one: bool) -> Self {
+        Self {
+            hide_all_guess_one,
+            ..self
+        }
+    }
+
+    /// Adds `shape` to the group `ordinal`, starting at 1
+    pub fn shape(mut self, ordinal: usize, shape: Shape) -> Self {
+        self.groups.entry(ordinal).or_default().push(shape);
+        self
+    }
+
+    /// Returns the content of the `Occlusion` field
+    ///
+    /// Returns `Err` if a group has the ordinal 0 or a shape is not inside the image
+    pub fn occlusion_markup(&self) -> Result<String> {
+        let mut clozes = vec![];
+        for (&ordinal, shapes) in &self.groups {
+            if ordinal == 0 {
+                return Err(anyhow!(Error::InvalidShape(
+                    "group ordinals start at 1".to_string()
+                )));
+            }
+            for shape in shapes {
+                shape.validate()?;
+                let mode = if self.hide_all_guess_one { ":oi=1" } else { "" };
+                clozes.push(format!(
+                    "{{{{c{}::image-occlusion:{}{}}}}}",
+                    ordinal,
+                    shape.markup(),
+                    mode
+                ));
+            }
+        }
+        Ok(clozes.join("<br>"))
+    }
+
+    /// Creates a `Note` of the [`image_occlusion_model`] with one card per group
+    ///
+    /// Returns `Err` if there are no shapes or a shape is invalid
+    pub fn to_note(&self) -> Result<Note> {
+        if self.groups.is_empty() {
+            return Err(anyhow!(Error::InvalidShape(
+                "an image occlusion note needs at least one shape".to_string()
+            )));
+        }
+        let occlusion = self.occlusion_markup()?;
+        let image = format!(
+            "<img src=\"{}\">",
+            self.image.replace('&', "&amp;").replace('"', "&quot;")
+        );
+        Note::new(
+            image_occlusion_model(),
+            vec![
+                &occlusion,
+                &image,
+                &self.header,
+                &self.back_extra,
+                &self.comments,
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markup_of_shapes() {
+        let occlusion = ImageOcclusion::new("map.png")
+            .shape(1, Shape::rect(0.1, 0.25, 0.3, 0.125))
+            .shape(2, Shape::ellipse(0.5, 0.5, 0.1, 0.05))
+            .shape(2, Shape::polygon(vec![(0.7, 0.2), (0.9, 0.1), (0.8, 0.3)]));
+        assert_eq!(
+            occlusion.occlusion_markup().unwrap(),
+            "{{c1::image-occlusion:rect:left=0.1:top=0.25:width=0.3:height=0.125:oi=1}}<br>\
+             {{c2::image-occlusion:ellipse:left=0.5:top=0.5:rx=0.1:ry=0.05:oi=1}}<br>\
+             {{c2::image-occlusion:polygon:left=0.7:top=0.1:points=0.7,0.2 0.9,0.1 0.8,0.3:oi=1}}"
+        );
+        assert_eq!(
+            occlusion
+                .hide_all_guess_one(false)
+                .occlusion_markup()
+                .unwrap(),
+            "{{c1::image-occlusion:rect:left=0.1:top=0.25:width=0.3:height=0.125}}<br>\
+             {{c2::image-occlusion:ellipse:left=0.5:top=0.5:rx=0.1:ry=0.05}}<br>\
+             {{c2::image-occlusion:polygon:left=0.7:top=0.1:points=0.7,0.2 0.9,0.1 0.8,0.3}}"
+        );
+    }
+
+    #[test]
+    fn one_card_per_group() {
+        let note = ImageOcclusion::new("heart.png")
+            .header("Heart")
+            .shape(1, Shape::rect(0.0, 0.0, 0.5, 0.5))
+            .shape(3, Shape::rect(0.5, 0.5, 0.5, 0.5))
+            .shape(3, Shape::rect(0.0, 0.5, 0.5, 0.5))
+            .to_note()
+            .unwrap();
+        let mut ords = note.cards().iter().map(|card| card.ord).collect::<Vec<_>>();
+        ords.sort();
+        assert_eq!(ords, [0, 2]);
+        assert_eq!(note.fields()[1], "<img src=\"heart.png\">");
+        assert_eq!(note.fields()[2], "Heart");
+    }
+
+    #[test]
+    fn invalid_shapes_are_rejected() {
+        let invalid = [
+            (1, Shape::rect(0.8, 0.0, 0.5, 0.5)),
+            (1, Shape::rect(0.1, 0.1, 0.0, 0.5)),
+            (1, Shape::polygon(vec![(0.1, 0.1), (0.2, 0.2)])),
+            (0, Shape::rect(0.1, 0.1, 0.1, 0.1)),
+        ];
+        for (ordinal, shape) in invalid {
+            assert!(
+                ImageOcclusion::new("a.png")
+                    .shape(ordinal, shape)
+                    .to_note()
+                    .is_err()
+            );
+        }
+        assert!(ImageOcclusion::new("a.png").to_note().is_err());
+    }
+}
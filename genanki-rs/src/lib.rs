@@ -188,26 +188,96 @@
 //! `sort_field_index` to change the sort field. `0` means the first field in
 //! the Note, `1` means the second, etc.
 //!
+//! ## Optional features
+//!
+//! * `markdown` - [`MarkdownConverter`] to write field contents in Markdown
+//! * `cli` - the `genanki` binary, which builds, inspects, validates, diffs and converts packages:
+//!   `cargo install genanki-rs --features cli`
+//! * `derive` - `#[derive(AnkiNote)]` to generate a model and notes from a struct, see [`AnkiNote`]
+//! * `ankiconnect` - [`AnkiConnect`] client to push decks into a running Anki
+//! * `media-processing` - [`MediaProcessor`] to resize and re-encode images and to downmix and
+//!   resample WAV files before packaging
+//!
 
+mod anki_note;
+mod anki_text;
+#[cfg(feature = "ankiconnect")]
+mod ankiconnect;
+mod apkg;
 mod builders;
 mod builtin_models;
 mod card;
+mod collection;
+mod conflict;
+mod crowdanki;
+mod csv_import;
 mod db_entries;
 mod deck;
+mod deck_config;
+mod diff;
 mod error;
+mod image_occlusion;
+#[cfg(feature = "markdown")]
+mod markdown;
+mod math;
+mod media;
+mod media_dedupe;
+#[cfg(feature = "media-processing")]
+mod media_processing;
+mod migration;
 mod model;
 mod note;
 mod package;
+mod project;
+mod render;
+mod sanitize;
+mod scheduling;
+mod tags;
 mod util;
+mod validation;
 
+// Lets the code generated by `#[derive(AnkiNote)]` refer to `::genanki_rs` inside this crate
+extern crate self as genanki_rs;
+
+pub use anki_note::AnkiNote;
+pub use anki_text::AnkiTextImporter;
+#[cfg(feature = "ankiconnect")]
+pub use ankiconnect::{AnkiConnect, PushSummary};
 pub use anyhow::Result;
-pub use builders::{Field, Template};
+pub use apkg::{Apkg, ApkgCard, ApkgNote, MediaFile};
+pub use builders::{Field, NoteBuilder, Template, Tts};
 pub use builtin_models::*;
+pub use card::{CardState, Flag};
+pub use collection::Collection;
+pub use conflict::ConflictPolicy;
+pub use csv_import::{Column, CsvImport, CsvImporter, ImportedNote, RowError};
+pub use db_entries::{DeckDbEntry, Fld, ModelDbEntry, Tmpl};
 pub use deck::Deck;
+pub use deck_config::DeckConfig;
+pub use diff::{ModelChange, NoteChange, PackageDiff};
 pub use error::Error;
+#[cfg(feature = "derive")]
+pub use genanki_rs_derive::AnkiNote;
+pub use image_occlusion::{ImageOcclusion, Shape};
+#[cfg(feature = "markdown")]
+pub use markdown::MarkdownConverter;
+pub use math::{MathKind, MathSnippet, check_math, math_snippets};
+pub use media::media_references;
+pub use media_dedupe::{DedupeReport, DuplicateMedia};
+#[cfg(feature = "media-processing")]
+pub use media_processing::{
+    AudioPolicy, ImageEncoding, ImagePolicy, MediaProcessor, MediaReport, ProcessedMedia,
+};
+pub use migration::{ModelMigration, SchemaChange};
 pub use model::{Model, ModelType};
 pub use note::Note;
 pub use package::Package;
+pub use project::Project;
+pub use render::CardPreview;
+pub use sanitize::HtmlSanitizer;
+pub use scheduling::{MemoryState, Rating, Review, ReviewKind, Scheduling};
+pub use tags::{TAG_SEPARATOR, normalize_tags, tag_hierarchy, validate_tag};
+pub use validation::{Issue, Severity, ValidationReport};
 
 #[cfg(test)]
 mod tests {
@@ -880,4 +950,224 @@ def latex(col, key):
             )));
         });
     }
+
+    #[sqlx::test(fixtures("anki"))]
+    #[serial]
+    async fn cloze_with_back_extra(pool: Pool<Sqlite>) {
+        let mut deck = Deck::new(1550428390, "foodeck", "");
+        deck.add_note(
+            Note::new(
+                cloze_with_back_extra_model(),
+                vec!["{{c1::Rome}} is the capital of {{c2::Italy}}", "since 1871"],
+            )
+            .unwrap(),
+        );
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut test_tear_up = TestTearUp::new(&pool).await;
+
+        test_tear_up.write_to_db(&mut package, None).await.unwrap();
+        let out_file = test_tear_up.write_to_zip(&mut package, false).unwrap();
+
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            setup.import_package(out_file).unwrap();
+            assert!(setup.check_col("len(col.find_cards('')) == 2"));
+            assert!(
+                setup.check_col("'since 1871' in col.get_card(col.find_cards('')[0]).answer()")
+            );
+        });
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    #[serial]
+    async fn type_in_the_answer_reversed(pool: Pool<Sqlite>) {
+        let mut deck = Deck::new(1171932208, "foodeck", "");
+        deck.add_note(
+            Note::new(
+                basic_type_in_the_answer_reversed_model(),
+                vec!["Japan", "Tokyo"],
+            )
+            .unwrap(),
+        );
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut test_tear_up = TestTearUp::new(&pool).await;
+
+        test_tear_up.write_to_db(&mut package, None).await.unwrap();
+        let out_file = test_tear_up.write_to_zip(&mut package, false).unwrap();
+
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            setup.import_package(out_file).unwrap();
+            assert!(
+                setup
+                    .check_col("sorted(col.get_card(i).ord for i in col.find_cards('')) == [0, 1]")
+            );
+        });
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    #[serial]
+    async fn vocabulary_model(pool: Pool<Sqlite>) {
+        let mut deck = Deck::new(1736452841, "foodeck", "");
+        deck.add_note(
+            Note::new(
+                VocabularyModel::new().build(),
+                vec!["Hund", "dog", "Der Hund bellt.", "", "", "der", "noun"],
+            )
+            .unwrap(),
+        );
+        deck.add_note(
+            Note::new(
+                VocabularyModel::new()
+                    .example(false)
+                    .audio(false)
+                    .image(false)
+                    .reversed(false)
+                    .build(),
+                vec!["Katze", "cat", "die", "noun"],
+            )
+            .unwrap(),
+        );
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut test_tear_up = TestTearUp::new(&pool).await;
+
+        test_tear_up.write_to_db(&mut package, None).await.unwrap();
+        let out_file = test_tear_up.write_to_zip(&mut package, false).unwrap();
+
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            setup.import_package(out_file).unwrap();
+            assert!(setup.check_col("len(col.find_cards('')) == 3"));
+            assert!(
+                setup.check_col(
+                    "'gender-der' in col.get_card(col.find_cards('Hund')[0]).question()"
+                )
+            );
+        });
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    #[serial]
+    async fn template_deck_override(pool: Pool<Sqlite>) {
+        let reverse_deck = Deck::new(2059400111, "Lang::Reverse", "");
+        let model = Model::new(
+            1516284204,
+            "Reversed Model",
+            vec![Field::new("Front"), Field::new("Back")],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Front}}")
+                    .afmt("{{FrontSide}}<hr id=answer>{{Back}}")
+                    .bqfmt("{{Front}} (browser)"),
+                Template::new("Card 2")
+                    .qfmt("{{Back}}")
+                    .afmt("{{FrontSide}}<hr id=answer>{{Front}}")
+                    .deck(&reverse_deck),
+            ],
+        );
+        let mut deck = Deck::new(2059400110, "Lang", "");
+        deck.add_note(Note::new(model, vec!["Hund", "dog"]).unwrap());
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut test_tear_up = TestTearUp::new(&pool).await;
+
+        test_tear_up.write_to_db(&mut package, None).await.unwrap();
+        let out_file = test_tear_up.write_to_zip(&mut package, false).unwrap();
+
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            setup.import_package(out_file).unwrap();
+            assert!(setup.check_col(
+                "sorted((c.ord, col.decks.name(c.did)) for c in map(col.get_card, col.find_cards(''))) \
+                 == [(0, 'Lang'), (1, 'Lang::Reverse')]"
+            ));
+            assert!(setup.check_col(
+                "col.models.by_name('Reversed Model')['tmpls'][0]['bqfmt'] == '{{Front}} (browser)'"
+            ));
+        });
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    #[serial]
+    async fn scheduled_cards_with_review_history(pool: Pool<Sqlite>) {
+        let state = CardState::new()
+            .scheduling(Scheduling::Review {
+                due: 1_900_000_000,
+                interval: 21,
+                ease: 2.2,
+            })
+            .reps(2)
+            .review(Review::new(1_700_000_000_000, Rating::Good).kind(ReviewKind::Learning))
+            .review(
+                Review::new(1_700_086_400_000, Rating::Good)
+                    .interval(21)
+                    .ease(2.2),
+            );
+        let note = Note::new(basic_model(), vec!["Hund", "dog"])
+            .unwrap()
+            .card_state(0, state)
+            .unwrap();
+        let mut deck = Deck::new(2059400110, "Scheduled", "");
+        deck.add_note(note);
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut test_tear_up = TestTearUp::new(&pool).await;
+
+        test_tear_up.write_to_db(&mut package, None).await.unwrap();
+        let out_file = test_tear_up.write_to_zip(&mut package, false).unwrap();
+
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            setup.import_package(out_file).unwrap();
+            assert!(setup.check_col(
+                "[(c.type, c.queue, c.ivl, c.factor, c.reps) for c in map(col.get_card, col.find_cards(''))] \
+                 == [(2, 2, 21, 2200, 2)]"
+            ));
+            assert!(setup.check_col("col.db.scalar('select count() from revlog') == 2"));
+        });
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    #[serial]
+    async fn fsrs_memory_state(pool: Pool<Sqlite>) {
+        let memory = MemoryState::new(30.25, 4.5)
+            .unwrap()
+            .desired_retention(0.85)
+            .unwrap();
+        let state = CardState::new()
+            .scheduling(Scheduling::Review {
+                due: 1_900_000_000,
+                interval: 30,
+                ease: 2.5,
+            })
+            .memory_state(memory);
+        let note = Note::new(basic_model(), vec!["Hund", "dog"])
+            .unwrap()
+            .card_state(0, state)
+            .unwrap();
+        let mut deck = Deck::new(2059400110, "FSRS", "");
+        deck.add_note(note);
+        deck.set_config(
+            DeckConfig::new(1675120101, "FSRS")
+                .unwrap()
+                .desired_retention(0.85)
+                .unwrap(),
+        );
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+        let mut test_tear_up = TestTearUp::new(&pool).await;
+
+        test_tear_up.write_to_db(&mut package, None).await.unwrap();
+        let out_file = test_tear_up.write_to_zip(&mut package, false).unwrap();
+
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            setup.import_package(out_file).unwrap();
+            assert!(setup.check_col(
+                "[(round(c.memory_state.stability, 2), round(c.memory_state.difficulty, 2), \
+                 round(c.desired_retention, 2)) for c in map(col.get_card, col.find_cards(''))] \
+                 == [(30.25, 4.5, 0.85)]"
+            ));
+            assert!(setup.check_col(
+                "col.decks.config_dict_for_deck_id(col.decks.id_for_name('FSRS'))['desiredRetention'] == 0.85"
+            ));
+        });
+    }
 }
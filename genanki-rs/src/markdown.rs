@@ -0,0 +1,292 @@
+use crate::Error;
+use crate::model::Model;
+use anyhow::{Result, anyhow};
+use fancy_regex::Regex;
+use pulldown_cmark::{Options, Parser, html};
+use std::sync::LazyLock;
+
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+/// Precedes the placeholder characters and itself where they occur in the input
+const PLACEHOLDER_ESCAPE: char = '\u{E002}';
+
+static MATH: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)\\\(.*?\\\)|\\\[.*?\\\]").expect("static regex"));
+
+/// Converts Markdown into the HTML expected in `Note` fields.
+///
+/// The converter understands CommonMark plus tables and strikethrough. Fenced code blocks keep
+/// their language as a `language-*` class on the `<code>` element, so highlighters like
+/// highlight.js or Prism can be loaded from the card template. MathJax snippets written as
+/// `\(...\)` or `\[...\]` are passed through untouched instead of being treated as escaped
+/// brackets.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, MarkdownConverter, Note};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let converter = MarkdownConverter::new();
+///     let fields = converter.convert_fields(
+///         &basic_model(),
+///         vec!["What is **2 + 2**?", r"It is \(2^2\)"],
+///         &["Front", "Back"],
+///     )?;
+///     assert_eq!(fields[0], "What is <strong>2 + 2</strong>?");
+///     assert_eq!(fields[1], r"It is \(2^2\)");
+///
+///     let note = Note::new(basic_model(), fields.iter().map(String::as_str).collect())?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The converter has the following default values:
+/// * `tables` - `true`
+/// * `math` - `true`
+/// * `unwrap_paragraph` - `true`
+#[derive(Clone)]
+pub struct MarkdownConverter {
+    tables: bool,
+    math: bool,
+    unwrap_paragraph: bool,
+}
+
+impl Default for MarkdownConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarkdownConverter {
+    /// Creates a new converter with the default options
+    pub fn new() -> Self {
+        Self {
+            tables: true,
+            math: true,
+            unwrap_paragraph: true,
+        }
+    }
+
+    /// Sets whether GitHub flavored tables and strikethrough are recognized
+    pub fn tables(self, tables: bool) -> Self {
+        Self { tables, ..self }
+    }
+
+    /// Sets whether `\(...\)` and `\[...\]` are passed through for MathJax
+    pub fn math(self, math: bool) -> Self {
+        Self { math, ..self }
+    }
+
+    /// Sets whether the `<p>` around output consisting of a single paragraph is removed
+    pub fn unwrap_paragraph(self, unwrap_paragraph: bool) -> Self {
+        Self {
+            unwrap_paragraph,
+            ..self
+        }
+    }
+
+    /// Converts `markdown` to HTML
+    pub fn convert(&self, markdown: &str) -> String {
+        let mut math_snippets = vec![];
+        let markdown = if self.math {
+            MATH.replace_all(
+                &escape_placeholders(markdown),
+                |caps: &fancy_regex::Captures| {
+                    math_snippets.push(escape_html(&caps[0]));
+                    format!(
+                        "{}{}{}",
+                        PLACEHOLDER_START,
+                        math_snippets.len() - 1,
+                        PLACEHOLDER_END
+                    )
+                },
+            )
+            .into_owned()
+        } else {
+            markdown.to_string()
+        };
+
+        let mut options = Options::empty();
+        if self.tables {
+            options.insert(Options::ENABLE_TABLES);
+            options.insert(Options::ENABLE_STRIKETHROUGH);
+        }
+        let mut rendered = String::new();
+        html::push_html(&mut rendered, Parser::new_ext(&markdown, options));
+
+        for (i, snippet) in math_snippets.iter().enumerate() {
+            rendered = rendered.replace(
+                &format!("{}{}{}", PLACEHOLDER_START, i, PLACEHOLDER_END),
+                snippet,
+            );
+        }
+        if self.math {
+            rendered = unescape_placeholders(&rendered);
+        }
+
+        let rendered = rendered.trim_end();
+        if self.unwrap_paragraph
+            && let Some(inner) = rendered
+                .strip_prefix("<p>")
+                .and_then(|rest| rest.strip_suffix("</p>"))
+            && !inner.contains("<p>")
+        {
+            return inner.to_string();
+        }
+        rendered.to_string()
+    }
+
+    /// Converts the values of the fields named in `markdown_fields` and leaves all others as they
+    /// are, so the result can be passed to `Note::new`.
+    ///
+    /// `fields` are given in the order of the fields of `model`.
+    ///
+    /// Returns `Err` if `model` has no field with one of the names in `markdown_fields`
+    pub fn convert_fields(
+        &self,
+        model: &Model,
+        fields: Vec<&str>,
+        markdown_fields: &[&str],
+    ) -> Result<Vec<String>> {
+        let model_fields = model.fields();
+        for &name in markdown_fields {
+            if !model_fields.iter().any(|field| field.name == name) {
+                return Err(anyhow!(Error::UnknownField {
+                    model: model.name().to_string(),
+                    field: name.to_string(),
+                }));
+            }
+        }
+        Ok(fields
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| match model_fields.get(i) {
+                Some(field) if markdown_fields.contains(&field.name.as_str()) => {
+                    self.convert(value)
+                }
+                _ => value.to_string(),
+            })
+            .collect())
+    }
+}
+
+/// Escapes the characters used for placeholders, so placeholders only come from math snippets
+fn escape_placeholders(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            PLACEHOLDER_START => escaped.push_str("\u{E002}0"),
+            PLACEHOLDER_END => escaped.push_str("\u{E002}1"),
+            PLACEHOLDER_ESCAPE => escaped.push_str("\u{E002}2"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_placeholders(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != PLACEHOLDER_ESCAPE {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('0') => unescaped.push(PLACEHOLDER_START),
+            Some('1') => unescaped.push(PLACEHOLDER_END),
+            Some('2') | None => unescaped.push(PLACEHOLDER_ESCAPE),
+            Some(c) => {
+                unescaped.push(PLACEHOLDER_ESCAPE);
+                unescaped.push(c);
+            }
+        }
+    }
+    unescaped
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_model;
+
+    #[test]
+    fn single_paragraph_is_unwrapped() {
+        let converter = MarkdownConverter::new();
+        assert_eq!(converter.convert("*Paris*"), "<em>Paris</em>");
+        assert_eq!(
+            converter.unwrap_paragraph(false).convert("*Paris*"),
+            "<p><em>Paris</em></p>"
+        );
+    }
+
+    #[test]
+    fn multiple_paragraphs_are_kept() {
+        assert_eq!(
+            MarkdownConverter::new().convert("one\n\ntwo"),
+            "<p>one</p>\n<p>two</p>"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let rendered = MarkdownConverter::new().convert("| a | b |\n|---|---|\n| 1 | 2 |");
+        assert!(rendered.starts_with("<table>"));
+        assert!(rendered.contains("<td>2</td>"));
+        let rendered = MarkdownConverter::new()
+            .tables(false)
+            .convert("| a | b |\n|---|---|\n| 1 | 2 |");
+        assert!(!rendered.contains("<table>"));
+    }
+
+    #[test]
+    fn code_blocks_have_language_class() {
+        let rendered = MarkdownConverter::new().convert("```rust\nlet a = 1 < 2;\n```");
+        assert_eq!(
+            rendered,
+            "<pre><code class=\"language-rust\">let a = 1 &lt; 2;\n</code></pre>"
+        );
+    }
+
+    #[test]
+    fn math_is_passed_through() {
+        let converter = MarkdownConverter::new();
+        assert_eq!(
+            converter.convert(r"Solve \(a_1 < b_2\) for *x*"),
+            r"Solve \(a_1 &lt; b_2\) for <em>x</em>"
+        );
+        assert_eq!(
+            converter.convert("\\[\n\\sum_{i=1}^n i\n\\]"),
+            "\\[\n\\sum_{i=1}^n i\n\\]"
+        );
+        assert_eq!(converter.math(false).convert(r"\(x\)"), "(x)");
+    }
+
+    #[test]
+    fn placeholder_characters_in_the_input_are_kept() {
+        let input = "\u{E000}0\u{E001} \u{E002}1 \\(x\\)";
+        assert_eq!(MarkdownConverter::new().convert(input), input);
+    }
+
+    #[test]
+    fn convert_fields_only_touches_selected_fields() {
+        let fields = MarkdownConverter::new()
+            .convert_fields(&basic_model(), vec!["**a**", "**b**"], &["Back"])
+            .unwrap();
+        assert_eq!(fields, ["**a**", "<strong>b</strong>"]);
+        assert!(
+            MarkdownConverter::new()
+                .convert_fields(&basic_model(), vec!["a", "b"], &["Answer"])
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use crate::validation::ValidationReport;
+use fancy_regex::Regex;
+use std::sync::LazyLock;
+
+/// Commands Anki refuses to pass to LaTeX for security reasons
+const FORBIDDEN_COMMANDS: &[&str] = &[
+    "\\write18",
+    "\\readline",
+    "\\input",
+    "\\include",
+    "\\catcode",
+    "\\openout",
+    "\\write",
+    "\\loop",
+    "\\def",
+    "\\shipout",
+];
+
+static CLOZE_START: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{c\d+::").expect("static regex"));
+static LEFT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\\left(?![a-zA-Z])").expect("static regex"));
+static RIGHT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\\right(?![a-zA-Z])").expect("static regex"));
+static ENVIRONMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\\(begin|end)\s*\{([^}]*)\}").expect("static regex"));
+
+const DELIMITERS: &[(MathKind, &str, &str)] = &[
+    (MathKind::Latex, "[latex]", "[/latex]"),
+    (MathKind::LatexDisplay, "[$$]", "[/$$]"),
+    (MathKind::LatexInline, "[$]", "[/$]"),
+    (MathKind::MathJaxInline, "\\(", "\\)"),
+    (MathKind::MathJaxDisplay, "\\[", "\\]"),
+];
+
+/// The syntax a [`MathSnippet`] is written in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathKind {
+    /// `[latex]...[/latex]`, rendered by LaTeX between the `latex_pre` and `latex_post` of the
+    /// model
+    Latex,
+    /// `[$]...[/$]`, rendered by LaTeX as inline math
+    LatexInline,
+    /// `[$$]...[/$$]`, rendered by LaTeX as display math
+    LatexDisplay,
+    /// `\(...\)`, rendered by MathJax as inline math
+    MathJaxInline,
+    /// `\[...\]`, rendered by MathJax as display math
+    MathJaxDisplay,
+}
+
+impl MathKind {
+    fn is_latex(self) -> bool {
+        matches!(self, Self::Latex | Self::LatexInline | Self::LatexDisplay)
+    }
+}
+
+/// LaTeX or MathJax content of a note field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MathSnippet {
+    pub kind: MathKind,
+    /// The content between the delimiters
+    pub content: String,
+}
+
+/// Returns all LaTeX and MathJax snippets in a note `field`, in order of appearance.
+///
+/// Snippets without a closing delimiter are skipped, see [`check_math`].
+pub fn math_snippets(field: &str) -> Vec<MathSnippet> {
+    scan(field).0
+}
+
+/// Checks the LaTeX and MathJax snippets in a note `field` for problems which would make them
+/// render broken, returning a message for every problem found.
+///
+/// The checks need no TeX installation: they cover unbalanced delimiters, braces, environments
+/// and `\left`/`\right` pairs, nested and empty snippets, `$` inside LaTeX math, preambles and
+/// commands Anki refuses to render, and `}}` inside math which ends cloze deletions early.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::check_math;
+///
+/// assert!(check_math(r"[$]\frac{1}{2}[/$] and \(x^2\)").is_empty());
+/// assert_eq!(
+///     check_math(r"\(\frac{1}{2\)"),
+///     [r"\(...\) has unbalanced braces"]
+/// );
+/// ```
+pub fn check_math(field: &str) -> Vec<String> {
+    let (snippets, mut problems) = scan(field);
+    let is_cloze = CLOZE_START.is_match(field).unwrap_or(false);
+    for snippet in &snippets {
+        problems.extend(
+            check_snippet(snippet, is_cloze)
+                .into_iter()
+                .map(|problem| format!("{} {}", describe(snippet.kind), problem)),
+        );
+    }
+    problems
+}
+
+/// Records the problems of [`check_math`] in all `fields` of a note as warnings, naming each field
+/// by its entry in `field_names`
+pub(super) fn report_math_problems<'a>(
+    report: &mut ValidationReport,
+    location: &str,
+    field_names: impl IntoIterator<Item = &'a str>,
+    fields: &[String],
+) {
+    for (name, field) in field_names.into_iter().zip(fields) {
+        for problem in check_math(field) {
+            report.warning(location, format!("field {:?}: {}", name, problem));
+        }
+    }
+}
+
+/// Finds the snippets of `field` along with problems of unbalanced delimiters
+fn scan(field: &str) -> (Vec<MathSnippet>, Vec<String>) {
+    let lowercase = field.to_ascii_lowercase();
+    let mut snippets = vec![];
+    let mut problems = vec![];
+    let mut position = 0;
+    loop {
+        let next = DELIMITERS
+            .iter()
+            .flat_map(|delimiter @ (_, open, close)| {
+                [
+                    find_token(&lowercase, position, open).map(|i| (i, delimiter, true)),
+                    find_token(&lowercase, position, close).map(|i| (i, delimiter, false)),
+                ]
+            })
+            .flatten()
+            .min_by_key(|(i, _, _)| *i);
+        let Some((start, &(kind, open, close), is_open)) = next else {
+            break;
+        };
+        if !is_open {
+            problems.push(format!("{} has no matching {}", close, open));
+            position = start + close.len();
+            continue;
+        }
+        let content_start = start + open.len();
+        match find_token(&lowercase, content_start, close) {
+            Some(end) => {
+                snippets.push(MathSnippet {
+                    kind,
+                    content: field[content_start..end].to_string(),
+                });
+                position = end + close.len();
+            }
+            None => {
+                problems.push(format!("{} is never closed", open));
+                position = content_start;
+            }
+        }
+    }
+    (snippets, problems)
+}
+
+/// Finds `token` in `text` starting at `from`, skipping MathJax delimiters escaped by another
+/// backslash like the line break `\\[2pt]`
+fn find_token(text: &str, from: usize, token: &str) -> Option<usize> {
+    text[from..]
+        .match_indices(token)
+        .map(|(i, _)| from + i)
+        .find(|&i| !token.starts_with('\\') || i == 0 || text.as_bytes()[i - 1] != b'\\')
+}
+
+fn describe(kind: MathKind) -> &'static str {
+    match kind {
+        MathKind::Latex => "[latex]...[/latex]",
+        MathKind::LatexInline => "[$]...[/$]",
+        MathKind::LatexDisplay => "[$$]...[/$$]",
+        MathKind::MathJaxInline => "\\(...\\)",
+        MathKind::MathJaxDisplay => "\\[...\\]",
+    }
+}
+
+fn check_snippet(snippet: &MathSnippet, is_cloze: bool) -> Vec<String> {
+    let content = snippet.content.as_str();
+    let mut problems = vec![];
+    if content.trim().is_empty() {
+        problems.push("is empty".to_string());
+        return problems;
+    }
+
+    let nested = DELIMITERS
+        .iter()
+        .filter(|(kind, _, _)| kind.is_latex() == snippet.kind.is_latex())
+        .find(|(_, open, _)| find_token(&content.to_ascii_lowercase(), 0, open).is_some());
+    if let Some((_, open, _)) = nested {
+        problems.push(format!("contains a nested {}", open));
+    }
+    if !braces_balanced(content) {
+        problems.push("has unbalanced braces".to_string());
+    }
+    problems.extend(check_environments(content));
+    if LEFT.find_iter(content).count() != RIGHT.find_iter(content).count() {
+        problems.push("has unbalanced \\left and \\right".to_string());
+    }
+
+    if snippet.kind.is_latex() {
+        if let Some(command) = FORBIDDEN_COMMANDS
+            .iter()
+            .find(|command| content.contains(*command))
+        {
+            problems.push(format!(
+                "contains {}, which Anki refuses to render",
+                command
+            ));
+        }
+        if content.contains("\\documentclass") || content.contains("\\begin{document}") {
+            problems.push(
+                "contains a preamble, which is added by the latex_pre of the model".to_string(),
+            );
+        }
+        if snippet.kind != MathKind::Latex && has_unescaped_dollar(content) {
+            problems.push("contains $, which ends math mode early".to_string());
+        }
+    } else if is_cloze && closes_cloze(content) {
+        problems.push(
+            "contains }}, which ends the cloze deletion early; add a space between the braces"
+                .to_string(),
+        );
+    }
+    problems
+}
+
+fn braces_balanced(content: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => return false,
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+fn check_environments(content: &str) -> Vec<String> {
+    let mut problems = vec![];
+    let mut open: Vec<&str> = vec![];
+    for captures in ENVIRONMENT.captures_iter(content).filter_map(|c| c.ok()) {
+        let name = captures.get(2).expect("group 2").as_str();
+        if &captures[1] == "begin" {
+            open.push(name);
+        } else if open.last() == Some(&name) {
+            open.pop();
+        } else {
+            problems.push(format!("has \\end{{{}}} without matching \\begin", name));
+            return problems;
+        }
+    }
+    for name in open {
+        problems.push(format!("has \\begin{{{}}} without matching \\end", name));
+    }
+    problems
+}
+
+fn has_unescaped_dollar(content: &str) -> bool {
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '$' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Returns `true` if `content` contains more `}}` than cloze deletions started inside it
+fn closes_cloze(content: &str) -> bool {
+    let started = CLOZE_START.find_iter(content).count();
+    content.matches("}}").count() > started
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Deck, Note, Package, basic_model};
+
+    #[test]
+    fn snippets_of_all_kinds() {
+        let snippets = math_snippets(r"[LaTeX]a[/latex] [$]b[/$] [$$]c[/$$] \(d\) \[e\]");
+        assert_eq!(
+            snippets
+                .iter()
+                .map(|s| (s.kind, s.content.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (MathKind::Latex, "a"),
+                (MathKind::LatexInline, "b"),
+                (MathKind::LatexDisplay, "c"),
+                (MathKind::MathJaxInline, "d"),
+                (MathKind::MathJaxDisplay, "e"),
+            ]
+        );
+    }
+
+    #[test]
+    fn valid_math_has_no_problems() {
+        assert!(check_math("no math at all").is_empty());
+        assert!(
+            check_math(
+                r"\[\begin{aligned} a &= \left( \frac{1}{2} \right) \\[2pt] b &= \{1, 2\} \end{aligned}\]"
+            )
+            .is_empty()
+        );
+        assert!(check_math(r"{{c1::\(x^{2} \)}} [$]\$5[/$]").is_empty());
+        assert!(check_math(r"\(x^{ {{c1::2}} }\)").is_empty());
+    }
+
+    #[test]
+    fn unbalanced_delimiters() {
+        assert_eq!(
+            check_math(r"[$]x and \(y\) [/$$]"),
+            ["[$] is never closed", "[/$$] has no matching [$$]"]
+        );
+        assert_eq!(check_math(r"\(a \(b\)"), [r"\(...\) contains a nested \("]);
+    }
+
+    #[test]
+    fn content_problems() {
+        assert_eq!(check_math(r"\( \)"), [r"\(...\) is empty"]);
+        assert_eq!(
+            check_math(r"\[\begin{matrix} a \end{pmatrix}\]"),
+            [r"\[...\] has \end{pmatrix} without matching \begin"]
+        );
+        assert_eq!(
+            check_math(r"\(\left( x\)"),
+            [r"\(...\) has unbalanced \left and \right"]
+        );
+        assert_eq!(
+            check_math(r"[$]a $ b[/$]"),
+            ["[$]...[/$] contains $, which ends math mode early"]
+        );
+        assert_eq!(
+            check_math(r"[latex]\input{/etc/passwd}[/latex]"),
+            [r"[latex]...[/latex] contains \input, which Anki refuses to render"]
+        );
+    }
+
+    #[test]
+    fn double_braces_in_cloze_notes() {
+        assert_eq!(
+            check_math(r"{{c1::\(\frac{1}{x^{2}}\)}}"),
+            [
+                r"\(...\) contains }}, which ends the cloze deletion early; add a space between the braces"
+            ]
+        );
+        assert!(check_math(r"\(\frac{1}{x^{2}}\)").is_empty());
+    }
+
+    #[test]
+    fn package_reports_notes_with_broken_math() {
+        let mut deck = Deck::new(2059400110, "Math", "");
+        deck.add_note(
+            Note::new(basic_model(), vec![r"\(x^2\)", r"[$]\frac{1}{2[/$]"])
+                .unwrap()
+                .guid("frac"),
+        );
+        let report = Package::new(vec![deck], vec![]).unwrap().validate_math();
+        assert_eq!(
+            report
+                .issues
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [r#"warning: note frac: field "Back": [$]...[/$] has unbalanced braces"#]
+        );
+    }
+}
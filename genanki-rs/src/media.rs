@@ -0,0 +1,113 @@
+use fancy_regex::{Captures, Regex};
+use std::collections::HashMap;
+
+const SOUND_REFERENCE: &str = r"\[sound:(.+?)\]";
+const SRC_REFERENCE: &str =
+    r#"(?i)<(?:img|audio|video|source)\b[^>]*?\bsrc\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>"']+))"#;
+/// Same as `SRC_REFERENCE`, with the part before the value as the first group
+const SRC_ATTRIBUTE: &str =
+    r#"(?i)(<(?:img|audio|video|source)\b[^>]*?\bsrc\s*=\s*)(?:"([^"]*)"|'([^']*)'|([^\s>"']+))"#;
+
+/// Returns the names of all media files referenced in a note `field`, in order of appearance.
+///
+/// Both `[sound:...]` tags and `src` attributes of `<img>`, `<audio>`, `<video>` and `<source>`
+/// elements are considered. Remote URLs and data URIs are skipped.
+pub fn media_references(field: &str) -> Vec<String> {
+    let mut references = vec![];
+    for regex_str in [SOUND_REFERENCE, SRC_REFERENCE] {
+        let regex = Regex::new(regex_str).expect("static regex");
+        for captures in regex.captures_iter(field).filter_map(|c| c.ok()) {
+            let reference = captures
+                .iter()
+                .skip(1)
+                .flatten()
+                .map(|m| m.as_str().trim())
+                .next();
+            if let Some(reference) = reference
+                && is_local(reference)
+                && !references.iter().any(|r| r == reference)
+            {
+                references.push(reference.to_string());
+            }
+        }
+    }
+    references
+}
+
+/// Replaces the references in a note `field` to media files named by a key of `renames` with the
+/// corresponding value, in the places [`media_references`] looks at
+pub(super) fn rename_media_references(field: &str, renames: &HashMap<String, String>) -> String {
+    let field = Regex::new(SOUND_REFERENCE)
+        .expect("static regex")
+        .replace_all(field, |caps: &Captures| match renames.get(caps[1].trim()) {
+            Some(name) => format!("[sound:{}]", name),
+            None => caps[0].to_string(),
+        });
+    Regex::new(SRC_ATTRIBUTE)
+        .expect("static regex")
+        .replace_all(&field, |caps: &Captures| {
+            let src = caps
+                .iter()
+                .skip(2)
+                .flatten()
+                .next()
+                .map_or("", |m| m.as_str());
+            match renames.get(src.trim()) {
+                Some(name) => format!("{}\"{}\"", &caps[1], name),
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+fn is_local(reference: &str) -> bool {
+    !reference.is_empty() && !reference.contains("://") && !reference.starts_with("data:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sound_and_image_references() {
+        assert_eq!(
+            media_references(
+                r#"[sound:a.mp3] <img src="b.jpg"> <IMG alt='x' SRC='c.png'> <img src=d.gif>"#
+            ),
+            ["a.mp3", "b.jpg", "c.png", "d.gif"]
+        );
+    }
+
+    #[test]
+    fn duplicates_and_remote_references_are_skipped() {
+        assert_eq!(
+            media_references(
+                r#"[sound:a.mp3][sound:a.mp3]<img src="https://example.com/x.png"><img src="data:image/png;base64,AA">"#
+            ),
+            ["a.mp3"]
+        );
+    }
+
+    #[test]
+    fn references_are_renamed() {
+        let renames = HashMap::from([
+            ("a.mp3".to_string(), "b.mp3".to_string()),
+            ("c.png".to_string(), "d.png".to_string()),
+        ]);
+        assert_eq!(
+            rename_media_references(
+                r#"[sound:a.mp3][sound:x.mp3]<IMG alt='c.png' SRC='c.png'><img src="https://x/c.png">"#,
+                &renames
+            ),
+            r#"[sound:b.mp3][sound:x.mp3]<IMG alt='c.png' SRC="d.png"><img src="https://x/c.png">"#
+        );
+    }
+
+    #[test]
+    fn audio_and_video_sources() {
+        assert_eq!(
+            media_references(r#"<audio src="a.ogg"></audio><video><source src="v.webm"></video>"#),
+            ["a.ogg", "v.webm"]
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use crate::media::rename_media_references;
+use crate::package::Package;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Display;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+/// A media file left out of a package because another file has the same content
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateMedia {
+    /// The name of the file left out
+    pub name: String,
+    /// The name of the file with the same content which is kept
+    pub canonical: String,
+    pub size: u64,
+}
+
+/// Media files removed by [`Package::dedupe_media`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DedupeReport {
+    pub duplicates: Vec<DuplicateMedia>,
+}
+
+impl DedupeReport {
+    /// Returns the number of bytes no longer stored in the package
+    pub fn saved_bytes(&self) -> u64 {
+        self.duplicates.iter().map(|duplicate| duplicate.size).sum()
+    }
+}
+
+impl Display for DedupeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for duplicate in &self.duplicates {
+            writeln!(
+                f,
+                "{}: same content as {}, {} bytes",
+                duplicate.name, duplicate.canonical, duplicate.size
+            )?;
+        }
+        writeln!(
+            f,
+            "saved {} bytes in {} files",
+            self.saved_bytes(),
+            self.duplicates.len()
+        )
+    }
+}
+
+/// A media file kept in the package, identified by its content
+struct Canonical<'a> {
+    path: &'a Path,
+    name: String,
+    hash: u64,
+    size: u64,
+}
+
+impl Package {
+    /// Stores media files with identical content only once, under the name of the first of them
+    /// in the package, and updates the references to the other names in all note fields.
+    ///
+    /// Files are compared by a hash of their content and then byte by byte. Files whose name
+    /// starts with `_` are referenced by templates and always kept; they are preferred as the
+    /// canonical name of their content.
+    ///
+    /// Returns `Err` if a media file cannot be read
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{basic_model, Deck, Note, Package};
+    /// use anyhow::Result;
+    ///
+    /// fn main() -> Result<()> {
+    ///     let mut deck = Deck::new(1234, "Sounds", "");
+    ///     deck.add_note(Note::new(basic_model(), vec!["[sound:sound.mp3]", "one"])?);
+    ///     deck.add_note(Note::new(basic_model(), vec!["[sound:copy.mp3]", "two"])?);
+    ///
+    ///     let dir = tempfile::tempdir()?;
+    ///     let copy = dir.path().join("copy.mp3");
+    ///     std::fs::copy("fixtures/sound.mp3", &copy)?;
+    ///     let mut package = Package::new(vec![deck], vec!["fixtures/sound.mp3", copy.to_str().unwrap()])?;
+    ///
+    ///     let report = package.dedupe_media()?;
+    ///     assert_eq!(report.duplicates[0].canonical, "sound.mp3");
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn dedupe_media(&mut self) -> Result<DedupeReport> {
+        let names = self
+            .media_files()
+            .iter()
+            .map(|path| file_name(path))
+            .collect::<Result<Vec<_>>>()?;
+        let mut order = (0..names.len()).collect::<Vec<_>>();
+        // Template media is kept in any case, so it becomes the canonical copy of its content
+        order.sort_by_key(|&i| !names[i].starts_with('_'));
+
+        let mut canonicals: Vec<Canonical> = vec![];
+        let mut kept = vec![false; self.media_files().len()];
+        let mut report = DedupeReport::default();
+        for i in order {
+            let path = &self.media_files()[i];
+            let name = names[i].to_string();
+            let content = fs::read(path)?;
+            let size = content.len() as u64;
+            let mut hasher = DefaultHasher::new();
+            content.hash(&mut hasher);
+            let hash = hasher.finish();
+
+            let mut canonical = None;
+            for candidate in &canonicals {
+                if candidate.hash == hash
+                    && candidate.size == size
+                    && fs::read(candidate.path)? == content
+                {
+                    canonical = Some(candidate.name.clone());
+                    break;
+                }
+            }
+            match canonical {
+                Some(canonical) if !name.starts_with('_') => {
+                    report.duplicates.push(DuplicateMedia {
+                        name,
+                        canonical,
+                        size,
+                    });
+                }
+                _ => {
+                    kept[i] = true;
+                    canonicals.push(Canonical {
+                        path,
+                        name,
+                        hash,
+                        size,
+                    });
+                }
+            }
+        }
+
+        let media_files = self
+            .media_files()
+            .iter()
+            .zip(kept)
+            .filter(|(_, kept)| *kept)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<PathBuf>>();
+        self.set_media_files(media_files);
+        let renames = report
+            .duplicates
+            .iter()
+            .filter(|duplicate| duplicate.name != duplicate.canonical)
+            .map(|duplicate| (duplicate.name.clone(), duplicate.canonical.clone()))
+            .collect::<HashMap<_, _>>();
+        if !renames.is_empty() {
+            for deck in self.decks_mut() {
+                deck.map_fields(|field| rename_media_references(field, &renames))?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn file_name(path: &Path) -> Result<&str> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("media path {:?} has no valid file name", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Deck, Note, basic_model};
+
+    fn write(dir: &Path, name: &str, content: &str) -> String {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn identical_files_are_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut deck = Deck::new(1234, "Sounds", "");
+        deck.add_note(
+            Note::new(
+                basic_model(),
+                vec![
+                    "[sound:a.mp3] [sound:b.mp3]",
+                    r#"<img src="c.png"><img src='d.png'>"#,
+                ],
+            )
+            .unwrap(),
+        );
+        let files = [
+            write(dir.path(), "a.mp3", "sound"),
+            write(dir.path(), "b.mp3", "sound"),
+            write(dir.path(), "c.png", "image"),
+            write(dir.path(), "d.png", "other image"),
+            write(dir.path(), "_e.png", "image"),
+        ];
+        let mut package =
+            Package::new(vec![deck], files.iter().map(String::as_str).collect()).unwrap();
+        let report = package.dedupe_media().unwrap();
+
+        assert_eq!(
+            report.duplicates,
+            [
+                DuplicateMedia {
+                    name: "b.mp3".to_string(),
+                    canonical: "a.mp3".to_string(),
+                    size: 5,
+                },
+                DuplicateMedia {
+                    name: "c.png".to_string(),
+                    canonical: "_e.png".to_string(),
+                    size: 5,
+                },
+            ]
+        );
+        assert_eq!(report.saved_bytes(), 10);
+        let kept = package
+            .media_files()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(kept, ["a.mp3", "d.png", "_e.png"]);
+        assert_eq!(
+            package.decks()[0].notes()[0].fields(),
+            [
+                "[sound:a.mp3] [sound:a.mp3]",
+                r#"<img src="_e.png"><img src='d.png'>"#
+            ]
+        );
+    }
+
+    #[test]
+    fn files_listed_twice_are_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = write(dir.path(), "a.mp3", "sound");
+        let mut package = Package::new(vec![], vec![&file, &file]).unwrap();
+        let report = package.dedupe_media().unwrap();
+        assert_eq!(report.saved_bytes(), 5);
+        assert_eq!(package.media_files().len(), 1);
+    }
+}
@@ -0,0 +1,728 @@
+use crate::media::rename_media_references;
+use crate::package::Package;
+use anyhow::{Result, anyhow};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// How an image is encoded by an [`ImagePolicy`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageEncoding {
+    /// Keeps the format of the file, PNGs are recompressed
+    Original,
+    /// JPEG with a quality from 1 to 100. Transparency is lost.
+    Jpeg(u8),
+    /// Lossless WebP, which keeps transparency
+    WebP,
+}
+
+/// What happens to images of one file type, see [`MediaProcessor`]
+///
+/// The builder has the following default values:
+/// * `max_dimension` - `None`, images are not resized
+/// * `encoding` - `ImageEncoding::Original`
+/// * `only_if_smaller` - `true`
+#[derive(Clone, Debug)]
+pub struct ImagePolicy {
+    max_dimension: Option<u32>,
+    encoding: ImageEncoding,
+    only_if_smaller: bool,
+}
+
+impl Default for ImagePolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImagePolicy {
+    /// Creates a new policy with the default options
+    pub fn new() -> Self {
+        Self {
+            max_dimension: None,
+            encoding: ImageEncoding::Original,
+            only_if_smaller: true,
+        }
+    }
+
+    /// Sets the maximum width and height, larger images are scaled down keeping their aspect ratio
+    pub fn max_dimension(self, max_dimension: u32) -> Self {
+        Self {
+            max_dimension: Some(max_dimension),
+            ..self
+        }
+    }
+
+    /// Sets the encoding of the processed images
+    pub fn encoding(self, encoding: ImageEncoding) -> Self {
+        Self { encoding, ..self }
+    }
+
+    /// Sets whether the original file is kept if processing does not make it smaller
+    pub fn only_if_smaller(self, only_if_smaller: bool) -> Self {
+        Self {
+            only_if_smaller,
+            ..self
+        }
+    }
+}
+
+/// What happens to WAV files, see [`MediaProcessor::audio_policy`]
+///
+/// Processed files are written as 16-bit PCM WAV. Resampling uses linear interpolation, which is
+/// good enough for speech recordings.
+///
+/// The builder has the following default values:
+/// * `mono` - `false`, the channels are kept
+/// * `max_sample_rate` - `None`, the sample rate is kept
+/// * `only_if_smaller` - `true`
+#[derive(Clone, Debug)]
+pub struct AudioPolicy {
+    mono: bool,
+    max_sample_rate: Option<u32>,
+    only_if_smaller: bool,
+}
+
+impl Default for AudioPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioPolicy {
+    /// Creates a new policy with the default options
+    pub fn new() -> Self {
+        Self {
+            mono: false,
+            max_sample_rate: None,
+            only_if_smaller: true,
+        }
+    }
+
+    /// Sets whether files with more than one channel are downmixed to mono
+    pub fn mono(self, mono: bool) -> Self {
+        Self { mono, ..self }
+    }
+
+    /// Sets the maximum sample rate in Hz, files with a higher rate are resampled to it
+    pub fn max_sample_rate(self, max_sample_rate: u32) -> Self {
+        Self {
+            max_sample_rate: Some(max_sample_rate),
+            ..self
+        }
+    }
+
+    /// Sets whether the original file is kept if processing does not make it smaller
+    pub fn only_if_smaller(self, only_if_smaller: bool) -> Self {
+        Self {
+            only_if_smaller,
+            ..self
+        }
+    }
+}
+
+/// Shrinks the media files of a [`Package`] before it is written, see [`Package::process_media`].
+///
+/// Policies are set per file extension. Files without a policy and files whose name starts with
+/// `_`, which Anki reserves for media used by templates, are left unchanged. Images are resized
+/// and re-encoded, WAV files can be downmixed and resampled. Compressed audio such as MP3 or OGG
+/// is out of scope and always left unchanged, as there are no pure-Rust encoders for it.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{AudioPolicy, ImageEncoding, ImagePolicy, MediaProcessor};
+///
+/// let processor = MediaProcessor::new()
+///     .policy("png", ImagePolicy::new().max_dimension(1024).encoding(ImageEncoding::WebP))
+///     .policy("jpg", ImagePolicy::new().max_dimension(1024).encoding(ImageEncoding::Jpeg(80)))
+///     .audio_policy("wav", AudioPolicy::new().mono(true).max_sample_rate(22050));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MediaProcessor {
+    policies: HashMap<String, ImagePolicy>,
+    audio_policies: HashMap<String, AudioPolicy>,
+}
+
+/// The policy that applies to one file
+enum Policy<'a> {
+    Image(&'a ImagePolicy),
+    Audio(&'a AudioPolicy),
+}
+
+impl MediaProcessor {
+    /// Creates a new processor without any policies
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the policy for images with the `extension`, e.g. `png`, compared case-insensitively
+    pub fn policy(mut self, extension: &str, policy: ImagePolicy) -> Self {
+        let extension = normalize_extension(extension);
+        self.audio_policies.remove(&extension);
+        self.policies.insert(extension, policy);
+        self
+    }
+
+    /// Sets the policy for WAV files with the `extension`, e.g. `wav`, compared
+    /// case-insensitively
+    pub fn audio_policy(mut self, extension: &str, policy: AudioPolicy) -> Self {
+        let extension = normalize_extension(extension);
+        self.policies.remove(&extension);
+        self.audio_policies.insert(extension, policy);
+        self
+    }
+
+    /// Returns the policy for the file at `path`, or `None` if it is left unchanged
+    fn policy_for(&self, path: &Path) -> Result<Option<Policy<'_>>> {
+        if file_name(path)?.starts_with('_') {
+            return Ok(None);
+        }
+        let extension = extension(path).to_lowercase();
+        Ok(self
+            .policies
+            .get(&extension)
+            .map(Policy::Image)
+            .or_else(|| self.audio_policies.get(&extension).map(Policy::Audio)))
+    }
+
+    /// Returns the name the file at `path` gets if it is processed
+    fn target_name(&self, path: &Path) -> Result<String> {
+        let name = file_name(path)?;
+        let extension = match self.policy_for(path)? {
+            Some(Policy::Image(policy)) => image_extension(extension(path), policy.encoding),
+            Some(Policy::Audio(_)) => "wav",
+            None => return Ok(name),
+        };
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(&name);
+        Ok(format!("{}.{}", stem, extension))
+    }
+
+    /// Processes the file at `path`, writing the result to `output_dir` if it is changed and
+    /// returning the path of the written file
+    fn process(&self, path: &Path, output_dir: &Path) -> Result<(ProcessedMedia, Option<PathBuf>)> {
+        let name = file_name(path)?;
+        let original_size = fs::metadata(path)?.len();
+        let unchanged = ProcessedMedia {
+            original: name.clone(),
+            name: name.clone(),
+            original_size,
+            size: original_size,
+        };
+        let (bytes, only_if_smaller) = match self.policy_for(path)? {
+            Some(Policy::Image(policy)) => (process_image(path, policy)?, policy.only_if_smaller),
+            Some(Policy::Audio(policy)) => (process_audio(path, policy)?, policy.only_if_smaller),
+            None => (None, false),
+        };
+        let Some(bytes) = bytes else {
+            return Ok((unchanged, None));
+        };
+        if only_if_smaller && bytes.len() as u64 >= original_size {
+            return Ok((unchanged, None));
+        }
+
+        let new_name = self.target_name(path)?;
+        let output = output_dir.join(&new_name);
+        fs::write(&output, &bytes)?;
+        let processed = ProcessedMedia {
+            name: new_name,
+            size: bytes.len() as u64,
+            ..unchanged
+        };
+        Ok((processed, Some(output)))
+    }
+}
+
+/// Resizes and re-encodes the image at `path`, returning `None` if there is nothing to do
+fn process_image(path: &Path, policy: &ImagePolicy) -> Result<Option<Vec<u8>>> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = reader
+        .format()
+        .ok_or_else(|| anyhow!("unknown image format of {}", path.display()))?;
+    let mut image = reader.decode()?;
+    let resize = policy
+        .max_dimension
+        .is_some_and(|max| image.width() > max || image.height() > max);
+    if let Some(max) = policy.max_dimension
+        && resize
+    {
+        image = image.resize(max, max, FilterType::Lanczos3);
+    }
+    if !resize && policy.encoding == ImageEncoding::Original && format != ImageFormat::Png {
+        return Ok(None);
+    }
+    encode(&image, format, policy.encoding).map(Some)
+}
+
+/// Encodes `image` with `encoding`
+fn encode(image: &DynamicImage, format: ImageFormat, encoding: ImageEncoding) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    match encoding {
+        ImageEncoding::Jpeg(quality) => {
+            let encoder = JpegEncoder::new_with_quality(&mut bytes, quality.clamp(1, 100));
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)?;
+        }
+        ImageEncoding::WebP => {
+            image.write_with_encoder(WebPEncoder::new_lossless(&mut bytes))?;
+        }
+        ImageEncoding::Original if format == ImageFormat::Png => {
+            let encoder = PngEncoder::new_with_quality(
+                &mut bytes,
+                CompressionType::Best,
+                PngFilter::Adaptive,
+            );
+            image.write_with_encoder(encoder)?;
+        }
+        ImageEncoding::Original => {
+            image.write_to(&mut Cursor::new(&mut bytes), format)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Returns the file extension of an image with `extension` after it is encoded with `encoding`
+fn image_extension(extension: &str, encoding: ImageEncoding) -> &str {
+    match encoding {
+        ImageEncoding::Jpeg(_)
+            if ImageFormat::from_extension(extension) == Some(ImageFormat::Jpeg) =>
+        {
+            extension
+        }
+        ImageEncoding::Jpeg(_) => "jpg",
+        ImageEncoding::WebP => "webp",
+        ImageEncoding::Original => extension,
+    }
+}
+
+/// Downmixes and resamples the WAV file at `path`, returning `None` if there is nothing to do
+fn process_audio(path: &Path, policy: &AudioPolicy) -> Result<Option<Vec<u8>>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    let channels = usize::from(spec.channels.max(1));
+    let downmix = policy.mono && channels > 1;
+    let sample_rate = policy
+        .max_sample_rate
+        .filter(|&max| max > 0)
+        .map_or(spec.sample_rate, |max| max.min(spec.sample_rate));
+    let requantize = spec.sample_format == hound::SampleFormat::Float || spec.bits_per_sample > 16;
+    if !downmix && sample_rate == spec.sample_rate && !requantize {
+        return Ok(None);
+    }
+
+    let mut frames = samples
+        .chunks(channels)
+        .map(|frame| frame.to_vec())
+        .collect::<Vec<_>>();
+    if downmix {
+        frames = frames
+            .into_iter()
+            .map(|frame| vec![frame.iter().sum::<f32>() / frame.len() as f32])
+            .collect();
+    }
+    if sample_rate != spec.sample_rate {
+        frames = resample(&frames, spec.sample_rate, sample_rate);
+    }
+
+    let out_spec = hound::WavSpec {
+        channels: if downmix { 1 } else { spec.channels },
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = Cursor::new(vec![]);
+    let mut writer = hound::WavWriter::new(&mut bytes, out_spec)?;
+    for sample in frames.iter().flatten() {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(Some(bytes.into_inner()))
+}
+
+/// Resamples `frames` from the rate `from` to `to` with linear interpolation
+fn resample(frames: &[Vec<f32>], from: u32, to: u32) -> Vec<Vec<f32>> {
+    let Some(last) = frames.len().checked_sub(1) else {
+        return vec![];
+    };
+    let length = (frames.len() as u64 * u64::from(to)).div_ceil(u64::from(from));
+    (0..length)
+        .map(|index| {
+            let position = index as f64 * f64::from(from) / f64::from(to);
+            let before = (position.floor() as usize).min(last);
+            let after = (before + 1).min(last);
+            let weight = (position - before as f64) as f32;
+            frames[before]
+                .iter()
+                .zip(&frames[after])
+                .map(|(a, b)| a + (b - a) * weight)
+                .collect()
+        })
+        .collect()
+}
+
+fn normalize_extension(extension: &str) -> String {
+    extension.trim_start_matches('.').to_lowercase()
+}
+
+fn extension(path: &Path) -> &str {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("media path {:?} has no valid file name", path))
+}
+
+/// A media file handled by a [`MediaProcessor`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessedMedia {
+    /// The file name before processing
+    pub original: String,
+    /// The file name in the package, which differs from `original` if the format was changed
+    pub name: String,
+    pub original_size: u64,
+    pub size: u64,
+}
+
+/// Sizes of the media files before and after [`Package::process_media`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MediaReport {
+    pub files: Vec<ProcessedMedia>,
+}
+
+impl MediaReport {
+    /// Returns the size of all media files before processing in bytes
+    pub fn original_size(&self) -> u64 {
+        self.files.iter().map(|file| file.original_size).sum()
+    }
+
+    /// Returns the size of all media files after processing in bytes
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+impl Display for MediaReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for file in self
+            .files
+            .iter()
+            .filter(|file| file.size != file.original_size)
+        {
+            write!(
+                f,
+                "{}: {} -> {} bytes",
+                file.original, file.original_size, file.size
+            )?;
+            if file.name != file.original {
+                write!(f, ", renamed to {}", file.name)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "total: {} -> {} bytes",
+            self.original_size(),
+            self.size()
+        )
+    }
+}
+
+impl Package {
+    /// Processes the media files of the package with `processor`, writing changed files to
+    /// `output_dir` and using them instead of the originals. The original files are not modified.
+    ///
+    /// If a file is renamed because its format changed, the references to it are updated in all
+    /// note fields.
+    ///
+    /// Returns `Err` if a file cannot be read or decoded, or if a renamed file would have the name
+    /// of another media file. Names are checked before any file is written.
+    ///
+    /// Example:
+    ///
+    /// ```rust,no_run
+    /// use genanki_rs::{basic_model, Deck, ImageEncoding, ImagePolicy, MediaProcessor, Note, Package};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let mut deck = Deck::new(1234, "Animals", "");
+    ///     deck.add_note(Note::new(basic_model(), vec!["Hund", r#"<img src="dog.png">"#])?);
+    ///     let mut package = Package::new(vec![deck], vec!["media/dog.png"])?;
+    ///
+    ///     let processor = MediaProcessor::new()
+    ///         .policy("png", ImagePolicy::new().max_dimension(800).encoding(ImageEncoding::Jpeg(85)));
+    ///     let output_dir = tempfile::tempdir()?;
+    ///     let report = package.process_media(&processor, output_dir.path())?;
+    ///     print!("{}", report);
+    ///
+    ///     package.generate_anki("animals.apkg", None).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn process_media(
+        &mut self,
+        processor: &MediaProcessor,
+        output_dir: impl AsRef<Path>,
+    ) -> Result<MediaReport> {
+        let output_dir = output_dir.as_ref();
+        fs::create_dir_all(output_dir)?;
+        let targets = self
+            .media_files()
+            .iter()
+            .map(|path| Ok((file_name(path)?, processor.target_name(path)?)))
+            .collect::<Result<Vec<_>>>()?;
+        for (index, (_, target)) in targets.iter().enumerate() {
+            let taken = targets
+                .iter()
+                .enumerate()
+                .any(|(other, (name, other_target))| {
+                    other != index && (target == name || target == other_target)
+                });
+            if taken {
+                return Err(anyhow!(
+                    "processing media would create more than one file named {}",
+                    target
+                ));
+            }
+        }
+
+        let mut report = MediaReport::default();
+        let mut media_files = vec![];
+        for path in self.media_files() {
+            let (processed, output) = processor.process(path, output_dir)?;
+            media_files.push(output.unwrap_or_else(|| path.clone()));
+            report.files.push(processed);
+        }
+        let renames = report
+            .files
+            .iter()
+            .filter(|file| file.name != file.original)
+            .map(|file| (file.original.clone(), file.name.clone()))
+            .collect::<HashMap<_, _>>();
+        self.set_media_files(media_files);
+        if !renames.is_empty() {
+            for deck in self.decks_mut() {
+                deck.map_fields(|field| rename_media_references(field, &renames))?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Deck, Note, basic_model};
+    use image::{Rgba, RgbaImage};
+
+    fn write_png(dir: &Path, name: &str, width: u32, height: u32) -> PathBuf {
+        let path = dir.join(name);
+        let image = RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8, 255])
+        });
+        image.save(&path).unwrap();
+        path
+    }
+
+    fn package(dir: &Path) -> Package {
+        let mut deck = Deck::new(1234, "Animals", "");
+        deck.add_note(
+            Note::new(
+                basic_model(),
+                vec![
+                    r#"<img src="dog.png">"#,
+                    r#"<IMG alt=x SRC='_logo.png'> [sound:dog.wav]"#,
+                ],
+            )
+            .unwrap(),
+        );
+        let dog = write_png(dir, "dog.png", 400, 200);
+        let logo = write_png(dir, "_logo.png", 400, 200);
+        Package::new(
+            vec![deck],
+            vec![dog.to_str().unwrap(), logo.to_str().unwrap()],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn images_are_resized_and_renamed() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let mut package = package(input.path());
+        let processor = MediaProcessor::new().policy(
+            "PNG",
+            ImagePolicy::new()
+                .max_dimension(100)
+                .encoding(ImageEncoding::Jpeg(80)),
+        );
+        let report = package.process_media(&processor, output.path()).unwrap();
+
+        let dog = &report.files[0];
+        assert_eq!(dog.name, "dog.jpg");
+        assert!(dog.size < dog.original_size);
+        let logo = &report.files[1];
+        assert_eq!(
+            (logo.name.as_str(), logo.size),
+            ("_logo.png", logo.original_size)
+        );
+
+        let processed = image::open(output.path().join("dog.jpg")).unwrap();
+        assert_eq!((processed.width(), processed.height()), (100, 50));
+        assert_eq!(
+            package.media_files(),
+            [
+                output.path().join("dog.jpg"),
+                input.path().join("_logo.png")
+            ]
+        );
+        let fields = package.decks()[0].notes()[0].fields().to_vec();
+        assert_eq!(
+            fields,
+            [
+                r#"<img src="dog.jpg">"#,
+                r#"<IMG alt=x SRC='_logo.png'> [sound:dog.wav]"#
+            ]
+        );
+        assert!(report.to_string().contains("renamed to dog.jpg"));
+    }
+
+    #[test]
+    fn files_without_policy_are_unchanged() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let mut package = package(input.path());
+        let processor = MediaProcessor::new().policy("jpg", ImagePolicy::new().max_dimension(10));
+        let report = package.process_media(&processor, output.path()).unwrap();
+        assert_eq!(report.size(), report.original_size());
+        assert_eq!(package.media_files()[0], input.path().join("dog.png"));
+        assert_eq!(fs::read_dir(output.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn webp_keeps_dimensions_of_small_images() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let mut package = package(input.path());
+        let processor = MediaProcessor::new().policy(
+            "png",
+            ImagePolicy::new()
+                .max_dimension(1000)
+                .encoding(ImageEncoding::WebP)
+                .only_if_smaller(false),
+        );
+        let report = package.process_media(&processor, output.path()).unwrap();
+        assert_eq!(report.files[0].name, "dog.webp");
+        let processed = image::open(output.path().join("dog.webp")).unwrap();
+        assert_eq!((processed.width(), processed.height()), (400, 200));
+    }
+
+    #[test]
+    fn colliding_names_are_rejected_before_writing() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let png = write_png(input.path(), "a.png", 400, 200);
+        let gif = input.path().join("a.gif");
+        image::open(&png).unwrap().save(&gif).unwrap();
+        let mut package =
+            Package::new(vec![], vec![png.to_str().unwrap(), gif.to_str().unwrap()]).unwrap();
+        let policy = ImagePolicy::new()
+            .encoding(ImageEncoding::WebP)
+            .only_if_smaller(false);
+        let processor = MediaProcessor::new()
+            .policy("png", policy.clone())
+            .policy("gif", policy);
+
+        let err = package
+            .process_media(&processor, output.path())
+            .unwrap_err();
+        assert!(err.to_string().contains("a.webp"));
+        assert_eq!(fs::read_dir(output.path()).unwrap().count(), 0);
+        assert_eq!(package.media_files(), [png, gif]);
+    }
+
+    #[test]
+    fn wav_files_are_downmixed_and_resampled() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let path = input.path().join("dog.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 24,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for index in 0..44100 {
+            writer.write_sample(index % 1000 * 1000).unwrap();
+            writer.write_sample(-(index % 1000 * 1000)).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut package = Package::new(vec![], vec![path.to_str().unwrap()]).unwrap();
+        let processor = MediaProcessor::new()
+            .audio_policy("WAV", AudioPolicy::new().mono(true).max_sample_rate(22050));
+        let report = package.process_media(&processor, output.path()).unwrap();
+
+        let dog = &report.files[0];
+        assert_eq!(dog.name, "dog.wav");
+        assert!(dog.size < dog.original_size / 4);
+        assert_eq!(package.media_files(), [output.path().join("dog.wav")]);
+        let mut reader = hound::WavReader::open(output.path().join("dog.wav")).unwrap();
+        let spec = reader.spec();
+        assert_eq!(
+            (spec.channels, spec.sample_rate, spec.bits_per_sample),
+            (1, 22050, 16)
+        );
+        assert_eq!(reader.len(), 22050);
+        assert!(reader.samples::<i16>().all(|sample| sample.unwrap() == 0));
+    }
+
+    #[test]
+    fn wav_files_within_policy_are_unchanged() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let path = input.path().join("dog.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for index in 0..1600 {
+            writer.write_sample(index as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut package = Package::new(vec![], vec![path.to_str().unwrap()]).unwrap();
+        let processor = MediaProcessor::new()
+            .audio_policy("wav", AudioPolicy::new().mono(true).max_sample_rate(22050));
+        let report = package.process_media(&processor, output.path()).unwrap();
+        assert_eq!(report.size(), report.original_size());
+        assert_eq!(package.media_files(), [path]);
+    }
+}
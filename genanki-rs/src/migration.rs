@@ -0,0 +1,361 @@
+use crate::Error;
+use crate::model::{Model, ModelType};
+use anyhow::{Result, anyhow};
+use std::fmt::Display;
+
+/// Change between two versions of a `Model` which Anki can only apply with a full sync
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaChange {
+    FieldAdded(String),
+    FieldRemoved(String),
+    FieldRenamed { from: String, to: String },
+    FieldsReordered,
+    TemplateAdded(String),
+    TemplateRemoved(String),
+    TemplatesReordered,
+    ModelTypeChanged,
+}
+
+impl Display for SchemaChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaChange::FieldAdded(name) => write!(f, "field {:?} added", name),
+            SchemaChange::FieldRemoved(name) => write!(f, "field {:?} removed", name),
+            SchemaChange::FieldRenamed { from, to } => {
+                write!(f, "field {:?} renamed to {:?}", from, to)
+            }
+            SchemaChange::FieldsReordered => write!(f, "fields reordered"),
+            SchemaChange::TemplateAdded(name) => write!(f, "template {:?} added", name),
+            SchemaChange::TemplateRemoved(name) => write!(f, "template {:?} removed", name),
+            SchemaChange::TemplatesReordered => write!(f, "templates reordered"),
+            SchemaChange::ModelTypeChanged => write!(f, "model type changed"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum FieldSource {
+    /// Index of the field of the old model the value is taken from
+    Field(usize),
+    /// Value used for every note
+    Default(String),
+}
+
+/// Maps the fields of notes from one version of a `Model` to another.
+///
+/// Fields are matched by name, so reordered fields keep their values. Fields only in the new
+/// model are empty unless a default or a rename is given, fields only in the old model are
+/// dropped.
+///
+/// Anki rejects notes whose field count does not match the model already in the collection, so
+/// when [`ModelMigration::requires_full_sync`] returns `true` either give the new model a new id
+/// or expect users to accept a full sync.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{Field, Model, ModelMigration, Note, Template};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let template = Template::new("Card 1").qfmt("{{Word}}").afmt("{{Meaning}}");
+///     let v1 = Model::new(
+///         1607392319,
+///         "Vocabulary",
+///         vec![Field::new("Word"), Field::new("Translation")],
+///         vec![template.clone()],
+///     );
+///     let v2 = Model::new(
+///         1607392319,
+///         "Vocabulary",
+///         vec![Field::new("Word"), Field::new("Meaning"), Field::new("Level")],
+///         vec![template],
+///     );
+///     let migration = ModelMigration::new(&v1, &v2)
+///         .rename("Translation", "Meaning")?
+///         .default_value("Level", "B1")?;
+///     assert!(migration.requires_full_sync());
+///
+///     let note = Note::new(v1, vec!["Hund", "dog"])?.migrate(&migration)?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ModelMigration {
+    from: Model,
+    to: Model,
+    sources: Vec<FieldSource>,
+}
+
+impl ModelMigration {
+    /// Creates a migration from the model `from` to the model `to`, matching fields by name
+    pub fn new(from: &Model, to: &Model) -> Self {
+        let old_fields = from.fields();
+        let sources = to
+            .fields()
+            .iter()
+            .map(
+                |field| match old_fields.iter().position(|old| old.name == field.name) {
+                    Some(index) => FieldSource::Field(index),
+                    None => FieldSource::Default(String::new()),
+                },
+            )
+            .collect();
+        Self {
+            from: from.clone(),
+            to: to.clone(),
+            sources,
+        }
+    }
+
+    /// Fills the field `new` of the new model with the field `old` of the old model
+    ///
+    /// Returns `Err` if one of the models has no such field
+    pub fn rename(mut self, old: &str, new: &str) -> Result<Self> {
+        let old_index = field_index(&self.from, old)?;
+        let new_index = field_index(&self.to, new)?;
+        self.sources[new_index] = FieldSource::Field(old_index);
+        Ok(self)
+    }
+
+    /// Fills the field `field` of the new model with `value` for every note
+    ///
+    /// Returns `Err` if the new model has no such field
+    pub fn default_value(mut self, field: &str, value: &str) -> Result<Self> {
+        let index = field_index(&self.to, field)?;
+        self.sources[index] = FieldSource::Default(value.to_string());
+        Ok(self)
+    }
+
+    /// Discards the field `field` of the old model, even if the new model has a field of the
+    /// same name
+    ///
+    /// Returns `Err` if the old model has no such field
+    pub fn remove_field(mut self, field: &str) -> Result<Self> {
+        let index = field_index(&self.from, field)?;
+        for source in &mut self.sources {
+            if *source == FieldSource::Field(index) {
+                *source = FieldSource::Default(String::new());
+            }
+        }
+        Ok(self)
+    }
+
+    /// Returns the changes between the two models which Anki can only apply with a full sync
+    pub fn changes(&self) -> Vec<SchemaChange> {
+        let old_fields = self.from.fields();
+        let new_fields = self.to.fields();
+        let mut changes = vec![];
+        if self.from.get_model_type() != self.to.get_model_type() {
+            changes.push(SchemaChange::ModelTypeChanged);
+        }
+
+        let mut used = vec![];
+        for (field, source) in new_fields.iter().zip(&self.sources) {
+            match source {
+                FieldSource::Field(index) => {
+                    let old = &old_fields[*index].name;
+                    if *old != field.name {
+                        changes.push(SchemaChange::FieldRenamed {
+                            from: old.clone(),
+                            to: field.name.clone(),
+                        });
+                    }
+                    used.push(*index);
+                }
+                FieldSource::Default(_) => {
+                    changes.push(SchemaChange::FieldAdded(field.name.clone()))
+                }
+            }
+        }
+        for (index, field) in old_fields.iter().enumerate() {
+            if !used.contains(&index) {
+                changes.push(SchemaChange::FieldRemoved(field.name.clone()));
+            }
+        }
+        if used.windows(2).any(|pair| pair[0] >= pair[1]) {
+            changes.push(SchemaChange::FieldsReordered);
+        }
+
+        let old_templates = self.from.templates();
+        let new_templates = self.to.templates();
+        for template in &new_templates {
+            if !old_templates.iter().any(|old| old.name == template.name) {
+                changes.push(SchemaChange::TemplateAdded(template.name.clone()));
+            }
+        }
+        let mut kept = vec![];
+        for template in &old_templates {
+            match new_templates
+                .iter()
+                .position(|new| new.name == template.name)
+            {
+                Some(position) => kept.push(position),
+                None => changes.push(SchemaChange::TemplateRemoved(template.name.clone())),
+            }
+        }
+        if kept.windows(2).any(|pair| pair[0] >= pair[1]) {
+            changes.push(SchemaChange::TemplatesReordered);
+        }
+        changes
+    }
+
+    /// Returns whether importing notes of the new model over the old one needs a full sync
+    pub fn requires_full_sync(&self) -> bool {
+        !self.changes().is_empty()
+    }
+
+    /// Maps the `fields` of a note of the old model to the fields of the new model
+    ///
+    /// Returns `Err` if the number of fields does not match the old model
+    pub fn migrate_fields(&self, fields: &[String]) -> Result<Vec<String>> {
+        let model_len = self.from.fields().len();
+        if fields.len() != model_len {
+            return Err(anyhow!(Error::ModelFieldCountMismatch {
+                model_len,
+                card_len: fields.len(),
+            }));
+        }
+        Ok(self
+            .sources
+            .iter()
+            .map(|source| match source {
+                FieldSource::Field(index) => fields[*index].clone(),
+                FieldSource::Default(value) => value.clone(),
+            })
+            .collect())
+    }
+
+    pub(super) fn to_model(&self) -> Model {
+        self.to.clone()
+    }
+
+    /// Returns the ordinal in the new model of the card with ordinal `ord` in the old model
+    pub(super) fn card_ord(&self, ord: i64) -> Option<i64> {
+        if self.to.get_model_type() == ModelType::Cloze {
+            return Some(ord);
+        }
+        let templates = self.from.templates();
+        let name = &templates.get(ord as usize)?.name;
+        self.to
+            .templates()
+            .iter()
+            .position(|template| &template.name == name)
+            .map(|position| position as i64)
+    }
+}
+
+fn field_index(model: &Model, name: &str) -> Result<usize> {
+    model
+        .fields()
+        .iter()
+        .position(|field| field.name == name)
+        .ok_or_else(|| {
+            anyhow!(Error::UnknownField {
+                model: model.name().to_string(),
+                field: name.to_string(),
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CardState, Field, Note, Template};
+
+    fn model(fields: &[&str], templates: &[&str]) -> Model {
+        Model::new(
+            1607392319,
+            "Vocabulary",
+            fields.iter().map(|&name| Field::new(name)).collect(),
+            templates
+                .iter()
+                .map(|&name| {
+                    Template::new(name)
+                        .qfmt(&format!("{{{{{}}}}}", fields[0]))
+                        .afmt("{{FrontSide}}")
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn fields_are_matched_by_name() {
+        let v1 = model(&["Word", "Translation", "Notes"], &["Card 1"]);
+        let v2 = model(&["Word", "Level", "Meaning"], &["Card 1"]);
+        let migration = ModelMigration::new(&v1, &v2)
+            .rename("Translation", "Meaning")
+            .unwrap()
+            .default_value("Level", "B1")
+            .unwrap();
+        let fields = ["Hund", "dog", "noun"].map(String::from);
+        assert_eq!(
+            migration.migrate_fields(&fields).unwrap(),
+            ["Hund", "B1", "dog"]
+        );
+        assert_eq!(
+            migration.changes(),
+            [
+                SchemaChange::FieldAdded("Level".to_string()),
+                SchemaChange::FieldRenamed {
+                    from: "Translation".to_string(),
+                    to: "Meaning".to_string()
+                },
+                SchemaChange::FieldRemoved("Notes".to_string()),
+            ]
+        );
+        assert!(migration.migrate_fields(&fields[..2]).is_err());
+        assert!(migration.clone().rename("Nope", "Meaning").is_err());
+        assert!(migration.default_value("Nope", "").is_err());
+    }
+
+    #[test]
+    fn reordering_and_dropping() {
+        let v1 = model(&["Word", "Translation"], &["Card 1"]);
+        let v2 = model(&["Translation", "Word"], &["Card 1"]);
+        let migration = ModelMigration::new(&v1, &v2);
+        assert_eq!(migration.changes(), [SchemaChange::FieldsReordered]);
+        assert_eq!(
+            migration
+                .remove_field("Translation")
+                .unwrap()
+                .migrate_fields(&["Hund".to_string(), "dog".to_string()])
+                .unwrap(),
+            ["", "Hund"]
+        );
+
+        let migration = ModelMigration::new(&v1, &v1.clone());
+        assert!(!migration.requires_full_sync());
+    }
+
+    #[test]
+    fn migrated_notes_keep_guid_tags_and_card_states() {
+        let v1 = model(&["Word", "Translation"], &["Forward", "Reverse"]);
+        let v2 = model(&["Word", "Translation", "Example"], &["Reverse", "Forward"]);
+        let migration = ModelMigration::new(&v1, &v2);
+        assert_eq!(
+            migration.changes(),
+            [
+                SchemaChange::FieldAdded("Example".to_string()),
+                SchemaChange::TemplatesReordered
+            ]
+        );
+
+        let note = Note::new(v1, vec!["Hund", "dog"])
+            .unwrap()
+            .guid("hund")
+            .tags(["animal"])
+            .card_state(1, CardState::new().suspended(true))
+            .unwrap();
+        let migrated = note.migrate(&migration).unwrap();
+        assert_eq!(migrated.fields(), ["Hund", "dog", ""]);
+        assert_eq!(migrated.get_guid(), "hund");
+        assert_eq!(migrated.get_tags(), ["animal"]);
+        let suspended = migrated
+            .cards()
+            .into_iter()
+            .find(|card| card.state == CardState::new().suspended(true))
+            .unwrap();
+        assert_eq!(suspended.ord, 0);
+    }
+}
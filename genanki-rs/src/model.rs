@@ -1,11 +1,12 @@
 use crate::Field;
 use crate::builders::Template;
-use crate::db_entries::{Fld, ModelDbEntry, Tmpl};
+use crate::db_entries::{DeckDbEntry, Fld, ModelDbEntry, Tmpl};
 use crate::error::{json_error, template_error};
 use anyhow::{Result, anyhow};
 use fancy_regex::Regex;
 use ramhorns::Template as RamTemplate;
 use std::collections::HashMap;
+use std::sync::LazyLock;
 
 const DEFAULT_LATEX_PRE: &str = r#"
 \documentclass[12pt]{article}
@@ -19,6 +20,10 @@ const DEFAULT_LATEX_PRE: &str = r#"
 "#;
 const DEFAULT_LATEX_POST: &str = r"\end{document}";
 
+static FILTERED_REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{\{\s*([^{}#^/!][^{}]*):([^:{}]+?)\s*\}\}").expect("static regex")
+});
+
 /// `FrontBack` or `Cloze` to determine the type of a Model.
 ///
 /// When creating a Model, the default is `FrontBack`
@@ -40,6 +45,7 @@ pub struct Model {
     latex_pre: String,
     latex_post: String,
     sort_field_index: i64,
+    override_decks: Vec<DeckDbEntry>,
 }
 
 impl Model {
@@ -64,6 +70,7 @@ impl Model {
             name: name.to_string(),
             fields: fields.iter().cloned().map(|f| f.into()).collect(),
             templates: templates.iter().cloned().map(|t| t.into()).collect(),
+            override_decks: override_decks(&templates),
             css: "".to_string(),
             model_type: ModelType::FrontBack,
             latex_pre: DEFAULT_LATEX_PRE.to_string(),
@@ -95,6 +102,7 @@ impl Model {
             name: name.to_string(),
             fields: fields.iter().cloned().map(|f| f.into()).collect(),
             templates: templates.iter().cloned().map(|t| t.into()).collect(),
+            override_decks: override_decks(&templates),
             css: css.unwrap_or("").to_string(),
             model_type: model_type.unwrap_or(ModelType::FrontBack),
             latex_pre: latex_pre.unwrap_or(DEFAULT_LATEX_PRE).to_string(),
@@ -111,6 +119,8 @@ impl Model {
 
     /// Adds an additional template to the model
     pub fn with_template(mut self, template: Template) -> Self {
+        self.override_decks
+            .extend(override_decks(std::slice::from_ref(&template)));
         self.templates.push(template.into());
         self
     }
@@ -160,7 +170,7 @@ impl Model {
             .map(|field| (field.as_str(), format!("{}{}", &field, &sentinel)));
         let mut req = Vec::new();
         for (template_ord, template) in self.templates.iter().enumerate() {
-            let rendered = RamTemplate::new(template.qfmt.clone())
+            let rendered = RamTemplate::new(resolve_filters(&template.qfmt))
                 .map_err(template_error)?
                 .render::<HashMap<&str, String>>(&field_values.clone().collect());
             let required_fields = field_values
@@ -187,6 +197,54 @@ impl Model {
         Ok(req)
     }
 
+    /// Builds a model from an entry of `col.models`, ignoring template deck overrides
+    pub(super) fn from_db_entry(id: i64, entry: &ModelDbEntry) -> Self {
+        let fields = entry
+            .flds
+            .iter()
+            .map(|fld| {
+                let mut field = Field::new(&fld.name).rtl(fld.rtl).sticky(fld.sticky);
+                if !fld.font.is_empty() {
+                    field = field.font(&fld.font);
+                }
+                if fld.size > 0 {
+                    field = field.size(fld.size);
+                }
+                field
+            })
+            .collect();
+        let templates = entry
+            .tmpls
+            .iter()
+            .map(|tmpl| {
+                let mut template = Template::new(&tmpl.name).qfmt(&tmpl.qfmt).afmt(&tmpl.afmt);
+                if !tmpl.bqfmt.is_empty() {
+                    template = template.bqfmt(&tmpl.bqfmt);
+                }
+                if !tmpl.bafmt.is_empty() {
+                    template = template.bafmt(&tmpl.bafmt);
+                }
+                template
+            })
+            .collect();
+        let mut model = Model::new(id, &entry.name, fields, templates)
+            .css(&entry.css)
+            .sort_field_index(entry.sortf);
+        if entry.model_db_entry_type == 1 {
+            model = model.model_type(ModelType::Cloze);
+        }
+        if !entry.latex_pre.is_empty() {
+            model = model.latex_pre(&entry.latex_pre);
+        }
+        if !entry.latex_post.is_empty() {
+            model = model.latex_post(&entry.latex_post);
+        }
+        model
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.name
+    }
     pub(super) fn fields(&self) -> Vec<Fld> {
         self.fields.clone()
     }
@@ -196,6 +254,21 @@ impl Model {
     pub(super) fn get_model_type(&self) -> ModelType {
         self.model_type.clone()
     }
+    /// Decks created by templates through [`Template::deck`]
+    pub(super) fn override_decks(&self) -> &[DeckDbEntry] {
+        &self.override_decks
+    }
+    /// Returns the deck override of the template generating cards with `ord`
+    pub(super) fn template_deck_id(&self, ord: i64) -> Option<i64> {
+        let template_index = match self.model_type {
+            ModelType::FrontBack => ord as usize,
+            ModelType::Cloze => 0,
+        };
+        self.templates
+            .get(template_index)
+            .and_then(|template| template.did)
+            .map(|did| did as i64)
+    }
     pub(super) fn to_model_db_entry(&self, timestamp: f64, deck_id: i64) -> Result<ModelDbEntry> {
         let templates: Vec<Tmpl> = self
             .templates
@@ -251,6 +324,31 @@ impl Model {
     }
 }
 
+fn override_decks(templates: &[Template]) -> Vec<DeckDbEntry> {
+    templates
+        .iter()
+        .filter_map(|template| template.override_deck().cloned())
+        .collect()
+}
+
+/// Replaces filtered field references like `{{furigana:Field}}` or `{{tts de_DE:Field}}` by plain
+/// references, which ramhorns would otherwise render as unknown variables. References with the
+/// `type` filter are removed, as they do not make a card non-empty, and `cloze` ones are kept.
+fn resolve_filters(qfmt: &str) -> String {
+    FILTERED_REFERENCE
+        .replace_all(qfmt, |caps: &fancy_regex::Captures| {
+            let filters = caps[1].split(':').map(str::trim).collect::<Vec<_>>();
+            if filters.contains(&"cloze") {
+                caps[0].to_string()
+            } else if filters.contains(&"type") {
+                String::new()
+            } else {
+                format!("{{{{{}}}}}", caps[2].trim())
+            }
+        })
+        .into_owned()
+}
+
 fn contains_other_fields(rendered: &str, current_field: &str, sentinel: &str) -> bool {
     Regex::new(&format!(
         "(?!{field}\\b)\\b(\\w)*{sentinel}+",
@@ -265,7 +363,7 @@ fn contains_other_fields(rendered: &str, current_field: &str, sentinel: &str) ->
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Deck, Note};
+    use crate::{Deck, Error, Note, Package};
     use sqlx::{Pool, Sqlite};
     use std::collections::HashSet;
     use tempfile::NamedTempFile;
@@ -408,6 +506,91 @@ mod tests {
             .unwrap();
     }
 
+    fn reversed_model(reverse_template: Template) -> Model {
+        Model::new(
+            1516284203,
+            "Reversed Model",
+            vec![Field::new("Front"), Field::new("Back")],
+            vec![
+                Template::new("Card 1")
+                    .qfmt("{{Front}}")
+                    .afmt("{{FrontSide}}<hr id=answer>{{Back}}")
+                    .bqfmt("{{Front}} (browser)")
+                    .bafmt("{{Back}} (browser)"),
+                reverse_template
+                    .qfmt("{{Back}}")
+                    .afmt("{{FrontSide}}<hr id=answer>{{Front}}"),
+            ],
+        )
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn template_deck_override(pool: Pool<Sqlite>) {
+        let reverse_deck = Deck::new(2059400111, "Lang::Reverse", "Reversed cards");
+        let model = reversed_model(Template::new("Card 2").deck(&reverse_deck));
+        let mut deck = Deck::new(2059400110, "Lang", "");
+        deck.add_note(Note::new(model, vec!["Hund", "dog"]).unwrap());
+        let mut package = Package::new(vec![deck], vec![]).unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        package
+            .write_maybe_timestamp(None, &mut conn)
+            .await
+            .unwrap();
+
+        let cards = sqlx::query!("SELECT ord, did FROM cards ORDER BY ord")
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap();
+        let placement = cards
+            .iter()
+            .map(|card| (card.ord, card.did))
+            .collect::<Vec<_>>();
+        assert_eq!(placement, [(0, 2059400110), (1, 2059400111)]);
+
+        let col = sqlx::query!("SELECT decks, models FROM col")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        let decks: HashMap<i64, DeckDbEntry> = serde_json::from_str(&col.decks).unwrap();
+        assert_eq!(decks[&2059400111].name, "Lang::Reverse");
+        assert_eq!(decks[&2059400111].desc, "Reversed cards");
+        let models: HashMap<i64, ModelDbEntry> = serde_json::from_str(&col.models).unwrap();
+        let templates = &models[&1516284203].tmpls;
+        assert_eq!(templates[0].did, None);
+        assert_eq!(templates[0].bqfmt, "{{Front}} (browser)");
+        assert_eq!(templates[0].bafmt, "{{Back}} (browser)");
+        assert_eq!(templates[1].did, Some(2059400111));
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn template_deck_override_must_exist(pool: Pool<Sqlite>) {
+        let mut conn = pool.acquire().await.unwrap();
+
+        let mut deck = Deck::new(2059400110, "Lang", "");
+        let model = reversed_model(Template::new("Card 2").did(42));
+        deck.add_note(Note::new(model, vec!["Hund", "dog"]).unwrap());
+        let mut package = Package::new(vec![deck.clone()], vec![]).unwrap();
+        let err = package
+            .write_maybe_timestamp(None, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UnknownDeck(42))
+        ));
+
+        // The default deck of the collection always exists
+        let model = reversed_model(Template::new("Card 2").did(1));
+        deck.add_note(Note::new(model, vec!["Katze", "cat"]).unwrap());
+        let other = Deck::new(42, "Other", "");
+        let mut package = Package::new(vec![deck, other], vec![]).unwrap();
+        package
+            .write_maybe_timestamp(None, &mut conn)
+            .await
+            .unwrap();
+    }
+
     #[test]
     fn cloze_multi_field() {
         let fields = vec![
@@ -456,6 +639,28 @@ mod tests {
         assert_eq!(sorted, vec![0, 1]);
     }
 
+    #[test]
+    fn req_resolves_filtered_fields() {
+        let model = Model::new(
+            12345,
+            "Japanese",
+            vec![Field::new("Front"), Field::new("Back"), Field::new("Extra")],
+            vec![
+                Template::new("Reading").qfmt(
+                    "{{furigana:Front}}<br>{{tts ja_JP voices=Apple_Kyoko:Front}}{{type:Back}}",
+                ),
+                Template::new("Meaning").qfmt("{{text:Back}}<br>{{kanji:Extra}}"),
+            ],
+        );
+        assert_eq!(
+            model.req().unwrap(),
+            [
+                (0, "all".to_string(), vec![0]),
+                (1, "any".to_string(), vec![1, 2])
+            ]
+        );
+    }
+
     #[test]
     fn build_all_fields() {
         // A simple test to make sure we can call all the setters on the builder.
@@ -1,12 +1,19 @@
 use crate::Error;
-use crate::card::Card;
+use crate::builders::NoteBuilder;
+use crate::card::{Card, CardState};
+use crate::conflict::ConflictPolicy;
+use crate::migration::ModelMigration;
 use crate::model::{Model, ModelType};
+use crate::sanitize::decode_entities;
+use crate::tags::{normalize_tags, validate_tag};
 use crate::util::guid_for;
 use anyhow::{Result, anyhow};
 use fancy_regex::Regex;
+use sha1::{Digest, Sha1};
 use sqlx::SqliteConnection;
 use std::collections::HashSet;
 use std::str::FromStr;
+use std::sync::LazyLock;
 
 /// Note (Flashcard) to be added to a `Deck`
 #[derive(Clone)]
@@ -33,11 +40,8 @@ impl Note {
     pub fn new(model: Model, fields: Vec<&str>) -> Result<Self> {
         let fields: Vec<String> = fields.iter().map(|&s| s.to_string()).collect();
 
-        let cards = match model.get_model_type() {
-            ModelType::FrontBack => front_back_cards(&model, &fields)?,
-            ModelType::Cloze => cloze_cards(&model, &fields),
-        };
-        let guid = guid_for(&fields);
+        let cards = generate_cards(&model, &fields)?;
+        let guid = guid_for(model.id, &fields);
         Ok(Self {
             model,
             fields,
@@ -48,10 +52,15 @@ impl Note {
         })
     }
 
+    /// Creates a builder which sets the fields of a note of `model` by name, see [`NoteBuilder`]
+    pub fn builder(model: Model) -> NoteBuilder {
+        NoteBuilder::new(model)
+    }
+
     /// Creates a new Note with a new `model`, `fields` and custom parameters:
     /// * `sort_field` - whether to sort field, default is `false`
     /// * `tags` - List of tags
-    /// * `guid` - Custom unique note id, default is hash of all fields
+    /// * `guid` - Custom unique note id, default is hash of the model id and all fields
     ///
     /// Returns `Err` if tags or fields are invalid
     pub fn new_with_options(
@@ -68,11 +77,8 @@ impl Note {
             .collect();
         validate_tags(&tags)?;
         let fields: Vec<String> = fields.iter().map(|s| s.to_string()).collect();
-        let cards = match model.get_model_type() {
-            ModelType::FrontBack => front_back_cards(&model, &fields)?,
-            ModelType::Cloze => cloze_cards(&model, &fields),
-        };
-        let guid = guid.unwrap_or(&guid_for(&fields)).to_string();
+        let cards = generate_cards(&model, &fields)?;
+        let guid = guid.unwrap_or(&guid_for(model.id, &fields)).to_string();
         Ok(Self {
             model,
             fields,
@@ -112,19 +118,89 @@ impl Note {
         }
     }
 
+    /// Sets the state of the card generated by the template with ordinal `ord`, starting at 0
+    ///
+    /// Returns `Err` if the note has no such card, e.g. because the fields required by the
+    /// template are empty
+    pub fn card_state(mut self, ord: i64, state: CardState) -> Result<Self> {
+        let card = self
+            .cards
+            .iter_mut()
+            .find(|card| card.ord == ord)
+            .ok_or_else(|| anyhow!(Error::NoSuchCard(ord)))?;
+        card.state = state;
+        Ok(self)
+    }
+
+    /// Sets the state of the card generated by cloze number `cloze` (`{{c1::...}}` is 1) of a note
+    /// with a cloze model
+    ///
+    /// Returns `Err` if the note has no such cloze
+    pub fn cloze_state(self, cloze: i64, state: CardState) -> Result<Self> {
+        self.card_state(cloze - 1, state)
+    }
+
+    /// Converts the note to the new model of `migration`, keeping its GUID, tags and the states
+    /// of cards whose template still exists
+    ///
+    /// Returns `Err` if the fields do not match the old model of the migration
+    pub fn migrate(&self, migration: &ModelMigration) -> Result<Self> {
+        let fields = migration.migrate_fields(&self.fields)?;
+        let mut note = Note::new(
+            migration.to_model(),
+            fields.iter().map(String::as_str).collect(),
+        )?;
+        note.sort_field = self.sort_field;
+        note.tags = self.tags.clone();
+        note.guid = self.guid.clone();
+        for card in &self.cards {
+            if let Some(ord) = migration.card_ord(card.ord)
+                && let Some(new_card) = note.cards.iter_mut().find(|new| new.ord == ord)
+            {
+                new_card.state = card.state.clone();
+            }
+        }
+        Ok(note)
+    }
+
     pub(super) fn model(&self) -> Model {
         self.model.clone()
     }
 
-    #[allow(dead_code)]
     pub(super) fn cards(&self) -> Vec<Card> {
         self.cards.clone()
     }
 
-    fn get_guid(&self) -> String {
+    pub(super) fn get_guid(&self) -> String {
         self.guid.clone()
     }
 
+    pub(super) fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    pub(super) fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Replaces every field by the result of `map`, keeping the GUID of the note
+    ///
+    /// The cards are generated again from the new fields, keeping the states of the cards which
+    /// still exist.
+    pub(super) fn map_fields(&mut self, map: impl Fn(&str) -> String) -> Result<()> {
+        for field in &mut self.fields {
+            *field = map(field);
+        }
+        let mut cards = generate_cards(&self.model, &self.fields)?;
+        for card in &mut cards {
+            if let Some(old) = self.cards.iter().find(|old| old.ord == card.ord) {
+                card.state = old.state.clone();
+            }
+        }
+        self.cards = cards;
+        Ok(())
+    }
+
     fn check_number_model_fields_matches_num_fields(&self) -> Result<()> {
         if self.model.fields().len() != self.fields.len() {
             Err(anyhow!(Error::ModelFieldCountMismatch {
@@ -153,50 +229,147 @@ impl Note {
         self.fields.clone().join("\x1f")
     }
 
-    fn format_tags(&self) -> String {
-        format!(" {} ", self.tags.join(" "))
+    fn format_tags(&self) -> Result<String> {
+        Ok(format!(" {} ", normalize_tags(&self.tags)?.join(" ")))
     }
+    /// Writes the note and its cards, returning `false` if an existing note was kept instead
     pub(super) async fn write_to_db(
         &self,
         conn: &mut SqliteConnection,
         timestamp: f64,
         deck_id: i64,
-    ) -> Result<()> {
+        policy: ConflictPolicy,
+    ) -> Result<bool> {
         self.check_number_model_fields_matches_num_fields()?;
         self.check_invalid_html_tags_in_fields()?;
         // let mut conn = pool.acquire().await?;
         let guid = self.get_guid();
         let timestamp_i64 = timestamp as i64;
-        let tags = self.format_tags();
+        let mut tags = self.format_tags()?;
         let flds = self.format_fields();
+        let csum = self.fields.first().map_or(0, |field| field_checksum(field));
 
-        let rec = sqlx::query!(
+        let existing = sqlx::query!(
             r#"
-                INSERT INTO notes (guid, mid, mod, usn, tags, flds, sfld, csum, flags, data)
-                VALUES(?,?,?,?,?,?,?,?,?,?)
+                SELECT id, mid, tags FROM notes WHERE guid = ?
         "#,
-            guid,
-            self.model.id, // mid
-            timestamp_i64,
-            -1, // usn
-            tags,
-            flds,
-            self.sort_field, // sfld
-            0,               // csum, can be ignored
-            0,               // flags
-            "",              // data
+            guid
         )
-        .execute(&mut *conn)
+        .fetch_optional(&mut *conn)
         .await?;
-        let note_id = rec.last_insert_rowid() as usize;
+
+        // A note with the same GUID is handled according to `policy`, keeping its existing cards.
+        // Its model is never replaced, as cards of templates missing in the new model would remain.
+        let (note_id, existing_ords) = match existing {
+            Some(_) if policy == ConflictPolicy::Skip => return Ok(false),
+            Some(existing) if policy == ConflictPolicy::Error || existing.mid != self.model.id => {
+                return Err(anyhow!(Error::DuplicateGuid(guid)));
+            }
+            Some(existing) => {
+                let note_id = existing.id;
+                if policy == ConflictPolicy::MergeTags {
+                    let merged = existing
+                        .tags
+                        .split_whitespace()
+                        .chain(tags.split_whitespace());
+                    tags = format!(" {} ", normalize_tags(merged)?.join(" "));
+                }
+                sqlx::query!(
+                    r#"
+                        UPDATE notes SET mod = ?, usn = ?, tags = ?, flds = ?, sfld = ?, csum = ?
+                        WHERE id = ?
+                "#,
+                    timestamp_i64,
+                    -1, // usn
+                    tags,
+                    flds,
+                    self.sort_field, // sfld
+                    csum,
+                    note_id,
+                )
+                .execute(&mut *conn)
+                .await?;
+                let ords = sqlx::query_scalar!(
+                    r#"
+                        SELECT ord FROM cards WHERE nid = ?
+                "#,
+                    note_id
+                )
+                .fetch_all(&mut *conn)
+                .await?;
+                (note_id, ords)
+            }
+            None => {
+                let rec = sqlx::query!(
+                    r#"
+                        INSERT INTO notes (guid, mid, mod, usn, tags, flds, sfld, csum, flags, data)
+                        VALUES(?,?,?,?,?,?,?,?,?,?)
+                "#,
+                    guid,
+                    self.model.id, // mid
+                    timestamp_i64,
+                    -1, // usn
+                    tags,
+                    flds,
+                    self.sort_field, // sfld
+                    csum,
+                    0,  // flags
+                    "", // data
+                )
+                .execute(&mut *conn)
+                .await?;
+                (rec.last_insert_rowid(), vec![])
+            }
+        };
 
         for card in &self.cards {
-            card.write_to_db(conn, timestamp, deck_id, note_id).await?;
+            let deck_id = self.model.template_deck_id(card.ord).unwrap_or(deck_id);
+            if existing_ords.contains(&card.ord) {
+                // Existing cards keep their scheduling and move to the deck the note is written
+                // to, or back to it when the filtered deck they are in is emptied
+                sqlx::query!(
+                    r#"
+                        UPDATE cards SET
+                            did = CASE WHEN odid = 0 THEN ? ELSE did END,
+                            odid = CASE WHEN odid = 0 THEN 0 ELSE ? END,
+                            mod = ?, usn = ?
+                        WHERE nid = ? AND ord = ?
+                "#,
+                    deck_id,
+                    deck_id,
+                    timestamp_i64,
+                    -1, // usn
+                    note_id,
+                    card.ord,
+                )
+                .execute(&mut *conn)
+                .await?;
+                continue;
+            }
+            card.write_to_db(conn, timestamp, deck_id, note_id as usize)
+                .await?;
         }
-        Ok(())
+        Ok(true)
     }
 }
 
+static IMG_SRC: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)<img[^>]+src=["']?([^"'>]+)["']?[^>]*>"#).expect("static regex")
+});
+static HTML_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<!--.*?-->|<[^>]*>").expect("static regex"));
+
+/// Returns the checksum Anki uses to find duplicates: the first 32 bits of the SHA-1 of `field`
+/// without HTML, keeping the names of images
+fn field_checksum(field: &str) -> i64 {
+    let text = IMG_SRC.replace_all(field, " $1 ");
+    let text = decode_entities(&HTML_TAG.replace_all(&text, ""));
+    let digest = Sha1::digest(text.as_bytes());
+    i64::from(u32::from_be_bytes([
+        digest[0], digest[1], digest[2], digest[3],
+    ]))
+}
+
 fn cloze_cards(model: &Model, self_fields: &[String]) -> Vec<Card> {
     let mut card_ords: HashSet<i64> = HashSet::new();
     let mut cloze_replacements: HashSet<String> = HashSet::new();
@@ -233,6 +406,13 @@ fn cloze_cards(model: &Model, self_fields: &[String]) -> Vec<Card> {
         .collect()
 }
 
+fn generate_cards(model: &Model, fields: &[String]) -> Result<Vec<Card>> {
+    Ok(match model.get_model_type() {
+        ModelType::FrontBack => front_back_cards(model, fields)?,
+        ModelType::Cloze => cloze_cards(model, fields),
+    })
+}
+
 fn front_back_cards(model: &Model, self_fields: &[String]) -> Result<Vec<Card>> {
     let mut rv = vec![];
     for (card_ord, any_or_all, required_field_ords) in model.req()?.drain(..) {
@@ -264,12 +444,8 @@ fn re_findall(regex_str: &'static str, to_match: &str) -> Vec<String> {
         .collect()
 }
 
-fn validate_tags(tags: &[String]) -> Result<()> {
-    if tags.iter().any(|tag| tag.contains(' ')) {
-        Err(anyhow!(Error::TagContainsWhitespace))
-    } else {
-        Ok(())
-    }
+pub(super) fn validate_tags(tags: &[String]) -> Result<()> {
+    tags.iter().try_for_each(|tag| validate_tag(tag))
 }
 
 fn find_invalid_html_tags_in_field(field: &str) -> Vec<String> {
@@ -313,11 +489,156 @@ mod tests {
 
         let mut conn = pool.acquire().await.unwrap();
         my_note
-            .write_to_db(&mut conn, timestamp, deck_id)
+            .write_to_db(&mut conn, timestamp, deck_id, ConflictPolicy::Overwrite)
             .await
             .unwrap();
     }
 
+    #[sqlx::test(fixtures("anki"))]
+    async fn card_states_are_written(pool: Pool<Sqlite>) {
+        let note = Note::new(crate::basic_and_reversed_card_model(), vec!["Hund", "dog"])
+            .unwrap()
+            .card_state(
+                0,
+                CardState::new()
+                    .buried(true)
+                    .flag(crate::Flag::Purple)
+                    .position(7),
+            )
+            .unwrap()
+            .card_state(1, CardState::new().suspended(true).buried(true))
+            .unwrap();
+        let (timestamp, deck_id) = write_to_db_setup();
+        let mut conn = pool.acquire().await.unwrap();
+        note.write_to_db(&mut conn, timestamp, deck_id, ConflictPolicy::Overwrite)
+            .await
+            .unwrap();
+
+        let cards = sqlx::query!("SELECT ord, queue, due, flags FROM cards ORDER BY ord")
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|card| (card.ord, card.queue, card.due, card.flags))
+            .collect::<Vec<_>>();
+        assert_eq!(cards, [(0, -3, 7, 7), (1, -1, 0, 0)]);
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn scheduling_and_reviews_are_written(pool: Pool<Sqlite>) {
+        use crate::{Rating, Review, ReviewKind, Scheduling};
+
+        let created = 1411124400;
+        let note = Note::new(crate::basic_and_reversed_card_model(), vec!["Hund", "dog"])
+            .unwrap()
+            .card_state(
+                0,
+                CardState::new()
+                    .scheduling(Scheduling::Review {
+                        due: created + 30 * 86400 + 100,
+                        interval: 12,
+                        ease: 2.35,
+                    })
+                    .reps(3)
+                    .lapses(1)
+                    .review(
+                        Review::new(1_500_000_000_000, Rating::Good)
+                            .interval(-600)
+                            .kind(ReviewKind::Learning),
+                    )
+                    .review(
+                        Review::new(1_500_100_000_000, Rating::Easy)
+                            .interval(12)
+                            .last_interval(-600)
+                            .ease(2.35)
+                            .duration(4200),
+                    ),
+            )
+            .unwrap()
+            .card_state(
+                1,
+                CardState::new().scheduling(Scheduling::Learning {
+                    due: created + 600,
+                    remaining_steps: 2,
+                }),
+            )
+            .unwrap();
+        let (timestamp, deck_id) = write_to_db_setup();
+        let mut conn = pool.acquire().await.unwrap();
+        note.write_to_db(&mut conn, timestamp, deck_id, ConflictPolicy::Overwrite)
+            .await
+            .unwrap();
+
+        let cards = sqlx::query!(
+            "SELECT type, queue, due, ivl, factor, reps, lapses, left FROM cards ORDER BY ord"
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| {
+            (
+                c.r#type, c.queue, c.due, c.ivl, c.factor, c.reps, c.lapses, c.left,
+            )
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(
+            cards,
+            [
+                (2, 2, 30, 12, 2350, 3, 1, 0),
+                (1, 1, created + 600, 0, 0, 0, 0, 2002)
+            ]
+        );
+
+        let card_id = sqlx::query_scalar!("SELECT id FROM cards WHERE ord = 0")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        let reviews = sqlx::query!(
+            "SELECT id, cid, ease, ivl, lastivl, factor, time, type FROM revlog ORDER BY id"
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| {
+            (
+                r.id, r.cid, r.ease, r.ivl, r.lastivl, r.factor, r.time, r.r#type,
+            )
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(
+            reviews,
+            [
+                (1_500_000_000_000, card_id, 3, -600, 0, 2500, 0, 0),
+                (1_500_100_000_000, card_id, 4, 12, -600, 2350, 4200, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn state_of_missing_card_is_an_error() {
+        let note = Note::new(crate::basic_model(), vec!["Hund", "dog"]).unwrap();
+        assert!(note.clone().card_state(1, CardState::new()).is_err());
+
+        let note = Note::new(crate::cloze_model(), vec!["{{c2::Berlin}}"]).unwrap();
+        let note = note
+            .cloze_state(2, CardState::new().suspended(true))
+            .unwrap();
+        assert!(note.cards()[0].state == CardState::new().suspended(true));
+        assert!(note.cloze_state(1, CardState::new()).is_err());
+    }
+
+    #[test]
+    fn checksum_of_first_field() {
+        assert_eq!(field_checksum("Hund"), 243334449);
+        assert_eq!(field_checksum("<b>Hund</b>"), 243334449);
+        assert_eq!(
+            field_checksum(r#"<img src="dog.jpg"> &amp; Katze"#),
+            1093713142
+        );
+    }
+
     #[test]
     fn tags_new() {
         let _ = Note::new_with_options(
@@ -371,7 +692,7 @@ mod tests {
         .unwrap();
         let (timestamp, deck_id) = write_to_db_setup();
         let mut conn = pool.acquire().await.unwrap();
-        note.write_to_db(&mut conn, timestamp, deck_id)
+        note.write_to_db(&mut conn, timestamp, deck_id, ConflictPolicy::Overwrite)
             .await
             .unwrap();
     }
@@ -397,7 +718,7 @@ mod tests {
         let note = Note::new(model, vec!["Capital of Germany", "Berlin"]).unwrap();
         let (timestamp, deck_id) = write_to_db_setup();
         let mut conn = pool.acquire().await.unwrap();
-        note.write_to_db(&mut conn, timestamp, deck_id)
+        note.write_to_db(&mut conn, timestamp, deck_id, ConflictPolicy::Overwrite)
             .await
             .unwrap();
     }
@@ -427,7 +748,7 @@ mod tests {
         .unwrap();
         let (timestamp, deck_id) = write_to_db_setup();
         let mut conn = pool.acquire().await.unwrap();
-        note.write_to_db(&mut conn, timestamp, deck_id)
+        note.write_to_db(&mut conn, timestamp, deck_id, ConflictPolicy::Overwrite)
             .await
             .unwrap();
     }
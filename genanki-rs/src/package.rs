@@ -1,18 +1,25 @@
 use sqlx::SqliteConnection;
 use sqlx::migrate::MigrateDatabase;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tempfile::NamedTempFile;
 use zip::ZipWriter;
 use zip::write::SimpleFileOptions;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{Read, Seek, Write};
 use std::path::{Path, PathBuf};
 
+use crate::Error;
+use crate::collection::init_collection;
+use crate::conflict::ConflictPolicy;
 use crate::deck::Deck;
 use crate::error::{json_error, zip_error};
-use anyhow::Result;
+use crate::math::report_math_problems;
+use crate::sanitize::HtmlSanitizer;
+use crate::validation::ValidationReport;
+use anyhow::{Result, anyhow};
 use std::str::FromStr;
 
 /// `Package` to pack `Deck`s and `media_files` and write them to a `.apkg` file
@@ -45,9 +52,15 @@ use std::str::FromStr;
 /// Ok(())
 /// }
 /// ```
+///
+/// The builder has the following default values:
+/// * `conflict_policy` - `ConflictPolicy::Overwrite`
+/// * `sanitizer` - `None`
 pub struct Package {
     decks: Vec<Deck>,
     media_files: Vec<PathBuf>,
+    conflict_policy: ConflictPolicy,
+    sanitizer: Option<HtmlSanitizer>,
 }
 
 impl Package {
@@ -59,7 +72,66 @@ impl Package {
             .iter()
             .map(|&s| PathBuf::from_str(s))
             .collect::<Result<Vec<_>, _>>()?;
-        Ok(Self { decks, media_files })
+        Ok(Self {
+            decks,
+            media_files,
+            conflict_policy: ConflictPolicy::default(),
+            sanitizer: None,
+        })
+    }
+
+    /// Sets what happens when two notes of the package have the same GUID
+    pub fn conflict_policy(self, conflict_policy: ConflictPolicy) -> Self {
+        Self {
+            conflict_policy,
+            ..self
+        }
+    }
+
+    /// Sets a sanitizer which is applied to all fields of all notes when they are written
+    ///
+    /// The cards of the notes are generated from the sanitized fields, so a card whose fields
+    /// become empty is left out. The notes of the package itself are not changed.
+    pub fn sanitizer(self, sanitizer: HtmlSanitizer) -> Self {
+        Self {
+            sanitizer: Some(sanitizer),
+            ..self
+        }
+    }
+
+    /// Checks the LaTeX and MathJax in the fields of all notes, see [`check_math`](crate::check_math)
+    ///
+    /// Every problem is reported as a warning for the note it was found in.
+    pub fn validate_math(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        for deck in &self.decks {
+            for note in deck.notes() {
+                let model = note.model();
+                report_math_problems(
+                    &mut report,
+                    &format!("note {}", note.get_guid()),
+                    model.fields().iter().map(|field| field.name.as_str()),
+                    note.fields(),
+                );
+            }
+        }
+        report
+    }
+
+    pub(super) fn decks(&self) -> &[Deck] {
+        &self.decks
+    }
+
+    pub(super) fn media_files(&self) -> &[PathBuf] {
+        &self.media_files
+    }
+
+    pub(super) fn decks_mut(&mut self) -> &mut [Deck] {
+        &mut self.decks
+    }
+
+    pub(super) fn set_media_files(&mut self, media_files: Vec<PathBuf>) {
+        self.media_files = media_files;
     }
 
     /// Writes the package to any writer that implements Write and Seek
@@ -134,13 +206,51 @@ impl Package {
             SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64()
         };
 
+        self.check_deck_overrides(&mut *conn).await?;
         for deck in &mut self.decks {
-            deck.write_to_db(&mut *conn, timestamp).await?;
+            // The decks of the package are kept as they are, only the written notes are sanitized
+            match &self.sanitizer {
+                Some(sanitizer) => {
+                    let mut sanitized = deck.clone();
+                    sanitized.map_fields(|field| sanitizer.sanitize(field))?;
+                    sanitized
+                        .write_to_db(&mut *conn, timestamp, self.conflict_policy)
+                        .await?;
+                }
+                None => {
+                    deck.write_to_db(&mut *conn, timestamp, self.conflict_policy)
+                        .await?
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Makes sure every deck a template places cards in is part of the package or the collection
+    async fn check_deck_overrides(&self, conn: &mut SqliteConnection) -> Result<()> {
+        let rec = sqlx::query!(
+            r#"
+            SELECT decks FROM col
+        "#
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        let existing: HashMap<i64, serde_json::Value> =
+            serde_json::from_str(&rec.decks).map_err(json_error)?;
+
+        let mut known = existing.into_keys().collect::<HashSet<_>>();
+        for deck in &self.decks {
+            known.extend(deck.written_deck_ids());
+        }
+        for deck in &self.decks {
+            if let Some(&missing) = deck.card_deck_ids().difference(&known).min() {
+                return Err(anyhow!(Error::UnknownDeck(missing)));
+            }
+        }
+        Ok(())
+    }
+
     pub fn write_to_zip<W: Write + Seek>(&mut self, writer: W, db_file_path: &Path) -> Result<()> {
         let options = SimpleFileOptions::default()
             .compression_method(zip::CompressionMethod::Stored)
@@ -155,7 +265,7 @@ impl Package {
             .media_files
             .iter()
             .enumerate()
-            .collect::<HashMap<usize, &PathBuf>>();
+            .collect::<BTreeMap<usize, &PathBuf>>();
         let media_map = media_file_idx_to_path
             .clone()
             .into_iter()
@@ -168,7 +278,7 @@ impl Package {
                         .expect("should always have string"),
                 )
             })
-            .collect::<HashMap<String, &str>>();
+            .collect::<BTreeMap<String, &str>>();
         let media_json = serde_json::to_string(&media_map).map_err(json_error)?;
         outzip.start_file("media", options).map_err(zip_error)?;
         outzip.write_all(media_json.as_bytes())?;
@@ -190,12 +300,12 @@ impl Package {
         let db_file_url = db_file_path.to_str().expect("DB file should be created");
         sqlx::Sqlite::create_database(db_file_url).await?;
 
-        let pool = sqlx::SqlitePool::connect(db_file_url).await?;
+        // Without a write-ahead log all writes end up in the file that is zipped
+        let options =
+            SqliteConnectOptions::from_str(db_file_url)?.journal_mode(SqliteJournalMode::Delete);
+        let pool = sqlx::SqlitePool::connect_with(options).await?;
         let mut conn = pool.acquire().await?;
-        sqlx::migrate!().run(&mut *conn).await?;
-        sqlx::query_file!("fixtures/anki.sql")
-            .execute(&mut *conn)
-            .await?;
+        init_collection(&mut conn).await?;
 
         self.write_to_file_timestamp(file_name, timestamp, &mut conn, db_file_path)
             .await?;
@@ -0,0 +1,798 @@
+use crate::builders::{Field, Template};
+use crate::csv_import::CsvImporter;
+use crate::deck::Deck;
+use crate::error::{csv_error, manifest_error};
+use crate::math::report_math_problems;
+use crate::media::media_references;
+use crate::model::{Model, ModelType};
+use crate::note::{Note, validate_tags};
+use crate::package::Package;
+use crate::tags::normalize_tags;
+use crate::util::id_for;
+use crate::validation::ValidationReport;
+use crate::{Error, builtin_models};
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const TOML_MANIFEST: &str = "genanki.toml";
+const JSON_MANIFEST: &str = "genanki.json";
+
+/// A deck source directory which can be built into a `Package`.
+///
+/// The directory contains a manifest named `genanki.toml` (or `genanki.json`) describing models
+/// and decks, the note files the decks are made of and a `media/` directory. Only media files
+/// referenced by a note or a template are packaged, as well as files starting with `_`, which
+/// Anki keeps for use in templates and CSS.
+///
+/// ```toml
+/// [[models]]
+/// id = 1607392319
+/// name = "Capitals"
+/// fields = ["Country", { name = "Capital", font = "Arial" }, "Flag"]
+/// css_file = "style.css"
+///
+/// [[models.templates]]
+/// name = "Card 1"
+/// qfmt = "{{Country}}<br>{{Flag}}"
+/// afmt_file = "templates/back.html"
+///
+/// [[decks]]
+/// name = "Geography::Capitals"
+/// description = "Capitals of the world"
+/// model = "Capitals"
+/// notes = ["notes/europe.csv", { path = "notes/asia.tsv", tags_column = "Tags" }]
+/// ```
+///
+/// Models can also refer to the built-in models `basic`, `basic_and_reversed_card`,
+/// `basic_optional_reversed_card`, `basic_type_in_the_answer`,
+/// `basic_type_in_the_answer_reversed`, `cloze`, `cloze_with_back_extra`, `image_occlusion` and
+/// `vocabulary`. Deck and model ids are derived from their names when omitted.
+///
+/// Note files are CSV (`.csv`), TSV (`.tsv`) or JSON (`.json`). CSV and TSV files need a header
+/// row naming the model fields, JSON files contain an array of notes:
+///
+/// ```json
+/// [{ "fields": { "Country": "France", "Capital": "Paris" }, "tags": ["europe"], "guid": "fr" }]
+/// ```
+///
+/// Example:
+///
+/// ```rust,no_run
+/// use genanki_rs::Project;
+/// use anyhow::Result;
+///
+/// #[tokio::main] async fn main() -> Result<()> {
+///     let (mut package, report) = Project::load("my-deck")?.build()?;
+///     print!("{}", report);
+///     if report.is_ok() {
+///         package.generate_anki("my-deck.apkg", Some(0.0)).await?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct Project {
+    root: PathBuf,
+    manifest: Manifest,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    models: Vec<ModelSpec>,
+    #[serde(default)]
+    decks: Vec<DeckSpec>,
+    #[serde(default = "default_media_dir")]
+    media_dir: String,
+}
+
+fn default_media_dir() -> String {
+    "media".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelSpec {
+    id: Option<i64>,
+    name: String,
+    #[serde(default, rename = "type")]
+    model_type: ModelTypeSpec,
+    fields: Vec<FieldSpec>,
+    templates: Vec<TemplateSpec>,
+    css: Option<String>,
+    css_file: Option<String>,
+    sort_field: Option<i64>,
+    latex_pre: Option<String>,
+    latex_post: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum ModelTypeSpec {
+    #[default]
+    FrontBack,
+    Cloze,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FieldSpec {
+    Name(String),
+    Options {
+        name: String,
+        font: Option<String>,
+        size: Option<i64>,
+        rtl: Option<bool>,
+        sticky: Option<bool>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateSpec {
+    name: String,
+    qfmt: Option<String>,
+    qfmt_file: Option<String>,
+    afmt: Option<String>,
+    afmt_file: Option<String>,
+    bqfmt: Option<String>,
+    bafmt: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeckSpec {
+    id: Option<i64>,
+    name: String,
+    #[serde(default)]
+    description: String,
+    model: Option<String>,
+    #[serde(default)]
+    notes: Vec<NoteSource>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NoteSource {
+    Path(String),
+    Options(NoteFile),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoteFile {
+    path: String,
+    model: Option<String>,
+    tags_column: Option<String>,
+    guid_column: Option<String>,
+    deck_column: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonNote {
+    model: Option<String>,
+    fields: JsonFields,
+    #[serde(default)]
+    tags: Vec<String>,
+    guid: Option<String>,
+    deck: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonFields {
+    Positional(Vec<String>),
+    Named(HashMap<String, String>),
+}
+
+impl Project {
+    /// Reads the manifest of the project in `dir`
+    ///
+    /// Returns `Err` if there is no manifest or it is invalid
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let root = dir.as_ref().to_path_buf();
+        let manifest = if root.join(TOML_MANIFEST).exists() {
+            toml::from_str(&fs::read_to_string(root.join(TOML_MANIFEST))?)
+                .map_err(manifest_error)?
+        } else {
+            serde_json::from_str(&fs::read_to_string(root.join(JSON_MANIFEST))?)
+                .map_err(manifest_error)?
+        };
+        Ok(Self { root, manifest })
+    }
+
+    /// Creates a project consisting of a single note file, whose notes all go into the deck
+    /// `deck_name`. `model_name` is either the name of a built-in model, see [`Project`], or is
+    /// ignored for JSON notes naming their own model.
+    ///
+    /// For CSV and TSV files, header columns named `Tags`, `Guid` and `Deck` are read as tags,
+    /// GUID and target deck of a note unless the model has a field of the same name. Media files
+    /// are looked up in a `media/` directory next to the note file.
+    ///
+    /// Returns `Err` if the file cannot be read
+    pub fn from_note_file<P: AsRef<Path>>(
+        path: P,
+        deck_name: &str,
+        model_name: &str,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("{:?} is not a valid note file name", path))?
+            .to_string();
+
+        let source = if file_name.ends_with(".json") {
+            NoteSource::Path(file_name)
+        } else {
+            let delimiter = if file_name.ends_with(".tsv") {
+                b'\t'
+            } else {
+                b','
+            };
+            let headers = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .from_path(path)
+                .and_then(|mut reader| reader.headers().cloned())
+                .map_err(csv_error)?;
+            let model_fields = lookup_model(&HashMap::new(), model_name)?
+                .fields()
+                .into_iter()
+                .map(|field| field.name)
+                .collect::<Vec<_>>();
+            let special_column = |name: &str| {
+                (headers.iter().any(|header| header.trim() == name)
+                    && !model_fields.iter().any(|field| field == name))
+                .then(|| name.to_string())
+            };
+            NoteSource::Options(NoteFile {
+                path: file_name,
+                model: None,
+                tags_column: special_column("Tags"),
+                guid_column: special_column("Guid"),
+                deck_column: special_column("Deck"),
+            })
+        };
+
+        Ok(Self {
+            root,
+            manifest: Manifest {
+                models: vec![],
+                decks: vec![DeckSpec {
+                    id: None,
+                    name: deck_name.to_string(),
+                    description: String::new(),
+                    model: Some(model_name.to_string()),
+                    notes: vec![source],
+                }],
+                media_dir: default_media_dir(),
+            },
+        })
+    }
+
+    /// Builds the `Package` described by the project
+    ///
+    /// Problems with single notes or media files are collected in the returned
+    /// `ValidationReport` and the affected notes are left out. Returns `Err` if the manifest
+    /// refers to missing files or unknown models.
+    pub fn build(&self) -> Result<(Package, ValidationReport)> {
+        let mut report = ValidationReport::default();
+        let models = self.models()?;
+        let decks = self.decks(&models, &mut report)?;
+        let media_files = self.media_files(&decks, &models, &mut report)?;
+        let media_files = media_files
+            .iter()
+            .map(|path| {
+                path.to_str()
+                    .ok_or_else(|| anyhow!("media path {:?} is not valid UTF-8", path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((Package::new(decks, media_files)?, report))
+    }
+
+    fn read(&self, path: &str) -> Result<String> {
+        fs::read_to_string(self.root.join(path))
+            .map_err(|e| anyhow!("could not read {}: {}", path, e))
+    }
+
+    fn models(&self) -> Result<HashMap<String, Model>> {
+        let mut models = HashMap::new();
+        for spec in &self.manifest.models {
+            let fields = spec
+                .fields
+                .iter()
+                .map(|field| match field {
+                    FieldSpec::Name(name) => Field::new(name),
+                    FieldSpec::Options {
+                        name,
+                        font,
+                        size,
+                        rtl,
+                        sticky,
+                    } => {
+                        let mut field = Field::new(name);
+                        if let Some(font) = font {
+                            field = field.font(font);
+                        }
+                        if let Some(size) = size {
+                            field = field.size(*size);
+                        }
+                        field
+                            .rtl(rtl.unwrap_or(false))
+                            .sticky(sticky.unwrap_or(false))
+                    }
+                })
+                .collect();
+            let templates = spec
+                .templates
+                .iter()
+                .map(|template| {
+                    let qfmt = self.inline_or_file(&template.qfmt, &template.qfmt_file)?;
+                    let afmt = self.inline_or_file(&template.afmt, &template.afmt_file)?;
+                    let mut built = Template::new(&template.name).qfmt(&qfmt).afmt(&afmt);
+                    if let Some(bqfmt) = &template.bqfmt {
+                        built = built.bqfmt(bqfmt);
+                    }
+                    if let Some(bafmt) = &template.bafmt {
+                        built = built.bafmt(bafmt);
+                    }
+                    Ok(built)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut model = Model::new(
+                spec.id.unwrap_or_else(|| id_for(&spec.name)),
+                &spec.name,
+                fields,
+                templates,
+            )
+            .css(self.inline_or_file(&spec.css, &spec.css_file)?)
+            .sort_field_index(spec.sort_field.unwrap_or(0));
+            if let ModelTypeSpec::Cloze = spec.model_type {
+                model = model.model_type(ModelType::Cloze);
+            }
+            if let Some(latex_pre) = &spec.latex_pre {
+                model = model.latex_pre(latex_pre);
+            }
+            if let Some(latex_post) = &spec.latex_post {
+                model = model.latex_post(latex_post);
+            }
+            models.insert(spec.name.clone(), model);
+        }
+        Ok(models)
+    }
+
+    fn inline_or_file(&self, inline: &Option<String>, file: &Option<String>) -> Result<String> {
+        match (inline, file) {
+            (Some(inline), _) => Ok(inline.clone()),
+            (None, Some(file)) => self.read(file),
+            (None, None) => Ok(String::new()),
+        }
+    }
+
+    fn decks(
+        &self,
+        models: &HashMap<String, Model>,
+        report: &mut ValidationReport,
+    ) -> Result<Vec<Deck>> {
+        let mut decks: Vec<Deck> = vec![];
+        for spec in &self.manifest.decks {
+            let id = spec.id.unwrap_or_else(|| id_for(&spec.name));
+            if decks.iter().all(|deck| deck.name() != spec.name) {
+                decks.push(Deck::new(id, &spec.name, &spec.description));
+            }
+            for source in &spec.notes {
+                let (path, options) = match source {
+                    NoteSource::Path(path) => (path, None),
+                    NoteSource::Options(options) => (&options.path, Some(options)),
+                };
+                let model_name = options
+                    .and_then(|options| options.model.as_ref())
+                    .or(spec.model.as_ref());
+                let model = model_name
+                    .map(|name| lookup_model(models, name))
+                    .transpose()?;
+                let notes = if path.ends_with(".json") {
+                    self.json_notes(path, model, models, report)?
+                } else {
+                    self.csv_notes(path, model, options, report)?
+                };
+                for (deck, note) in notes {
+                    let deck = deck.unwrap_or_else(|| spec.name.clone());
+                    deck_by_name(&mut decks, &deck).add_note(note);
+                }
+            }
+        }
+
+        // Notes with errors are reported and left out, the first note with a GUID is kept
+        let mut guids = HashSet::new();
+        for deck in &mut decks {
+            deck.retain_notes(|note| {
+                let guid = note.get_guid();
+                if note.cards().is_empty() {
+                    report.warning(&guid, "note does not produce any cards");
+                }
+                let model = note.model();
+                report_math_problems(
+                    report,
+                    &guid,
+                    model.fields().iter().map(|field| field.name.as_str()),
+                    note.fields(),
+                );
+                if let Err(e) = normalize_tags(note.get_tags()) {
+                    report.error(&guid, e);
+                    return false;
+                }
+                if !guids.insert(guid.clone()) {
+                    report.error(&guid, "GUID is used by more than one note");
+                    return false;
+                }
+                true
+            });
+            if deck.notes().is_empty() {
+                report.warning(deck.name(), "deck contains no notes");
+            }
+        }
+        Ok(decks)
+    }
+
+    fn csv_notes(
+        &self,
+        path: &str,
+        model: Option<Model>,
+        options: Option<&NoteFile>,
+        report: &mut ValidationReport,
+    ) -> Result<Vec<(Option<String>, Note)>> {
+        let model = model.ok_or_else(|| anyhow!("no model given for {}", path))?;
+        let mut importer = if path.ends_with(".tsv") {
+            CsvImporter::tsv(model)
+        } else {
+            CsvImporter::new(model)
+        };
+        if let Some(options) = options {
+            if let Some(column) = &options.tags_column {
+                importer = importer.tags_column(column.as_str());
+            }
+            if let Some(column) = &options.guid_column {
+                importer = importer.guid_column(column.as_str());
+            }
+            if let Some(column) = &options.deck_column {
+                importer = importer.deck_column(column.as_str());
+            }
+        }
+        let import = importer.import_path(self.root.join(path))?;
+        for error in &import.errors {
+            report.error(format!("{}:{}", path, error.row), &error.message);
+        }
+        Ok(import
+            .notes
+            .into_iter()
+            .map(|imported| (imported.deck, imported.note))
+            .collect())
+    }
+
+    fn json_notes(
+        &self,
+        path: &str,
+        model: Option<Model>,
+        models: &HashMap<String, Model>,
+        report: &mut ValidationReport,
+    ) -> Result<Vec<(Option<String>, Note)>> {
+        let json_notes: Vec<JsonNote> =
+            serde_json::from_str(&self.read(path)?).map_err(manifest_error)?;
+        let mut notes = vec![];
+        for (i, json_note) in json_notes.into_iter().enumerate() {
+            let location = format!("{}#{}", path, i);
+            let note_model = match (&json_note.model, &model) {
+                (Some(name), _) => lookup_model(models, name),
+                (None, Some(model)) => Ok(model.clone()),
+                (None, None) => Err(anyhow!("no model given")),
+            };
+            match note_model.and_then(|model| json_note.into_note(model)) {
+                Ok(note) => notes.push(note),
+                Err(e) => report.error(location, e),
+            }
+        }
+        Ok(notes)
+    }
+
+    fn media_files(
+        &self,
+        decks: &[Deck],
+        models: &HashMap<String, Model>,
+        report: &mut ValidationReport,
+    ) -> Result<Vec<PathBuf>> {
+        let media_dir = self.root.join(&self.manifest.media_dir);
+        let mut available = BTreeSet::new();
+        if media_dir.is_dir() {
+            for entry in fs::read_dir(&media_dir)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                if let Ok(name) = entry.file_name().into_string()
+                    && !name.starts_with('.')
+                {
+                    available.insert(name);
+                }
+            }
+        }
+
+        let mut referenced = BTreeSet::new();
+        for model in models.values() {
+            for template in model.templates() {
+                referenced.extend(media_references(&template.qfmt));
+                referenced.extend(media_references(&template.afmt));
+            }
+        }
+        for deck in decks {
+            for note in deck.notes() {
+                for field in note.fields() {
+                    for reference in media_references(field) {
+                        if !available.contains(&reference) {
+                            report.warning(
+                                note.get_guid(),
+                                format!("references missing media file {:?}", reference),
+                            );
+                        }
+                        referenced.insert(reference);
+                    }
+                }
+            }
+        }
+
+        let mut media_files = vec![];
+        for name in available {
+            if name.starts_with('_') || referenced.contains(&name) {
+                media_files.push(media_dir.join(name));
+            } else {
+                report.warning(
+                    format!("{}/{}", self.manifest.media_dir, name),
+                    "media file is not referenced by any note and was skipped",
+                );
+            }
+        }
+        Ok(media_files)
+    }
+}
+
+impl JsonNote {
+    fn into_note(self, model: Model) -> Result<(Option<String>, Note)> {
+        validate_tags(&self.tags)?;
+        let note = match self.fields {
+            JsonFields::Positional(fields) => {
+                Note::new(model, fields.iter().map(String::as_str).collect())?
+            }
+            JsonFields::Named(named) => Note::builder(model).fields(named).build()?,
+        };
+        let mut note = note.tags(self.tags);
+        if let Some(guid) = self.guid {
+            note = note.guid(guid);
+        }
+        Ok((self.deck, note))
+    }
+}
+
+fn lookup_model(models: &HashMap<String, Model>, name: &str) -> Result<Model> {
+    if let Some(model) = models.get(name) {
+        return Ok(model.clone());
+    }
+    match name {
+        "basic" => Ok(builtin_models::basic_model()),
+        "basic_and_reversed_card" => Ok(builtin_models::basic_and_reversed_card_model()),
+        "basic_optional_reversed_card" => Ok(builtin_models::basic_optional_reversed_card_model()),
+        "basic_type_in_the_answer" => Ok(builtin_models::basic_type_in_the_answer_model()),
+        "basic_type_in_the_answer_reversed" => {
+            Ok(builtin_models::basic_type_in_the_answer_reversed_model())
+        }
+        "cloze" => Ok(builtin_models::cloze_model()),
+        "cloze_with_back_extra" => Ok(builtin_models::cloze_with_back_extra_model()),
+        "image_occlusion" => Ok(builtin_models::image_occlusion_model()),
+        "vocabulary" => Ok(builtin_models::vocabulary_model()),
+        _ => Err(anyhow!(Error::UnknownModel(name.to_string()))),
+    }
+}
+
+fn deck_by_name<'a>(decks: &'a mut Vec<Deck>, name: &str) -> &'a mut Deck {
+    let index = match decks.iter().position(|deck| deck.name() == name) {
+        Some(index) => index,
+        None => {
+            decks.push(Deck::new(id_for(name), name, ""));
+            decks.len() - 1
+        }
+    };
+    &mut decks[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::Severity;
+    use tempfile::TempDir;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn example_project() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            TOML_MANIFEST,
+            r#"
+[[models]]
+id = 1607392319
+name = "Capitals"
+fields = ["Country", { name = "Capital", font = "Arial" }, "Flag"]
+css_file = "style.css"
+
+[[models.templates]]
+name = "Card 1"
+qfmt = "{{Country}}<br>{{Flag}}"
+afmt_file = "back.html"
+
+[[decks]]
+name = "Geography"
+model = "Capitals"
+notes = [
+    "notes/europe.csv",
+    { path = "notes/asia.tsv", tags_column = "Tags", deck_column = "Deck" },
+    "notes/extra.json",
+]
+
+[[decks]]
+name = "Empty"
+"#,
+        );
+        write(dir.path(), "style.css", ".card { color: black; }");
+        write(
+            dir.path(),
+            "back.html",
+            r#"{{FrontSide}}<hr id=answer>{{Capital}}<img src="_logo.png">"#,
+        );
+        write(
+            dir.path(),
+            "notes/europe.csv",
+            "Country,Capital,Flag\nFrance,Paris,<img src=\"fr.svg\">\nItaly,Rome,<img src=\"it.svg\">\n",
+        );
+        write(
+            dir.path(),
+            "notes/asia.tsv",
+            "Country\tCapital\tFlag\tTags\tDeck\nJapan\tTokyo\t\tasia island\tGeography::Asia\nChina\n",
+        );
+        write(
+            dir.path(),
+            "notes/extra.json",
+            r#"[
+                {"fields": {"Country": "Peru", "Capital": "Lima"}, "guid": "pe"},
+                {"model": "basic", "fields": ["Capital of Chile?", "Santiago"], "tags": ["americas"]},
+                {"fields": {"Land": "Chile"}},
+                {"fields": {"Country": "Chile"}, "tags": ["south america"]}
+            ]"#,
+        );
+        write(dir.path(), "media/fr.svg", "<svg/>");
+        write(dir.path(), "media/_logo.png", "png");
+        write(dir.path(), "media/unused.mp3", "mp3");
+        dir
+    }
+
+    #[test]
+    fn builds_decks_and_reports_problems() {
+        let dir = example_project();
+        let project = Project::load(dir.path()).unwrap();
+        let mut report = ValidationReport::default();
+        let models = project.models().unwrap();
+        let decks = project.decks(&models, &mut report).unwrap();
+
+        assert_eq!(
+            decks.iter().map(Deck::name).collect::<Vec<_>>(),
+            ["Geography", "Geography::Asia", "Empty"]
+        );
+        assert_eq!(decks[0].notes().len(), 4);
+        assert_eq!(decks[1].notes().len(), 1);
+        assert_eq!(decks[1].notes()[0].get_tags(), ["asia", "island"]);
+        assert_eq!(decks[0].notes()[2].get_guid(), "pe");
+
+        let errors = report.errors().collect::<Vec<_>>();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].location, "notes/asia.tsv:3");
+        assert_eq!(errors[1].location, "notes/extra.json#2");
+        assert_eq!(errors[2].location, "notes/extra.json#3");
+        assert!(
+            report
+                .warnings()
+                .any(|issue| issue.location == "Empty" && issue.severity == Severity::Warning)
+        );
+
+        let media = project.media_files(&decks, &models, &mut report).unwrap();
+        let media = media
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(media, ["_logo.png", "fr.svg"]);
+        assert!(
+            report
+                .warnings()
+                .any(|issue| issue.location == "media/unused.mp3")
+        );
+        assert!(
+            report
+                .warnings()
+                .any(|issue| issue.message.contains("\"it.svg\""))
+        );
+    }
+
+    #[test]
+    fn build_returns_package_and_report() {
+        let dir = example_project();
+        let (_package, report) = Project::load(dir.path()).unwrap().build().unwrap();
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn notes_with_errors_are_left_out() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "words.json",
+            r#"[
+                {"fields": ["Hund", "dog"], "guid": "h1"},
+                {"fields": ["Hund", "hound"], "guid": "h1"},
+                {"fields": ["Katze", "cat"]}
+            ]"#,
+        );
+        let project =
+            Project::from_note_file(dir.path().join("words.json"), "Words", "basic").unwrap();
+        let (package, report) = project.build().unwrap();
+        let errors = report.errors().collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location, "h1");
+        let notes = package.decks()[0].notes();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].fields(), ["Hund", "dog"]);
+    }
+
+    #[test]
+    fn json_manifest_and_unknown_model() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            JSON_MANIFEST,
+            r#"{"decks": [{"name": "D", "model": "nope", "notes": ["n.csv"]}]}"#,
+        );
+        write(dir.path(), "n.csv", "Front,Back\na,b\n");
+        assert!(Project::load(dir.path()).unwrap().build().is_err());
+    }
+
+    #[test]
+    fn single_note_file_uses_special_columns() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "words.tsv",
+            "Front\tBack\tTags\tGuid\tDeck\nHund\tdog\tanimal\th1\tWords::Animals\n",
+        );
+        let project =
+            Project::from_note_file(dir.path().join("words.tsv"), "Words", "basic").unwrap();
+        let mut report = ValidationReport::default();
+        let decks = project.decks(&HashMap::new(), &mut report).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(decks[1].name(), "Words::Animals");
+        let note = &decks[1].notes()[0];
+        assert_eq!(note.fields(), ["Hund", "dog"]);
+        assert_eq!(note.get_tags(), ["animal"]);
+        assert_eq!(note.get_guid(), "h1");
+    }
+
+    #[test]
+    fn missing_manifest_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        assert!(Project::load(dir.path()).is_err());
+    }
+}
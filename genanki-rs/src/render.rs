@@ -0,0 +1,394 @@
+use crate::Error;
+use crate::model::ModelType;
+use crate::note::Note;
+use anyhow::{Result, anyhow};
+use fancy_regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+static FURIGANA: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r" ?([^ >]+?)\[(.+?)\]").expect("static regex"));
+static CLOZE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)\{\{c(\d+)::(.*?)(?:::(.*?))?\}\}").expect("static regex"));
+static EMPTY_FIELD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(?:\s|</?(?:br|div) ?/?>)*$").expect("static regex"));
+static HTML: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<!--.*?-->|<[^>]*>").expect("static regex"));
+
+/// Question and answer of a card as rendered by Anki, before the scripts of the template run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CardPreview {
+    pub question: String,
+    pub answer: String,
+}
+
+impl Note {
+    /// Renders the card with ordinal `ord` of the note, the template index for normal models and
+    /// the cloze number minus one for cloze models
+    ///
+    /// Conditional sections, the `FrontSide`, `Tags`, `Type` and `Card` special fields and the
+    /// `text`, `furigana`, `kana`, `kanji`, `cloze`, `cloze-only`, `type` and `tts` filters are
+    /// supported. Sound is not played: `tts` filters are rendered as the `[anki:tts]` tags Anki
+    /// passes to its player, and `type` filters as `[[type:Field]]`.
+    ///
+    /// Returns `Err` if the model has no template for `ord`, a template refers to an unknown field
+    /// or a conditional section is not closed
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{Field, Model, Note, Template};
+    /// use anyhow::Result;
+    ///
+    /// fn main() -> Result<()> {
+    ///     let model = Model::new(
+    ///         1607392320,
+    ///         "Japanese",
+    ///         vec![Field::new("Expression"), Field::new("Meaning")],
+    ///         vec![Template::new("Card 1")
+    ///             .qfmt("{{kanji:Expression}}")
+    ///             .afmt("{{furigana:Expression}}<hr id=answer>{{Meaning}}")],
+    ///     );
+    ///     let note = Note::new(model, vec!["日本[にほん]", "Japan"])?;
+    ///     let preview = note.preview(0)?;
+    ///     assert_eq!(preview.question, "日本");
+    ///     assert_eq!(
+    ///         preview.answer,
+    ///         "<ruby><rb>日本</rb><rt>にほん</rt></ruby><hr id=answer>Japan"
+    ///     );
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn preview(&self, ord: i64) -> Result<CardPreview> {
+        let model = self.model();
+        let templates = model.templates();
+        let template = match model.get_model_type() {
+            ModelType::FrontBack => usize::try_from(ord).ok().and_then(|i| templates.get(i)),
+            ModelType::Cloze if ord >= 0 => templates.first(),
+            ModelType::Cloze => None,
+        }
+        .ok_or_else(|| anyhow!(Error::NoSuchCard(ord)))?;
+
+        let field_names = model.fields();
+        let tags = self.get_tags().join(" ");
+        let mut fields = field_names
+            .iter()
+            .map(|field| field.name.as_str())
+            .zip(self.fields().iter().map(String::as_str))
+            .collect::<HashMap<_, _>>();
+        fields.insert("Tags", &tags);
+        fields.insert("Type", model.name());
+        fields.insert("Card", &template.name);
+
+        let mut context = Context {
+            model: model.name(),
+            fields,
+            cloze: ord + 1,
+            answer: false,
+        };
+        let question = context.render(&template.qfmt)?;
+        context.fields.insert("FrontSide", &question);
+        context.answer = true;
+        let answer = context.render(&template.afmt)?;
+        Ok(CardPreview { question, answer })
+    }
+}
+
+enum Node<'a> {
+    Text(&'a str),
+    Replace {
+        filters: Vec<&'a str>,
+        field: &'a str,
+    },
+    Section {
+        field: &'a str,
+        inverted: bool,
+        children: Vec<Node<'a>>,
+    },
+}
+
+/// Nodes collected inside an open section, given by its field and whether it is inverted, or on
+/// the top level
+type Scope<'a> = (Option<(&'a str, bool)>, Vec<Node<'a>>);
+
+fn parse(template: &str) -> Result<Vec<Node<'_>>> {
+    // The bottom entry collects the top level nodes, every open section adds another
+    let mut stack: Vec<Scope> = vec![(None, vec![])];
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let nodes = &mut stack.last_mut().expect("root entry").1;
+        if start > 0 {
+            nodes.push(Node::Text(&rest[..start]));
+        }
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| anyhow!("unclosed {{{{ in template {:?}", template))?
+            + start;
+        let tag = rest[start + 2..end].trim();
+        rest = &rest[end + 2..];
+        if let Some(field) = tag.strip_prefix('#') {
+            stack.push((Some((field.trim(), false)), vec![]));
+        } else if let Some(field) = tag.strip_prefix('^') {
+            stack.push((Some((field.trim(), true)), vec![]));
+        } else if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            match stack.pop() {
+                Some((Some((field, inverted)), children)) if field == name => {
+                    stack.last_mut().expect("root entry").1.push(Node::Section {
+                        field,
+                        inverted,
+                        children,
+                    });
+                }
+                _ => return Err(anyhow!("unexpected {{{{/{}}}}} in template", name)),
+            }
+        } else if !tag.starts_with('!') {
+            let mut filters = tag.split(':').collect::<Vec<_>>();
+            let field = filters.pop().unwrap_or_default().trim();
+            nodes.push(Node::Replace { filters, field });
+        }
+    }
+    if !rest.is_empty() {
+        stack
+            .last_mut()
+            .expect("root entry")
+            .1
+            .push(Node::Text(rest));
+    }
+    match stack.pop() {
+        Some((None, nodes)) => Ok(nodes),
+        Some((Some((field, _)), _)) => Err(anyhow!("section {:?} is never closed", field)),
+        None => unreachable!("the root entry is never popped"),
+    }
+}
+
+struct Context<'a> {
+    model: &'a str,
+    fields: HashMap<&'a str, &'a str>,
+    /// Number of the active cloze deletion, `{{c1::...}}` is 1
+    cloze: i64,
+    answer: bool,
+}
+
+impl Context<'_> {
+    fn render(&self, template: &str) -> Result<String> {
+        let mut output = String::new();
+        self.render_nodes(&parse(template)?, &mut output)?;
+        Ok(output)
+    }
+
+    fn render_nodes(&self, nodes: &[Node], output: &mut String) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => output.push_str(text),
+                Node::Replace { filters, field } => {
+                    let value = self.field(field, filters.is_empty())?;
+                    let value = filters
+                        .iter()
+                        .rev()
+                        .fold(value.to_string(), |value, filter| {
+                            self.apply_filter(filter.trim(), field, value)
+                        });
+                    output.push_str(&value);
+                }
+                Node::Section {
+                    field,
+                    inverted,
+                    children,
+                } => {
+                    let empty = EMPTY_FIELD
+                        .is_match(self.field(field, false)?)
+                        .unwrap_or(false);
+                    if empty == *inverted {
+                        self.render_nodes(children, output)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value of `name`. The `FrontSide` of the question is empty, as in Anki.
+    fn field(&self, name: &str, plain: bool) -> Result<&str> {
+        match self.fields.get(name) {
+            Some(value) => Ok(value),
+            None if name == "FrontSide" && plain => Ok(""),
+            None => Err(anyhow!(Error::UnknownField {
+                model: self.model.to_string(),
+                field: name.to_string(),
+            })),
+        }
+    }
+
+    fn apply_filter(&self, filter: &str, field: &str, value: String) -> String {
+        match filter {
+            "text" => strip_html(&value),
+            "furigana" => replace_furigana(&value, |base, reading| {
+                format!("<ruby><rb>{}</rb><rt>{}</rt></ruby>", base, reading)
+            }),
+            "kana" => replace_furigana(&value, |_, reading| reading.to_string()),
+            "kanji" => replace_furigana(&value, |base, _| base.to_string()),
+            "cloze" => self.render_cloze(&value),
+            "cloze-only" => self.active_clozes(&value).join(", "),
+            "type" => format!("[[type:{}]]", field),
+            _ => match filter.strip_prefix("tts ") {
+                Some(options) => {
+                    let mut options = options.split_whitespace();
+                    let lang = options.next().unwrap_or_default();
+                    let mut tag = format!("[anki:tts lang={}", lang);
+                    for option in options {
+                        tag.push(' ');
+                        tag.push_str(option);
+                    }
+                    format!("{}]{}[/anki:tts]", tag, value)
+                }
+                // Filters added by add-ons or unknown to this renderer leave the field unchanged
+                None => value,
+            },
+        }
+    }
+
+    fn render_cloze(&self, value: &str) -> String {
+        CLOZE
+            .replace_all(value, |caps: &Captures| {
+                let text = &caps[2];
+                if caps[1].parse::<i64>() != Ok(self.cloze) {
+                    text.to_string()
+                } else if self.answer {
+                    format!(r#"<span class="cloze">{}</span>"#, text)
+                } else {
+                    let hint = caps.get(3).map_or("...", |hint| hint.as_str());
+                    format!(r#"<span class="cloze">[{}]</span>"#, hint)
+                }
+            })
+            .into_owned()
+    }
+
+    fn active_clozes(&self, value: &str) -> Vec<String> {
+        CLOZE
+            .captures_iter(value)
+            .filter_map(|caps| caps.ok())
+            .filter(|caps| caps[1].parse::<i64>() == Ok(self.cloze))
+            .map(|caps| caps[2].to_string())
+            .collect()
+    }
+}
+
+/// Replaces readings written as `漢字[かんじ]` using `replace(base, reading)`
+fn replace_furigana(value: &str, replace: impl Fn(&str, &str) -> String) -> String {
+    let value = value.replace("&nbsp;", " ");
+    FURIGANA
+        .replace_all(&value, |caps: &Captures| {
+            if caps[2].starts_with("sound:") {
+                caps[0].to_string()
+            } else {
+                replace(&caps[1], &caps[2])
+            }
+        })
+        .into_owned()
+}
+
+fn strip_html(value: &str) -> String {
+    HTML.replace_all(value, "").replace("&nbsp;", " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Field, Model, Template, basic_model, cloze_model};
+
+    fn japanese_model(qfmt: &str, afmt: &str) -> Model {
+        Model::new(
+            1607392320,
+            "Japanese",
+            vec![Field::new("Expression"), Field::new("Notes")],
+            vec![Template::new("Recognition").qfmt(qfmt).afmt(afmt)],
+        )
+    }
+
+    #[test]
+    fn furigana_filters() {
+        let model = japanese_model(
+            "{{furigana:Expression}}|{{kana:Expression}}|{{kanji:Expression}}",
+            "{{FrontSide}}",
+        );
+        let note = Note::new(model, vec!["今日[きょう]は 雨[あめ] [sound:ame.mp3]", ""]).unwrap();
+        assert_eq!(
+            note.preview(0).unwrap().question,
+            "<ruby><rb>今日</rb><rt>きょう</rt></ruby>は<ruby><rb>雨</rb><rt>あめ</rt></ruby> \
+             [sound:ame.mp3]|きょうはあめ [sound:ame.mp3]|今日は雨 [sound:ame.mp3]"
+        );
+    }
+
+    #[test]
+    fn sections_and_special_fields() {
+        let model = japanese_model(
+            "{{Expression}}{{#Notes}} ({{text:Notes}}){{/Notes}}{{^Notes}} -{{/Notes}}",
+            "{{FrontSide}}<hr id=answer>{{Card}}: {{Tags}}",
+        );
+        let note = Note::new(model.clone(), vec!["雨", "<b>rain</b>"])
+            .unwrap()
+            .tags(["N5", "weather"]);
+        assert_eq!(
+            note.preview(0).unwrap(),
+            CardPreview {
+                question: "雨 (rain)".to_string(),
+                answer: "雨 (rain)<hr id=answer>Recognition: N5 weather".to_string(),
+            }
+        );
+        let note = Note::new(model, vec!["雨", "<br>"]).unwrap();
+        assert_eq!(note.preview(0).unwrap().question, "雨 -");
+    }
+
+    #[test]
+    fn tts_and_type_filters() {
+        let model = japanese_model(
+            "{{tts ja_JP voices=Apple_Kyoko speed=0.8:kana:Expression}}",
+            "{{type:Notes}}",
+        );
+        let note = Note::new(model, vec!["雨[あめ]", ""]).unwrap();
+        assert_eq!(
+            note.preview(0).unwrap(),
+            CardPreview {
+                question: "[anki:tts lang=ja_JP voices=Apple_Kyoko speed=0.8]あめ[/anki:tts]"
+                    .to_string(),
+                answer: "[[type:Notes]]".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn cloze_deletions() {
+        let note = Note::new(
+            cloze_model(),
+            vec!["{{c1::Berlin::city}} is the capital of {{c2::Germany}}", ""],
+        )
+        .unwrap();
+        let preview = note.preview(1).unwrap();
+        assert_eq!(
+            preview.question,
+            r#"Berlin is the capital of <span class="cloze">[...]</span>"#
+        );
+        assert!(
+            preview
+                .answer
+                .starts_with(r#"Berlin is the capital of <span class="cloze">Germany</span>"#)
+        );
+        assert!(
+            note.preview(0)
+                .unwrap()
+                .question
+                .starts_with(r#"<span class="cloze">[city]</span>"#)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let note = Note::new(basic_model(), vec!["a", "b"]).unwrap();
+        assert!(note.preview(1).is_err());
+        let note = Note::new(japanese_model("{{Reading}}", ""), vec!["a", "b"]).unwrap();
+        assert!(note.preview(0).is_err());
+        let note = Note::new(japanese_model("{{#Notes}}", ""), vec!["a", "b"]).unwrap();
+        assert!(note.preview(0).is_err());
+    }
+}
@@ -0,0 +1,638 @@
+use crate::Error;
+use crate::model::Model;
+use anyhow::{Result, anyhow};
+use std::collections::HashSet;
+
+const DEFAULT_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "div",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "rp",
+    "rt",
+    "ruby",
+    "s",
+    "span",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+
+const DEFAULT_ATTRIBUTES: &[&str] = &["alt", "class", "colspan", "href", "rowspan", "src", "title"];
+
+/// Elements which never have content or a closing tag
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements which are removed together with their content instead of being unwrapped
+const DROPPED_TAGS: &[&str] = &[
+    "iframe", "noscript", "object", "script", "style", "template", "title",
+];
+
+/// Attributes which contain URLs and are checked for script schemes
+const URL_ATTRIBUTES: &[&str] = &["href", "src"];
+
+/// Cleans up the HTML of note fields, e.g. content scraped from the web.
+///
+/// Tags which are not allowed are removed while their content is kept, except for elements like
+/// `<script>` and `<style>` which are removed completely. Comments, attributes which are not
+/// allowed, `javascript:` URLs and classes which are not allowed are removed as well. Tag and
+/// attribute names are lowercased.
+///
+/// With entity normalization, numeric character references are replaced by the character, and
+/// stray `&`, `<` and `>` in the text are escaped. With tag balancing, closing tags without a
+/// matching opening tag are removed and tags left open are closed.
+///
+/// The sanitizer can be applied to single fields with [`HtmlSanitizer::sanitize_fields`] or to
+/// all fields of a package with [`Package::sanitizer`](crate::Package::sanitizer).
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::HtmlSanitizer;
+///
+/// let sanitizer = HtmlSanitizer::new().allowed_classes(["gender"]);
+/// assert_eq!(
+///     sanitizer.sanitize(r#"<B>der</B> <SPAN class="gender m" style="color: blue">Hund<script>x()</script>"#),
+///     r#"<b>der</b> <span class="gender">Hund</span>"#
+/// );
+/// ```
+///
+/// The sanitizer has the following default values:
+/// * `allowed_tags` - basic formatting, lists, tables, links, images and ruby annotations
+/// * `allowed_attributes` - `alt`, `class`, `colspan`, `href`, `rowspan`, `src` and `title`
+/// * `allowed_classes` - all classes
+/// * `normalize_entities` - `true`
+/// * `balance_tags` - `true`
+#[derive(Clone, Debug)]
+pub struct HtmlSanitizer {
+    tags: HashSet<String>,
+    attributes: HashSet<String>,
+    classes: Option<HashSet<String>>,
+    normalize_entities: bool,
+    balance_tags: bool,
+}
+
+impl Default for HtmlSanitizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HtmlSanitizer {
+    /// Creates a new sanitizer with the default options
+    pub fn new() -> Self {
+        Self {
+            tags: DEFAULT_TAGS.iter().map(|tag| tag.to_string()).collect(),
+            attributes: DEFAULT_ATTRIBUTES
+                .iter()
+                .map(|attr| attr.to_string())
+                .collect(),
+            classes: None,
+            normalize_entities: true,
+            balance_tags: true,
+        }
+    }
+
+    /// Sets the tags which are kept, replacing the default ones
+    pub fn allowed_tags(self, tags: impl IntoIterator<Item = impl ToString>) -> Self {
+        Self {
+            tags: lowercase_set(tags),
+            ..self
+        }
+    }
+
+    /// Sets the attributes which are kept on allowed tags, replacing the default ones
+    pub fn allowed_attributes(self, attributes: impl IntoIterator<Item = impl ToString>) -> Self {
+        Self {
+            attributes: lowercase_set(attributes),
+            ..self
+        }
+    }
+
+    /// Sets the CSS classes which are kept in `class` attributes, by default all are kept
+    pub fn allowed_classes(self, classes: impl IntoIterator<Item = impl ToString>) -> Self {
+        Self {
+            classes: Some(classes.into_iter().map(|class| class.to_string()).collect()),
+            ..self
+        }
+    }
+
+    /// Sets whether character references and stray special characters are normalized
+    pub fn normalize_entities(self, normalize_entities: bool) -> Self {
+        Self {
+            normalize_entities,
+            ..self
+        }
+    }
+
+    /// Sets whether unmatched closing tags are removed and open tags are closed
+    pub fn balance_tags(self, balance_tags: bool) -> Self {
+        Self {
+            balance_tags,
+            ..self
+        }
+    }
+
+    /// Sanitizes `html`
+    pub fn sanitize(&self, html: &str) -> String {
+        let mut output = String::with_capacity(html.len());
+        let mut open_tags: Vec<String> = vec![];
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            self.push_text(&mut output, &rest[..start]);
+            rest = &rest[start..];
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+                continue;
+            }
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+                continue;
+            }
+            let Some((tag, after)) = Tag::parse(rest) else {
+                self.push_text(&mut output, "<");
+                rest = &rest[1..];
+                continue;
+            };
+            rest = after;
+
+            if DROPPED_TAGS.contains(&tag.name.as_str()) {
+                if !tag.closing {
+                    rest = skip_element(rest, &tag.name);
+                }
+                continue;
+            }
+            if !self.tags.contains(&tag.name) {
+                continue;
+            }
+            let is_void = VOID_TAGS.contains(&tag.name.as_str());
+            if tag.closing {
+                if is_void {
+                    continue;
+                }
+                if !self.balance_tags {
+                    output.push_str(&format!("</{}>", tag.name));
+                } else if let Some(position) = open_tags.iter().rposition(|open| *open == tag.name)
+                {
+                    for open in open_tags.drain(position..).rev() {
+                        output.push_str(&format!("</{}>", open));
+                    }
+                }
+                continue;
+            }
+
+            output.push('<');
+            output.push_str(&tag.name);
+            for (name, value) in &tag.attributes {
+                self.push_attribute(&mut output, name, value.as_deref());
+            }
+            output.push('>');
+            if !is_void && !tag.self_closing {
+                open_tags.push(tag.name);
+            } else if !is_void {
+                output.push_str(&format!("</{}>", tag.name));
+            }
+        }
+        self.push_text(&mut output, rest);
+        if self.balance_tags {
+            for open in open_tags.into_iter().rev() {
+                output.push_str(&format!("</{}>", open));
+            }
+        }
+        output
+    }
+
+    /// Sanitizes the values of the fields named in `html_fields` and leaves all others as they
+    /// are, so the result can be passed to `Note::new`.
+    ///
+    /// `fields` are given in the order of the fields of `model`.
+    ///
+    /// Returns `Err` if `model` has no field with one of the names in `html_fields`
+    pub fn sanitize_fields(
+        &self,
+        model: &Model,
+        fields: Vec<&str>,
+        html_fields: &[&str],
+    ) -> Result<Vec<String>> {
+        let model_fields = model.fields();
+        for &name in html_fields {
+            if !model_fields.iter().any(|field| field.name == name) {
+                return Err(anyhow!(Error::UnknownField {
+                    model: model.name().to_string(),
+                    field: name.to_string(),
+                }));
+            }
+        }
+        Ok(fields
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| match model_fields.get(i) {
+                Some(field) if html_fields.contains(&field.name.as_str()) => self.sanitize(value),
+                _ => value.to_string(),
+            })
+            .collect())
+    }
+
+    fn push_text(&self, output: &mut String, text: &str) {
+        if !self.normalize_entities {
+            output.push_str(text);
+            return;
+        }
+        let mut rest = text;
+        while let Some(position) = rest.find(['&', '<', '>']) {
+            output.push_str(&rest[..position]);
+            rest = &rest[position..];
+            match rest.as_bytes()[0] {
+                b'<' => output.push_str("&lt;"),
+                b'>' => output.push_str("&gt;"),
+                _ => match parse_entity(rest) {
+                    Some((entity, len)) => {
+                        push_entity(output, entity);
+                        rest = &rest[len..];
+                        continue;
+                    }
+                    None => output.push_str("&amp;"),
+                },
+            }
+            rest = &rest[1..];
+        }
+        output.push_str(rest);
+    }
+
+    fn push_attribute(&self, output: &mut String, name: &str, value: Option<&str>) {
+        if !self.attributes.contains(name) {
+            return;
+        }
+        let Some(value) = value else {
+            output.push(' ');
+            output.push_str(name);
+            return;
+        };
+        let mut value = decode_entities(value);
+        if URL_ATTRIBUTES.contains(&name) && is_script_url(&value) {
+            return;
+        }
+        if name == "class" {
+            value = value
+                .split_whitespace()
+                .filter(|class| self.classes.as_ref().is_none_or(|c| c.contains(*class)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            if value.is_empty() {
+                return;
+            }
+        }
+        let value = value.replace('&', "&amp;").replace('"', "&quot;");
+        output.push_str(&format!(" {}=\"{}\"", name, value));
+    }
+}
+
+/// A start or end tag
+struct Tag {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl Tag {
+    /// Parses the tag at the start of `input`, returning it and the input following it
+    ///
+    /// Returns `None` if `input` does not start with a tag, e.g. `a < b` or an unterminated tag.
+    fn parse(input: &str) -> Option<(Self, &str)> {
+        let mut rest = input.strip_prefix('<')?;
+        let closing = rest.starts_with('/');
+        if closing {
+            rest = &rest[1..];
+        }
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let name_end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = &rest[name_end..];
+
+        let mut attributes = vec![];
+        let mut self_closing = false;
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix('>') {
+                let tag = Self {
+                    name,
+                    closing,
+                    self_closing,
+                    attributes,
+                };
+                return Some((tag, after));
+            }
+            if let Some(after) = rest.strip_prefix('/') {
+                self_closing = true;
+                rest = after;
+                continue;
+            }
+            let name_end = rest.find(|c: char| c.is_whitespace() || "/>=".contains(c))?;
+            if name_end == 0 {
+                // A `=` without an attribute name
+                rest = &rest[1..];
+                continue;
+            }
+            let attribute = rest[..name_end].to_ascii_lowercase();
+            rest = rest[name_end..].trim_start();
+            self_closing = false;
+            let value = match rest.strip_prefix('=') {
+                Some(after) => {
+                    let after = after.trim_start();
+                    let (value, after) = match after.chars().next()? {
+                        quote @ ('"' | '\'') => {
+                            let end = after[1..].find(quote)?;
+                            (&after[1..end + 1], &after[end + 2..])
+                        }
+                        _ => {
+                            let end = after
+                                .find(|c: char| c.is_whitespace() || c == '>')
+                                .unwrap_or(after.len());
+                            (&after[..end], &after[end..])
+                        }
+                    };
+                    rest = after;
+                    Some(value.to_string())
+                }
+                None => None,
+            };
+            attributes.push((attribute, value));
+        }
+    }
+}
+
+/// Skips the content of a `name` element up to and including its closing tag
+fn skip_element<'a>(input: &'a str, name: &str) -> &'a str {
+    let lowercase = input.to_ascii_lowercase();
+    let closing = format!("</{}", name);
+    let Some(start) = lowercase.find(&closing) else {
+        return "";
+    };
+    let rest = &input[start..];
+    rest.find('>').map_or("", |end| &rest[end + 1..])
+}
+
+enum Entity<'a> {
+    Char(char),
+    Named(&'a str),
+}
+
+/// Parses the character reference at the start of `input`, returning it and its length
+fn parse_entity(input: &str) -> Option<(Entity<'_>, usize)> {
+    let end = input.find(';')?;
+    let body = &input[1..end];
+    if let Some(number) = body.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return Some((Entity::Char(char::from_u32(code)?), end + 1));
+    }
+    if body.is_empty() || !body.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let entity = match body {
+        "amp" => Entity::Char('&'),
+        "lt" => Entity::Char('<'),
+        "gt" => Entity::Char('>'),
+        "quot" => Entity::Char('"'),
+        "apos" => Entity::Char('\''),
+        "nbsp" => Entity::Char('\u{a0}'),
+        _ => Entity::Named(body),
+    };
+    Some((entity, end + 1))
+}
+
+fn push_entity(output: &mut String, entity: Entity) {
+    match entity {
+        Entity::Char('&') => output.push_str("&amp;"),
+        Entity::Char('<') => output.push_str("&lt;"),
+        Entity::Char('>') => output.push_str("&gt;"),
+        Entity::Char('\u{a0}') => output.push_str("&nbsp;"),
+        Entity::Char(c) => output.push(c),
+        Entity::Named(name) => output.push_str(&format!("&{};", name)),
+    }
+}
+
+/// Replaces the character references in an attribute value, keeping unknown named ones
+pub(super) fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(position) = rest.find('&') {
+        decoded.push_str(&rest[..position]);
+        rest = &rest[position..];
+        match parse_entity(rest) {
+            Some((Entity::Char(c), len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn is_script_url(url: &str) -> bool {
+    let url = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    url.starts_with("javascript:") || url.starts_with("vbscript:")
+}
+
+fn lowercase_set(values: impl IntoIterator<Item = impl ToString>) -> HashSet<String> {
+    values
+        .into_iter()
+        .map(|value| value.to_string().to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Deck, Note, Package, basic_model, cloze_model};
+    use sqlx::{Pool, Sqlite};
+
+    #[test]
+    fn scripts_styles_and_comments_are_removed() {
+        let sanitizer = HtmlSanitizer::new();
+        assert_eq!(
+            sanitizer.sanitize(
+                "<style>p { color: red }</style>Hund<!-- note --><SCRIPT type=x>alert('<b>')</script>"
+            ),
+            "Hund"
+        );
+        assert_eq!(
+            sanitizer.sanitize(r#"<p style="color: red" onclick="x()">a</p>"#),
+            "<p>a</p>"
+        );
+        assert_eq!(
+            sanitizer.sanitize(r#"<a href=" JavaScript:alert(1)">link</a>"#),
+            "<a>link</a>"
+        );
+    }
+
+    #[test]
+    fn unknown_tags_are_unwrapped() {
+        assert_eq!(
+            HtmlSanitizer::new().sanitize("<article><FONT color=red>rot</FONT></article>"),
+            "rot"
+        );
+        assert_eq!(
+            HtmlSanitizer::new()
+                .allowed_tags(["font"])
+                .allowed_attributes(["color"])
+                .sanitize("<FONT COLOR=red><b>rot</b></FONT>"),
+            r#"<font color="red">rot</font>"#
+        );
+    }
+
+    #[test]
+    fn classes_are_filtered() {
+        let sanitizer = HtmlSanitizer::new().allowed_classes(["noun"]);
+        assert_eq!(
+            sanitizer.sanitize(r#"<span class="noun highlight">Hund</span>"#),
+            r#"<span class="noun">Hund</span>"#
+        );
+        assert_eq!(
+            sanitizer.sanitize(r#"<span class="highlight">Hund</span>"#),
+            "<span>Hund</span>"
+        );
+    }
+
+    #[test]
+    fn tags_are_balanced() {
+        let sanitizer = HtmlSanitizer::new();
+        assert_eq!(
+            sanitizer.sanitize("<b>fett <i>kursiv"),
+            "<b>fett <i>kursiv</i></b>"
+        );
+        assert_eq!(sanitizer.sanitize("</b>a</i>"), "a");
+        assert_eq!(
+            sanitizer.sanitize("<div><b>fett</div>"),
+            "<div><b>fett</b></div>"
+        );
+        assert_eq!(sanitizer.sanitize("a<br/>b<br></br>"), "a<br>b<br>");
+        assert_eq!(
+            sanitizer.balance_tags(false).sanitize("<b>a</i>"),
+            "<b>a</i>"
+        );
+    }
+
+    #[test]
+    fn entities_are_normalized() {
+        let sanitizer = HtmlSanitizer::new();
+        assert_eq!(
+            sanitizer.sanitize("Fish & Chips &#228; &#xE4; &auml; &nbsp;&lt;b&gt; a < b > c"),
+            "Fish &amp; Chips ä ä &auml; &nbsp;&lt;b&gt; a &lt; b &gt; c"
+        );
+        assert_eq!(
+            sanitizer.sanitize(r#"<img src="a&amp;b.jpg" alt='"x"'>"#),
+            r#"<img src="a&amp;b.jpg" alt="&quot;x&quot;">"#
+        );
+        assert_eq!(
+            sanitizer.normalize_entities(false).sanitize("a & b"),
+            "a & b"
+        );
+    }
+
+    #[test]
+    fn anki_syntax_is_kept() {
+        assert_eq!(
+            HtmlSanitizer::new().sanitize("{{c1::Hund::Tier}} [sound:hund.mp3] \\(x^2\\)"),
+            "{{c1::Hund::Tier}} [sound:hund.mp3] \\(x^2\\)"
+        );
+    }
+
+    #[test]
+    fn only_named_fields_are_sanitized() {
+        let fields = HtmlSanitizer::new()
+            .sanitize_fields(&basic_model(), vec!["<b>a", "<b>b"], &["Back"])
+            .unwrap();
+        assert_eq!(fields, ["<b>a", "<b>b</b>"]);
+        assert!(
+            HtmlSanitizer::new()
+                .sanitize_fields(&basic_model(), vec!["a", "b"], &["Extra"])
+                .is_err()
+        );
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn package_sanitizes_all_fields(pool: Pool<Sqlite>) {
+        let mut deck = Deck::new(2059400110, "Vocabulary", "");
+        deck.add_note(
+            Note::new(basic_model(), vec!["<B>Hund", "dog<script>x()</script>"]).unwrap(),
+        );
+        let mut package = Package::new(vec![deck], vec![])
+            .unwrap()
+            .sanitizer(HtmlSanitizer::new());
+        let mut conn = pool.acquire().await.unwrap();
+        package
+            .write_maybe_timestamp(Some(1425279151.0), &mut conn)
+            .await
+            .unwrap();
+        let fields = sqlx::query_scalar!("SELECT flds FROM notes")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(fields, "<b>Hund</b>\x1fdog");
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn cards_of_removed_content_are_not_written(pool: Pool<Sqlite>) {
+        let text = "{{c1::Berlin}}<script>'{{c2::x}}'</script>";
+        let mut deck = Deck::new(2059400110, "Capitals", "");
+        deck.add_note(Note::new(cloze_model(), vec![text]).unwrap());
+        let mut package = Package::new(vec![deck], vec![])
+            .unwrap()
+            .sanitizer(HtmlSanitizer::new());
+        let mut conn = pool.acquire().await.unwrap();
+        package
+            .write_maybe_timestamp(Some(1425279151.0), &mut conn)
+            .await
+            .unwrap();
+        let ords = sqlx::query_scalar!("SELECT ord FROM cards")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ords, [0]);
+        assert_eq!(package.decks()[0].notes()[0].fields()[0], text);
+    }
+}
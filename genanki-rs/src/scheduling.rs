@@ -0,0 +1,371 @@
+use crate::Error;
+use anyhow::{Result, anyhow};
+
+/// Scheduling state of a card, e.g. carried over from another spaced repetition program.
+///
+/// Due dates are Unix timestamps in seconds. Review cards are due on the day containing the
+/// timestamp, counted from the creation time of the collection like Anki does.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Scheduling {
+    /// Not studied yet, shown in the order of `CardState::position`
+    #[default]
+    New,
+    /// In the initial learning steps
+    Learning {
+        due: i64,
+        /// Learning steps left until the card graduates to review
+        remaining_steps: i64,
+    },
+    /// Graduated card in the review queue
+    Review {
+        due: i64,
+        /// Current interval in days
+        interval: i64,
+        /// Ease factor, e.g. `2.5` for 250%
+        ease: f64,
+    },
+    /// Lapsed review card going through the relearning steps
+    Relearning {
+        due: i64,
+        /// Interval in days the card returns to after relearning
+        interval: i64,
+        /// Ease factor, e.g. `2.5` for 250%
+        ease: f64,
+        /// Relearning steps left until the card returns to review
+        remaining_steps: i64,
+    },
+}
+
+impl Scheduling {
+    /// Returns the `type` column of the card
+    pub(super) fn card_type(&self) -> i64 {
+        match self {
+            Scheduling::New => 0,
+            Scheduling::Learning { .. } => 1,
+            Scheduling::Review { .. } => 2,
+            Scheduling::Relearning { .. } => 3,
+        }
+    }
+
+    /// Returns the `queue` column of a card which is neither suspended nor buried
+    pub(super) fn queue(&self) -> i64 {
+        match self {
+            Scheduling::New => 0,
+            Scheduling::Learning { .. } | Scheduling::Relearning { .. } => 1,
+            Scheduling::Review { .. } => 2,
+        }
+    }
+
+    /// Returns the `due` column, `collection_created` is the `crt` of the collection
+    pub(super) fn due(&self, position: i64, collection_created: i64) -> i64 {
+        match self {
+            Scheduling::New => position,
+            Scheduling::Learning { due, .. } | Scheduling::Relearning { due, .. } => *due,
+            Scheduling::Review { due, .. } => (due - collection_created).div_euclid(86400),
+        }
+    }
+
+    /// Returns the `ivl` column
+    pub(super) fn interval(&self) -> i64 {
+        match self {
+            Scheduling::New | Scheduling::Learning { .. } => 0,
+            Scheduling::Review { interval, .. } | Scheduling::Relearning { interval, .. } => {
+                *interval
+            }
+        }
+    }
+
+    /// Returns the `factor` column, the ease in permille
+    pub(super) fn factor(&self) -> i64 {
+        match self {
+            Scheduling::New | Scheduling::Learning { .. } => 0,
+            Scheduling::Review { ease, .. } | Scheduling::Relearning { ease, .. } => {
+                (ease * 1000.0).round() as i64
+            }
+        }
+    }
+
+    /// Returns the `left` column: steps left today in the thousands, steps left in total below
+    pub(super) fn left(&self) -> i64 {
+        match self {
+            Scheduling::Learning {
+                remaining_steps, ..
+            }
+            | Scheduling::Relearning {
+                remaining_steps, ..
+            } => remaining_steps * 1000 + remaining_steps,
+            Scheduling::New | Scheduling::Review { .. } => 0,
+        }
+    }
+}
+
+/// Answer button pressed in a [`Review`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rating {
+    Again = 1,
+    Hard = 2,
+    Good = 3,
+    Easy = 4,
+}
+
+/// Kind of a [`Review`], depending on the state the card was in when it was answered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReviewKind {
+    Learning = 0,
+    Review = 1,
+    Relearning = 2,
+    Filtered = 3,
+    Manual = 4,
+}
+
+/// Entry of the review history of a card, written to the `revlog` table.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, CardState, Note, Rating, Review, ReviewKind, Scheduling};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let state = CardState::new()
+///         .scheduling(Scheduling::Review {
+///             due: 1_767_225_600,
+///             interval: 12,
+///             ease: 2.5,
+///         })
+///         .reps(3)
+///         .review(Review::new(1_765_000_000_000, Rating::Good).kind(ReviewKind::Learning))
+///         .review(Review::new(1_765_100_000_000, Rating::Good).interval(4))
+///         .review(
+///             Review::new(1_766_200_000_000, Rating::Good)
+///                 .interval(12)
+///                 .last_interval(4),
+///         );
+///     let note = Note::new(basic_model(), vec!["Hund", "dog"])?.card_state(0, state)?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The builder has the following default values:
+/// * `interval` - `0`
+/// * `last_interval` - `0`
+/// * `ease` - `2.5`
+/// * `duration` - `0`
+/// * `kind` - `ReviewKind::Review`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Review {
+    time: i64,
+    rating: Rating,
+    interval: i64,
+    last_interval: i64,
+    ease: f64,
+    duration: i64,
+    kind: ReviewKind,
+}
+
+impl Review {
+    /// Creates a review answered with `rating` at `time`, a Unix timestamp in milliseconds
+    ///
+    /// Anki uses the time as id of the review, so it has to be unique within a collection.
+    pub fn new(time: i64, rating: Rating) -> Self {
+        Self {
+            time,
+            rating,
+            interval: 0,
+            last_interval: 0,
+            ease: 2.5,
+            duration: 0,
+            kind: ReviewKind::Review,
+        }
+    }
+
+    /// Sets the interval after the review, in days if positive and in seconds if negative
+    pub fn interval(self, interval: i64) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// Sets the interval before the review, in days if positive and in seconds if negative
+    pub fn last_interval(self, last_interval: i64) -> Self {
+        Self {
+            last_interval,
+            ..self
+        }
+    }
+
+    /// Sets the ease factor after the review, e.g. `2.5` for 250%
+    pub fn ease(self, ease: f64) -> Self {
+        Self { ease, ..self }
+    }
+
+    /// Sets how long answering took, in milliseconds
+    pub fn duration(self, duration: i64) -> Self {
+        Self { duration, ..self }
+    }
+
+    /// Sets the kind of the review
+    pub fn kind(self, kind: ReviewKind) -> Self {
+        Self { kind, ..self }
+    }
+
+    pub(super) fn time(&self) -> i64 {
+        self.time
+    }
+    pub(super) fn rating(&self) -> i64 {
+        self.rating as i64
+    }
+    pub(super) fn get_interval(&self) -> i64 {
+        self.interval
+    }
+    pub(super) fn get_last_interval(&self) -> i64 {
+        self.last_interval
+    }
+    pub(super) fn factor(&self) -> i64 {
+        (self.ease * 1000.0).round() as i64
+    }
+    pub(super) fn get_duration(&self) -> i64 {
+        self.duration
+    }
+    pub(super) fn get_kind(&self) -> i64 {
+        self.kind as i64
+    }
+}
+
+/// FSRS memory state of a card, e.g. computed offline from a review history.
+///
+/// Anki only uses the memory state if FSRS is enabled in the deck options.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, CardState, MemoryState, Note};
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let memory = MemoryState::new(42.5, 5.1)?.desired_retention(0.85)?;
+///     let note = Note::new(basic_model(), vec!["Hund", "dog"])?
+///         .card_state(0, CardState::new().memory_state(memory))?;
+///     Ok(())
+/// }
+/// ```
+///
+/// The builder has the following default values:
+/// * `desired_retention` - `None`, the desired retention of the deck options is used
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryState {
+    stability: f64,
+    difficulty: f64,
+    desired_retention: Option<f64>,
+}
+
+impl MemoryState {
+    /// Creates a memory state with `stability` in days and `difficulty` between 1 and 10
+    ///
+    /// Returns `Err` if the stability is not positive or the difficulty is out of range
+    pub fn new(stability: f64, difficulty: f64) -> Result<Self> {
+        if stability.is_nan() || stability <= 0.0 {
+            return Err(anyhow!(Error::InvalidScheduling(format!(
+                "stability must be positive, got {}",
+                stability
+            ))));
+        }
+        if !(1.0..=10.0).contains(&difficulty) {
+            return Err(anyhow!(Error::InvalidScheduling(format!(
+                "difficulty must be between 1 and 10, got {}",
+                difficulty
+            ))));
+        }
+        Ok(Self {
+            stability,
+            difficulty,
+            desired_retention: None,
+        })
+    }
+
+    /// Sets the desired retention the card was scheduled with, overriding the deck options
+    ///
+    /// Returns `Err` if the retention is not between 0 and 1
+    pub fn desired_retention(self, desired_retention: f64) -> Result<Self> {
+        check_retention(desired_retention)?;
+        Ok(Self {
+            desired_retention: Some(desired_retention),
+            ..self
+        })
+    }
+
+    /// Returns the `data` column of the card, rounded like Anki does
+    pub(super) fn card_data(&self) -> String {
+        let mut data = serde_json::Map::new();
+        data.insert("s".to_string(), round(self.stability, 4).into());
+        data.insert("d".to_string(), round(self.difficulty, 3).into());
+        if let Some(retention) = self.desired_retention {
+            data.insert("dr".to_string(), round(retention, 2).into());
+        }
+        serde_json::Value::Object(data).to_string()
+    }
+}
+
+/// Returns `Err` if `retention` is not a probability strictly between 0 and 1
+pub(super) fn check_retention(retention: f64) -> Result<()> {
+    if retention > 0.0 && retention < 1.0 {
+        Ok(())
+    } else {
+        Err(anyhow!(Error::InvalidScheduling(format!(
+            "desired retention must be between 0 and 1, got {}",
+            retention
+        ))))
+    }
+}
+
+fn round(value: f64, places: i32) -> f64 {
+    let factor = 10f64.powi(places);
+    (value * factor).round() / factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn review_due_is_counted_in_days_since_collection_creation() {
+        let created = 1411124400;
+        let review = Scheduling::Review {
+            due: created + 10 * 86400 + 3600,
+            interval: 10,
+            ease: 2.3,
+        };
+        assert_eq!(review.due(0, created), 10);
+        assert_eq!(review.factor(), 2300);
+        assert_eq!((review.card_type(), review.queue()), (2, 2));
+
+        let relearning = Scheduling::Relearning {
+            due: created + 600,
+            interval: 3,
+            ease: 2.1,
+            remaining_steps: 2,
+        };
+        assert_eq!(relearning.due(0, created), created + 600);
+        assert_eq!(relearning.left(), 2002);
+        assert_eq!((relearning.card_type(), relearning.queue()), (3, 1));
+
+        assert_eq!(Scheduling::New.due(5, created), 5);
+    }
+
+    #[test]
+    fn memory_state_card_data() {
+        let memory = MemoryState::new(12.345678, 5.12345).unwrap();
+        assert_eq!(memory.card_data(), r#"{"d":5.123,"s":12.3457}"#);
+        assert_eq!(
+            memory.desired_retention(0.855).unwrap().card_data(),
+            r#"{"d":5.123,"dr":0.86,"s":12.3457}"#
+        );
+
+        assert!(MemoryState::new(0.0, 5.0).is_err());
+        assert!(MemoryState::new(1.0, 11.0).is_err());
+        assert!(
+            MemoryState::new(1.0, 5.0)
+                .unwrap()
+                .desired_retention(1.0)
+                .is_err()
+        );
+    }
+}
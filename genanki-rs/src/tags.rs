@@ -0,0 +1,217 @@
+use crate::Error;
+use crate::error::json_error;
+use anyhow::{Result, anyhow};
+use sqlx::SqliteConnection;
+use std::collections::{BTreeMap, HashSet};
+
+/// Separator of the levels of a hierarchical tag, e.g. `Geography::Europe::France`
+pub const TAG_SEPARATOR: &str = "::";
+
+/// Checks whether Anki accepts `tag`
+///
+/// Returns `Err` if the tag contains whitespace, double quotes or control characters, or if
+/// one of its levels is empty, e.g. `::Europe` or `Geography::::France`
+pub fn validate_tag(tag: &str) -> Result<()> {
+    if tag.chars().any(char::is_whitespace) {
+        return Err(anyhow!(Error::TagContainsWhitespace));
+    }
+    if let Some(c) = tag.chars().find(|&c| c == '"' || c.is_control()) {
+        return Err(anyhow!(Error::InvalidTag {
+            tag: tag.to_string(),
+            reason: format!("contains the character {:?}", c),
+        }));
+    }
+    if tag.split(TAG_SEPARATOR).any(str::is_empty) {
+        return Err(anyhow!(Error::InvalidTag {
+            tag: tag.to_string(),
+            reason: "contains an empty level".to_string(),
+        }));
+    }
+    Ok(())
+}
+
+/// Validates `tags` and returns them sorted without duplicates
+///
+/// Anki compares tags ignoring case, so tags differing only in case are merged into the one
+/// appearing first.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::normalize_tags;
+/// use anyhow::Result;
+///
+/// fn main() -> Result<()> {
+///     let tags = normalize_tags(["verb", "Lang::German", "lang::german", "A1"])?;
+///     assert_eq!(tags, ["A1", "Lang::German", "verb"]);
+///     Ok(())
+/// }
+/// ```
+pub fn normalize_tags(tags: impl IntoIterator<Item = impl AsRef<str>>) -> Result<Vec<String>> {
+    let mut seen = HashSet::new();
+    let mut normalized = vec![];
+    for tag in tags {
+        let tag = tag.as_ref();
+        validate_tag(tag)?;
+        if seen.insert(tag.to_lowercase()) {
+            normalized.push(tag.to_string());
+        }
+    }
+    normalized.sort_by_key(|tag| tag.to_lowercase());
+    Ok(normalized)
+}
+
+/// Returns `tag` and all its parents, starting with the top level
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::tag_hierarchy;
+///
+/// assert_eq!(
+///     tag_hierarchy("Geography::Europe::France"),
+///     ["Geography", "Geography::Europe", "Geography::Europe::France"]
+/// );
+/// ```
+pub fn tag_hierarchy(tag: &str) -> Vec<String> {
+    let levels = tag.split(TAG_SEPARATOR).collect::<Vec<_>>();
+    (1..=levels.len())
+        .map(|depth| levels[..depth].join(TAG_SEPARATOR))
+        .collect()
+}
+
+/// Adds `tags` and their parents to the tag registry of the collection, which Anki shows in the
+/// sidebar of the browser
+pub(super) async fn register_tags(
+    conn: &mut SqliteConnection,
+    tags: impl IntoIterator<Item = &str>,
+) -> Result<()> {
+    let rec = sqlx::query!(
+        r#"
+            SELECT tags FROM col
+        "#
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let mut registry: BTreeMap<String, i64> =
+        serde_json::from_str(&rec.tags).map_err(json_error)?;
+    let mut known = registry
+        .keys()
+        .map(|tag| tag.to_lowercase())
+        .collect::<HashSet<_>>();
+    for tag in tags {
+        for tag in tag_hierarchy(tag) {
+            if known.insert(tag.to_lowercase()) {
+                registry.insert(tag, -1);
+            }
+        }
+    }
+
+    let tags_string = serde_json::to_string(&registry)?;
+    sqlx::query!(
+        r#"
+            UPDATE col SET tags = ?
+        "#,
+        tags_string
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConflictPolicy;
+    use crate::{Deck, Note, basic_model};
+    use sqlx::{Pool, Sqlite};
+
+    #[test]
+    fn invalid_tags() {
+        for tag in [
+            "",
+            "a b",
+            "a\u{3000}b",
+            "say\"hi\"",
+            "bell\u{7}",
+            "::a",
+            "a::",
+            "a::::b",
+        ] {
+            assert!(validate_tag(tag).is_err(), "{:?} should be invalid", tag);
+        }
+        for tag in ["a", "Geography::Europe", "a:b", "C++", "日本語"] {
+            assert!(validate_tag(tag).is_ok(), "{:?} should be valid", tag);
+        }
+    }
+
+    #[test]
+    fn normalization_removes_duplicates_ignoring_case() {
+        assert_eq!(
+            normalize_tags(["b", "A", "a", "B::c", "b"]).unwrap(),
+            ["A", "b", "B::c"]
+        );
+        assert!(normalize_tags(["ok", "not ok"]).is_err());
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn used_tags_are_registered(pool: Pool<Sqlite>) {
+        let mut deck = Deck::new(2059400110, "Tags", "");
+        deck.add_note(
+            Note::new(basic_model(), vec!["Hund", "dog"])
+                .unwrap()
+                .tags(["animal", "Lang::German::Nouns", "Animal"]),
+        );
+        deck.add_note(
+            Note::new(basic_model(), vec!["Katze", "cat"])
+                .unwrap()
+                .tags(["lang::german::nouns", "A1"]),
+        );
+        let mut conn = pool.acquire().await.unwrap();
+        deck.write_to_db(&mut conn, 1425279151.0, ConflictPolicy::Overwrite)
+            .await
+            .unwrap();
+
+        let registry = sqlx::query_scalar!("SELECT tags FROM col")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        let registry: BTreeMap<String, i64> = serde_json::from_str(&registry).unwrap();
+        assert_eq!(
+            registry.keys().collect::<Vec<_>>(),
+            [
+                "A1",
+                "Lang",
+                "Lang::German",
+                "Lang::German::Nouns",
+                "animal"
+            ]
+        );
+
+        let tags = sqlx::query_scalar!("SELECT tags FROM notes ORDER BY id")
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            tags,
+            [" animal Lang::German::Nouns ", " A1 lang::german::nouns "]
+        );
+    }
+
+    #[sqlx::test(fixtures("anki"))]
+    async fn invalid_tags_are_rejected_when_writing(pool: Pool<Sqlite>) {
+        let mut deck = Deck::new(2059400110, "Tags", "");
+        deck.add_note(
+            Note::new(basic_model(), vec!["Hund", "dog"])
+                .unwrap()
+                .with_tag("Lang::"),
+        );
+        let mut conn = pool.acquire().await.unwrap();
+        assert!(
+            deck.write_to_db(&mut conn, 1425279151.0, ConflictPolicy::Overwrite)
+                .await
+                .is_err()
+        );
+    }
+}
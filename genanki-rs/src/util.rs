@@ -1,15 +1,42 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-
-pub fn guid_for(fields: &[String]) -> String {
-    fields
-        .iter()
-        .map(|f| u64::to_string(&hash_str(f)))
+/// Derives the GUID of a note from the id of its `model` and its `fields`, so notes with the same
+/// fields but different models get different GUIDs.
+pub fn guid_for(model_id: i64, fields: &[String]) -> String {
+    std::iter::once(model_id.to_string())
+        .chain(fields.iter().cloned())
+        .map(|value| fnv1a(&value).to_string())
         .collect()
 }
 
-fn hash_str(to_hash: &str) -> u64 {
-    let mut s = DefaultHasher::new();
-    to_hash.hash(&mut s);
-    s.finish()
+/// Derives a stable, positive deck or model id from a `name`.
+///
+/// The id is kept below 2^53 so it survives a round trip through JavaScript numbers.
+pub fn id_for(name: &str) -> i64 {
+    (fnv1a(name) & ((1 << 53) - 1)) as i64
+}
+
+/// 64-bit FNV-1a hash, which unlike `DefaultHasher` does not change between Rust releases
+fn fnv1a(value: &str) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    value.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_do_not_depend_on_the_toolchain() {
+        assert_eq!(id_for(""), 0x0012_9ce4_8422_2325);
+        assert_eq!(id_for("a"), 0x0003_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn guids_depend_on_the_model() {
+        let fields = ["Hund".to_string(), "dog".to_string()];
+        assert_eq!(guid_for(1, &fields), guid_for(1, &fields));
+        assert_ne!(guid_for(1, &fields), guid_for(2, &fields));
+    }
 }
@@ -0,0 +1,83 @@
+use std::fmt::Display;
+
+/// How serious an [`Issue`] is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The package can be imported, but probably not as intended
+    Warning,
+    /// The package is broken or incomplete
+    Error,
+}
+
+/// A single finding of a validation pass
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    /// Where the issue was found, e.g. a file name and row or a note GUID
+    pub location: String,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.message)
+    }
+}
+
+/// Collection of [`Issue`]s found while building or checking a package
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    /// Records a warning
+    pub fn warning(&mut self, location: impl ToString, message: impl ToString) {
+        self.push(Severity::Warning, location, message);
+    }
+
+    /// Records an error
+    pub fn error(&mut self, location: impl ToString, message: impl ToString) {
+        self.push(Severity::Error, location, message);
+    }
+
+    fn push(&mut self, severity: Severity, location: impl ToString, message: impl ToString) {
+        self.issues.push(Issue {
+            severity,
+            location: location.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Returns all issues with `Severity::Error`
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+
+    /// Returns all issues with `Severity::Warning`
+    pub fn warnings(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Warning)
+    }
+
+    /// Returns `true` if no errors were found. Warnings are allowed.
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
@@ -1,38 +1,57 @@
 use crate::db;
 use anyhow::Result;
 use base64::{Engine, engine::general_purpose};
-use genanki_rs::{Deck, Field, Model, Note, Package, Template};
+use genanki_rs::{AnkiNote, Deck, Model, Package, Template};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::{fs::File, fs::read_dir, io::Write};
 
+/// Fields of a note of the German model
+#[derive(AnkiNote, Default)]
+#[anki(
+    id = 1607392319,
+    name = "German model (and reversed card)",
+    css_file = "src/anki/minimal.css"
+)]
+#[anki(template(
+    name = "Card 1",
+    qfmt_file = "src/anki/template-q.html",
+    afmt_file = "src/anki/template-a.html"
+))]
+struct GermanNote {
+    #[anki(rename = "AudioWord")]
+    audio_word: String,
+    #[anki(rename = "WordTranslation")]
+    word_translation: String,
+    #[anki(rename = "Word")]
+    word: String,
+    #[anki(rename = "Sentence")]
+    sentence: String,
+    #[anki(rename = "SentenceTranslation")]
+    sentence_translation: String,
+    #[anki(rename = "AudioWordTranslation")]
+    audio_word_translation: String,
+    #[anki(rename = "AudioSentence")]
+    audio_sentence: String,
+    #[anki(rename = "AudioSentenceTranslation")]
+    audio_sentence_translation: String,
+    #[anki(rename = "Level")]
+    level: String,
+    #[anki(rename = "CountryISO")]
+    country_iso: String,
+    #[anki(rename = "Picture")]
+    picture: String,
+    #[anki(rename = "Word-Symbol")]
+    word_symbol: String,
+    #[anki(rename = "Tags")]
+    tags: String,
+    #[anki(rename = "Note/Mnemonic")]
+    mnemonic: String,
+    #[anki(rename = "CreateReversed")]
+    create_reversed: String,
+}
+
 pub fn basic_model() -> Model {
-    Model::new(
-        1607392319,
-        "German model (and reversed card)",
-        vec![
-            Field::new("AudioWord"),
-            Field::new("WordTranslation"),
-            Field::new("Word"),
-            Field::new("Sentence"),
-            Field::new("SentenceTranslation"),
-            Field::new("AudioWordTranslation"),
-            Field::new("AudioSentence"),
-            Field::new("AudioSentenceTranslation"),
-            Field::new("Level"),
-            Field::new("CountryISO"),
-            Field::new("Picture"),
-            Field::new("Word-Symbol"),
-            Field::new("Tags"),
-            Field::new("Note/Mnemonic"),
-            Field::new("CreateReversed"),
-        ],
-        vec![
-            Template::new("Card 1")
-                .qfmt(include_str!("template-q.html"))
-                .afmt(include_str!("template-a.html")),
-        ],
-    )
-    .css(include_str!("minimal.css"))
+    GermanNote::model()
 }
 
 pub fn basic_model_reversed() -> Model {
@@ -73,26 +92,17 @@ pub async fn generate_deck(
         }
         let description = translation.description.clone().unwrap();
         let word_translation = word.description.clone().unwrap();
-        let my_note = Note::new(
-            model.clone(),
-            vec![
-                &format!("[sound:{}.ogg]", word.id),
-                &translation.translation,
-                &word.word,
-                &word_translation,
-                &description,
-                "",
-                "",
-                "",
-                "",
-                "DE",
-                "",
-                "",
-                "",
-                "",
-                "true",
-            ],
-        )?;
+        let my_note = GermanNote {
+            audio_word: format!("[sound:{}.ogg]", word.id),
+            word_translation: translation.translation.clone(),
+            word: word.word.clone(),
+            sentence: word_translation,
+            sentence_translation: description,
+            country_iso: "DE".to_string(),
+            create_reversed: "true".to_string(),
+            ..Default::default()
+        }
+        .to_note_with_model(model.clone())?;
         my_deck.add_note(my_note);
     }
     bar.finish();